@@ -0,0 +1,505 @@
+use crate::counter::{Counters, CountersSnapshot};
+use crate::limiter::{keyed::Consumption, KeyedLimiters};
+use futures::StreamExt;
+use libp2p::{
+    gossipsub,
+    identity::Keypair,
+    mdns, noise,
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
+    tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, mpsc, watch};
+
+const TOPIC: &str = "limitrs/1";
+const REPORT_INTERVAL: Duration = Duration::from_millis(50);
+const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(2);
+const INBOUND_CAPACITY: usize = 1024;
+/// The largest gossip message we send or accept.
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// How much of a message the counters may fill, leaving room for gossipsub's
+/// own framing and signature.
+const MAX_COUNTERS_SIZE: usize = MAX_MESSAGE_SIZE - 16 * 1024;
+
+/// How this backend finds and talks to its peers.
+#[derive(Clone, Debug, Default)]
+pub struct ClusterConfig {
+    /// Addresses to accept peer connections on, e.g. `/ip4/0.0.0.0/tcp/4001`.
+    pub listen: Vec<Multiaddr>,
+    /// Peers to dial at startup.
+    pub bootstrap: Vec<Multiaddr>,
+    /// Discover peers on the local network with mDNS.
+    pub mdns: bool,
+    /// A file holding this backend's private key, created if it is missing.
+    /// Counters credit changes to the peer id the key gives, so without one
+    /// every restart leaves another entry behind in each counter.
+    pub identity: Option<PathBuf>,
+}
+
+/// Everything backends gossip to each other.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ClusterMessage {
+    Consumption(Vec<Consumption>),
+    Counters(CountersSnapshot),
+}
+
+#[derive(Debug)]
+pub enum ClusterError {
+    Identity(PathBuf, String),
+    Transport(String),
+    Listen(String),
+}
+
+impl fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClusterError::Identity(path, err) => {
+                write!(f, "failed to load identity {}: {}", path.display(), err)
+            }
+            ClusterError::Transport(err) => write!(f, "failed to set up transport: {}", err),
+            ClusterError::Listen(err) => write!(f, "failed to listen for peers: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClusterError {}
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    gossipsub: gossipsub::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+}
+
+/// A handle on this backend's libp2p swarm, which runs in a background task
+/// for as long as any handle is alive.
+#[derive(Clone)]
+pub struct Cluster {
+    local_peer_id: PeerId,
+    listen_addrs: Vec<Multiaddr>,
+    outbound: mpsc::UnboundedSender<ClusterMessage>,
+    inbound: broadcast::Sender<ClusterMessage>,
+    peers: watch::Receiver<usize>,
+}
+
+impl Cluster {
+    /// Start the swarm, returning once it is listening on every configured address.
+    pub async fn start(config: &ClusterConfig) -> Result<Cluster, ClusterError> {
+        let mdns_enabled = config.mdns;
+        let keypair = match &config.identity {
+            Some(path) => load_identity(path)
+                .map_err(|err| ClusterError::Identity(path.clone(), err.to_string()))?,
+            None => Keypair::generate_ed25519(),
+        };
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .max_transmit_size(MAX_MESSAGE_SIZE)
+            .build()
+            .map_err(|err| ClusterError::Transport(err.to_string()))?;
+        let mut swarm = SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default().nodelay(true),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .map_err(|err| ClusterError::Transport(err.to_string()))?
+            .with_behaviour(|key| {
+                let gossipsub = gossipsub::Behaviour::new(
+                    gossipsub::MessageAuthenticity::Signed(key.clone()),
+                    gossipsub_config,
+                )?;
+                let mdns = if mdns_enabled {
+                    Some(mdns::tokio::Behaviour::new(
+                        mdns::Config::default(),
+                        key.public().to_peer_id(),
+                    )?)
+                } else {
+                    None
+                };
+                Ok(Behaviour {
+                    gossipsub,
+                    mdns: mdns.into(),
+                })
+            })
+            .map_err(|err| ClusterError::Transport(err.to_string()))?
+            .with_swarm_config(|config| {
+                config.with_idle_connection_timeout(Duration::from_secs(60))
+            })
+            .build();
+
+        let topic = gossipsub::IdentTopic::new(TOPIC);
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&topic)
+            .map_err(|err| ClusterError::Transport(err.to_string()))?;
+
+        let listen_addrs = listen(&mut swarm, &config.listen).await?;
+        for addr in &config.bootstrap {
+            if let Err(err) = swarm.dial(addr.clone()) {
+                log::warn!("failed to dial peer {}: {}", addr, err);
+            }
+        }
+
+        let local_peer_id = *swarm.local_peer_id();
+        log::info!("peer {} listening on {:?}", local_peer_id, listen_addrs);
+
+        let (outbound, outbound_rx) = mpsc::unbounded_channel();
+        let (inbound, _) = broadcast::channel(INBOUND_CAPACITY);
+        let (peers_tx, peers) = watch::channel(0);
+        tokio::spawn(drive_swarm(
+            swarm,
+            topic,
+            outbound_rx,
+            inbound.clone(),
+            peers_tx,
+        ));
+
+        Ok(Cluster {
+            local_peer_id,
+            listen_addrs,
+            outbound,
+            inbound,
+            peers,
+        })
+    }
+
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// The addresses peers can dial to reach this backend.
+    pub fn listen_addrs(&self) -> &[Multiaddr] {
+        &self.listen_addrs
+    }
+
+    /// How many peers are currently subscribed to our gossip.
+    pub fn peer_count(&self) -> usize {
+        *self.peers.borrow()
+    }
+
+    /// Wait until at least `count` peers are subscribed to our gossip.
+    pub async fn wait_for_peers(&self, count: usize) {
+        let mut peers = self.peers.clone();
+        while *peers.borrow_and_update() < count {
+            if peers.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Gossip `message` to every peer, returning it if the swarm has stopped.
+    pub fn publish(&self, message: ClusterMessage) -> Result<(), ClusterMessage> {
+        self.outbound.send(message).map_err(|err| err.0)
+    }
+
+    /// Receive every message gossiped by peers from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ClusterMessage> {
+        self.inbound.subscribe()
+    }
+}
+
+/// The key saved at `path`, or a new one saved there if there is none yet.
+fn load_identity(path: &Path) -> io::Result<Keypair> {
+    match fs::read(path) {
+        Ok(bytes) => Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate_ed25519();
+            let bytes = keypair
+                .to_protobuf_encoding()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            io::Write::write_all(&mut options.open(path)?, &bytes)?;
+            log::info!("saved a new identity to {}", path.display());
+            Ok(keypair)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn listen(
+    swarm: &mut Swarm<Behaviour>,
+    addrs: &[Multiaddr],
+) -> Result<Vec<Multiaddr>, ClusterError> {
+    let mut pending = HashSet::new();
+    for addr in addrs {
+        let listener = swarm
+            .listen_on(addr.clone())
+            .map_err(|err| ClusterError::Listen(err.to_string()))?;
+        pending.insert(listener);
+    }
+
+    // wildcard addresses expand to one address per interface, but one is enough
+    let mut listen_addrs = Vec::new();
+    while !pending.is_empty() {
+        match swarm.select_next_some().await {
+            SwarmEvent::NewListenAddr {
+                listener_id,
+                address,
+            } => {
+                pending.remove(&listener_id);
+                listen_addrs.push(address);
+            }
+            SwarmEvent::ListenerError { error, .. } => {
+                return Err(ClusterError::Listen(error.to_string()))
+            }
+            SwarmEvent::ListenerClosed { reason, .. } => {
+                let reason = reason.err().map(|err| err.to_string());
+                return Err(ClusterError::Listen(reason.unwrap_or_default()));
+            }
+            _ => (),
+        }
+    }
+    Ok(listen_addrs)
+}
+
+async fn drive_swarm(
+    mut swarm: Swarm<Behaviour>,
+    topic: gossipsub::IdentTopic,
+    mut outbound: mpsc::UnboundedReceiver<ClusterMessage>,
+    inbound: broadcast::Sender<ClusterMessage>,
+    peers: watch::Sender<usize>,
+) {
+    let mut subscribed = HashSet::new();
+
+    loop {
+        tokio::select! {
+            message = outbound.recv() => {
+                let message = match message {
+                    Some(message) => message,
+                    None => break,
+                };
+                let data = match serde_json::to_vec(&message) {
+                    Ok(data) => data,
+                    Err(_) => {
+                        log::error!("abject failure to build JSON");
+                        continue;
+                    }
+                };
+                match swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                    Ok(_) => (),
+                    // expected while we have no peers
+                    Err(gossipsub::PublishError::InsufficientPeers) => {
+                        log::trace!("no peers to publish to");
+                    }
+                    Err(err) => log::warn!("failed to publish to peers: {}", err),
+                }
+            }
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    message, ..
+                })) => match serde_json::from_slice(&message.data) {
+                    Ok(message) => {
+                        // no receivers just means nothing is interested yet
+                        let _ = inbound.send(message);
+                    }
+                    Err(_) => log::error!("peer {:?} sent invalid message", message.source),
+                },
+                SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                    peer_id, ..
+                })) => {
+                    subscribed.insert(peer_id);
+                    peers.send_replace(subscribed.len());
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(
+                    gossipsub::Event::Unsubscribed { peer_id, .. },
+                ))
+                | SwarmEvent::ConnectionClosed {
+                    peer_id,
+                    num_established: 0,
+                    ..
+                } => {
+                    subscribed.remove(&peer_id);
+                    peers.send_replace(subscribed.len());
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(found))) => {
+                    for (peer_id, addr) in found {
+                        log::debug!("discovered peer {} at {}", peer_id, addr);
+                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                    }
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Expired(lost))) => {
+                    for (peer_id, _) in lost {
+                        swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                    }
+                }
+                SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                    log::info!("connected to peer {} at {}", peer_id, endpoint.get_remote_address());
+                }
+                SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                    log::warn!("failed to connect to peer {:?}: {}", peer_id, error);
+                }
+                _ => (),
+            },
+        }
+    }
+}
+
+/// Share rate-limit consumption with the rest of the cluster: admitted
+/// requests are gossiped in small batches, and requests admitted by peers
+/// are charged against our own limiters, so a client spreading requests
+/// across backends still meets one budget.
+pub fn share_rate_limits(cluster: &Cluster, limiters: Arc<KeyedLimiters>) {
+    let mut inbound = cluster.subscribe();
+    let remote = limiters.clone();
+    tokio::spawn(async move {
+        loop {
+            match inbound.recv().await {
+                Ok(ClusterMessage::Consumption(consumption)) => {
+                    let now = Instant::now();
+                    for entry in &consumption {
+                        remote.record_remote(entry, now);
+                    }
+                }
+                Ok(_) => (),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("dropped {} messages from peers", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let cluster = cluster.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let consumption = limiters.take_unreported();
+            if !consumption.is_empty()
+                && cluster
+                    .publish(ClusterMessage::Consumption(consumption))
+                    .is_err()
+            {
+                break;
+            }
+        }
+    });
+}
+
+/// Replicate the counters across the cluster. Local changes are gossiped as
+/// soon as they are noticed, and the full state is re-sent periodically so
+/// that peers who missed an update, or joined late, still converge. Either
+/// is split over as many messages as it takes to stay within the size peers
+/// accept.
+pub fn share_counters(cluster: &Cluster, counters: Arc<Counters>) {
+    let mut inbound = cluster.subscribe();
+    let remote = counters.clone();
+    tokio::spawn(async move {
+        loop {
+            match inbound.recv().await {
+                Ok(ClusterMessage::Counters(state)) => {
+                    if remote.merge(&state) {
+                        log::debug!("merged counters from peer");
+                    }
+                }
+                Ok(_) => (),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("dropped {} messages from peers", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let cluster = cluster.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+        let mut published_version = counters.version();
+        let mut published_at = Instant::now();
+        loop {
+            interval.tick().await;
+            let version = counters.version();
+            let state = if published_at.elapsed() >= ANTI_ENTROPY_INTERVAL {
+                published_at = Instant::now();
+                counters.snapshot()
+            } else if version != published_version {
+                counters.changes_since(published_version)
+            } else {
+                continue;
+            };
+            published_version = version;
+            for part in split(state, MAX_COUNTERS_SIZE) {
+                if cluster.publish(ClusterMessage::Counters(part)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Split `state` into parts which serialise to at most about `max` bytes
+/// each, short of a single replica larger than that. Peers merge each part
+/// on its own, so they can arrive in any order.
+fn split(state: CountersSnapshot, max: usize) -> Vec<CountersSnapshot> {
+    fn size(entry: &impl Serialize) -> usize {
+        serde_json::to_vec(entry).map_or(0, |json| json.len())
+    }
+
+    let mut parts = Vec::new();
+    let mut part = CountersSnapshot::default();
+    let mut used = 0;
+    for (name, epoch) in state.deleted {
+        let needed = size(&(&name, &epoch));
+        if used > 0 && used + needed > max {
+            parts.push(std::mem::take(&mut part));
+            used = 0;
+        }
+        part.deleted.insert(name, epoch);
+        used += needed;
+    }
+    for (name, replica) in state.counters {
+        let needed = size(&(&name, &replica));
+        if used > 0 && used + needed > max {
+            parts.push(std::mem::take(&mut part));
+            used = 0;
+        }
+        part.counters.insert(name, replica);
+        used += needed;
+    }
+    if used > 0 {
+        parts.push(part);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::{Bounds, Counters};
+
+    #[test]
+    fn large_states_are_split_into_parts_within_the_limit() {
+        let counters = Counters::new("a");
+        for i in 0..100 {
+            counters
+                .put(&format!("counter-{}", i), i, Bounds::default())
+                .unwrap();
+            counters
+                .put(&format!("deleted-{}", i), i, Bounds::default())
+                .unwrap();
+            counters.remove(&format!("deleted-{}", i));
+        }
+        let state = counters.snapshot();
+        let parts = split(state.clone(), 2048);
+        assert!(parts.len() > 1);
+
+        let merged = Counters::new("b");
+        for part in &parts {
+            assert!(serde_json::to_vec(part).unwrap().len() <= 2048 + 64);
+            merged.merge(part);
+        }
+        assert_eq!(merged.snapshot(), state);
+        assert_eq!(merged.get("counter-42").unwrap().value(), 42);
+    }
+}
@@ -0,0 +1,554 @@
+use crate::counter::{Bounds, DEFAULT_COUNTER, DEFAULT_MAX_COUNTERS};
+use crate::limiter::{is_route, RateLimitConfig};
+use crate::routes::counters::is_valid_name;
+use crate::startup::{Settings, DEFAULT_DRAIN_TIMEOUT};
+use crate::store::StoreConfig;
+use client::OverflowPolicy;
+use libp2p::Multiaddr;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    net::{IpAddr, Ipv6Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use toml::{Table, Value};
+
+/// Environment variables starting with this override the configuration file.
+pub const ENV_PREFIX: &str = "LIMITRS_";
+/// Names the configuration file, unless `--config` does.
+pub const CONFIG_VARIABLE: &str = "LIMITRS_CONFIG";
+/// Separates the section from the key in an environment variable, as in
+/// `LIMITRS_SERVER__PORT`.
+const ENV_SEPARATOR: &str = "__";
+
+const LOG_LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+/// Everything the backend can be configured with.
+///
+/// Values are layered: the defaults here, then the TOML file, then
+/// `LIMITRS_*` environment variables, then command line flags. Each
+/// environment variable names a key in the file, e.g. `LIMITRS_SERVER__PORT=8081`
+/// sets `port` in `[server]`, and its value is read as TOML if it can be,
+/// or as a string otherwise.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub log: LogConfig,
+    /// Replaces the built-in policies entirely when given.
+    pub rate_limits: RateLimitConfig,
+    /// Counters to create at startup, unless they already exist.
+    pub counters: Vec<CounterConfig>,
+    pub store: StoreConfig,
+    pub cluster: ClusterSection,
+    /// Serve HTTPS rather than HTTP.
+    pub tls: Option<TlsConfig>,
+    pub admin: AdminConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            server: ServerConfig::default(),
+            log: LogConfig::default(),
+            rate_limits: RateLimitConfig::counter_defaults(),
+            counters: Vec::new(),
+            store: StoreConfig::default(),
+            cluster: ClusterSection::default(),
+            tls: None,
+            admin: AdminConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: IpAddr,
+    pub port: u16,
+    /// Where the frontend's static files are found.
+    pub static_dir: String,
+    /// Also serve the counters over gRPC on this port.
+    pub grpc_port: Option<u16>,
+    /// Also serve the counters over the Redis protocol on this port.
+    pub resp_port: Option<u16>,
+    pub idempotency_ttl_seconds: u64,
+    pub drain_timeout_seconds: u64,
+    /// How many counters clients can create, the default counter included.
+    pub max_counters: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addr: IpAddr::V6(Ipv6Addr::LOCALHOST),
+            port: 8080,
+            static_dir: "./dist".into(),
+            grpc_port: None,
+            resp_port: None,
+            idempotency_ttl_seconds: crate::idempotency::DEFAULT_TTL.as_secs(),
+            drain_timeout_seconds: DEFAULT_DRAIN_TIMEOUT.as_secs(),
+            max_counters: DEFAULT_MAX_COUNTERS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// One of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    pub level: String,
+    /// Colour the output.
+    pub ansi: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "debug".into(),
+            ansi: true,
+        }
+    }
+}
+
+/// A counter to create at startup.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CounterConfig {
+    pub name: String,
+    #[serde(default)]
+    pub value: i32,
+    #[serde(default = "i32_min")]
+    pub min: i32,
+    #[serde(default = "i32_max")]
+    pub max: i32,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+fn i32_min() -> i32 {
+    i32::MIN
+}
+
+fn i32_max() -> i32 {
+    i32::MAX
+}
+
+impl CounterConfig {
+    pub fn bounds(&self) -> Bounds {
+        Bounds::new(self.min, self.max).overflow(self.overflow)
+    }
+}
+
+/// Which peers to share rate limits and counters with. Peer-to-peer
+/// networking only starts when one of these is set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterSection {
+    /// Addresses to accept peer connections on, e.g. `/ip4/0.0.0.0/tcp/4001`.
+    pub listen: Vec<Multiaddr>,
+    /// Peers to dial at startup.
+    pub peers: Vec<Multiaddr>,
+    /// Discover peers on the local network with mDNS.
+    pub mdns: bool,
+    /// Keep this backend's peer id in this file across restarts.
+    pub identity: Option<PathBuf>,
+}
+
+impl ClusterSection {
+    pub fn enabled(&self) -> bool {
+        !self.listen.is_empty() || !self.peers.is_empty() || self.mdns
+    }
+}
+
+/// A PEM certificate chain and private key to serve HTTPS with.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Who may use the `/admin` API, which is disabled unless `tokens` is given.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Bearer tokens accepted in the `Authorization` header.
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Read { path: PathBuf, source: io::Error },
+    /// The configuration file is not valid TOML.
+    Parse { path: PathBuf, message: String },
+    /// An environment variable could not be applied.
+    Env { variable: String, message: String },
+    /// The configuration was read, but makes no sense.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            ConfigError::Parse { path, message } => {
+                write!(f, "failed to parse {}: {}", path.display(), message)
+            }
+            ConfigError::Env { variable, message } => write!(f, "{}: {}", variable, message),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// The defaults, overlaid with the file at `path` if there is one, and
+    /// then with the `LIMITRS_*` variables among `env`.
+    pub fn load(
+        path: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config, ConfigError> {
+        let mut table = match path {
+            Some(path) => read(path)?,
+            None => Table::new(),
+        };
+        let mut variables: Vec<_> = env
+            .into_iter()
+            .filter(|(variable, _)| variable.starts_with(ENV_PREFIX) && variable != CONFIG_VARIABLE)
+            .collect();
+        // so that `LIMITRS_SERVER` is applied before `LIMITRS_SERVER__PORT`
+        variables.sort();
+        for (variable, value) in variables {
+            overlay(&mut table, &variable, &value).map_err(|message| ConfigError::Env {
+                variable: variable.clone(),
+                message,
+            })?;
+        }
+        serde_path_to_error::deserialize(Value::Table(table)).map_err(|err| {
+            // name the key at fault, as the values no longer know where they came from
+            let message = match err.path().to_string().as_str() {
+                "." => err.inner().message().to_owned(),
+                path => format!("{}: {}", path, err.inner().message()),
+            };
+            ConfigError::Invalid(vec![message])
+        })
+    }
+
+    /// Report everything wrong with the configuration at once.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        let server = &self.server;
+        let mut ports = vec![("server.port", server.port)];
+        ports.extend(server.grpc_port.map(|port| ("server.grpc_port", port)));
+        ports.extend(server.resp_port.map(|port| ("server.resp_port", port)));
+        for (i, (name, port)) in ports.iter().enumerate() {
+            // port zero asks the OS for any free port, so cannot clash
+            if let Some((other, _)) = ports[..i].iter().find(|(_, p)| *p == *port && *port != 0) {
+                problems.push(format!("{} is the same as {}", name, other));
+            }
+        }
+        if server.drain_timeout_seconds == 0 {
+            problems.push("server.drain_timeout_seconds must be at least 1".into());
+        }
+        if server.max_counters == 0 {
+            problems.push("server.max_counters must be at least 1".into());
+        }
+
+        if !LOG_LEVELS.contains(&self.log.level.as_str()) {
+            problems.push(format!(
+                "log.level {:?} is not one of {}",
+                self.log.level,
+                LOG_LEVELS.join(", ")
+            ));
+        }
+
+        let mut routes: Vec<_> = self.rate_limits.routes.iter().collect();
+        routes.sort_by(|a, b| a.0.cmp(b.0));
+        for (route, policy) in routes {
+            if !is_route(route) {
+                problems.push(format!("rate_limits.routes: {:?} is not a route", route));
+            }
+            if let Err(problem) = policy.validate() {
+                problems.push(format!("rate_limits.routes.{:?}: {}", route, problem));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for counter in &self.counters {
+            let name = &counter.name;
+            if !is_valid_name(name) {
+                problems.push(format!("counters: {:?} is not a valid counter name", name));
+            } else if name == DEFAULT_COUNTER {
+                problems.push(format!("counters: {:?} always exists", name));
+            } else if !names.insert(name) {
+                problems.push(format!("counters: {:?} is configured twice", name));
+            }
+            if counter.min > counter.max {
+                problems.push(format!("counters.{}: min is greater than max", name));
+            } else if !counter.bounds().contains(counter.value.into()) {
+                problems.push(format!("counters.{}: value is outside min..=max", name));
+            }
+        }
+
+        if self
+            .admin
+            .tokens
+            .iter()
+            .any(|token| token.is_empty() || token.contains(char::is_whitespace))
+        {
+            problems.push("admin.tokens must not be empty or contain spaces".into());
+        }
+
+        if let Some(tls) = &self.tls {
+            for (name, path) in [("tls.cert", &tls.cert), ("tls.key", &tls.key)] {
+                if let Err(err) = fs::metadata(path) {
+                    problems.push(format!("{} {}: {}", name, path.display(), err));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    /// The settings for `startup::run` described by this configuration,
+    /// short of the listeners, store and cluster, which need setting up first.
+    pub fn settings(&self) -> Settings {
+        let mut settings = Settings::new(self.server.static_dir.clone())
+            .rate_limits(self.rate_limits.clone())
+            .idempotency_ttl(Duration::from_secs(self.server.idempotency_ttl_seconds))
+            .drain_timeout(Duration::from_secs(self.server.drain_timeout_seconds))
+            .counters(self.counters.clone())
+            .max_counters(self.server.max_counters)
+            .admin_tokens(self.admin.tokens.clone());
+        if let Some(tls) = &self.tls {
+            settings = settings.tls(tls.clone());
+        }
+        settings
+    }
+}
+
+fn read(path: &Path) -> Result<Table, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    text.parse()
+        .map_err(|err: toml::de::Error| ConfigError::Parse {
+            path: path.to_owned(),
+            message: err.to_string(),
+        })
+}
+
+/// Set the key named by `variable` in `table` to `value`.
+fn overlay(table: &mut Table, variable: &str, value: &str) -> Result<(), String> {
+    let name = variable[ENV_PREFIX.len()..].to_ascii_lowercase();
+    let mut path: Vec<&str> = name.split(ENV_SEPARATOR).collect();
+    let key = path.pop().filter(|key| !key.is_empty());
+    let key = key.ok_or_else(|| "does not name a configuration key".to_owned())?;
+
+    let mut table = table;
+    for section in path {
+        table = match table
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(inner) => inner,
+            _ => return Err(format!("{} is not a section", section)),
+        };
+    }
+    table.insert(key.to_owned(), parse_value(value));
+    Ok(())
+}
+
+/// `value` as TOML, so that numbers, booleans and arrays keep their types,
+/// or as a plain string if it is not.
+fn parse_value(value: &str) -> Value {
+    format!("value = {}", value)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limiter::Policy;
+    use std::io::Write;
+
+    fn env(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+        variables
+            .iter()
+            .map(|(variable, value)| (variable.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn defaults_match_the_command_line_defaults() {
+        let config = Config::load(None, Vec::new()).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.rate_limits, RateLimitConfig::counter_defaults());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn the_environment_overrides_the_file() {
+        let file = file(
+            r#"
+            store = "sqlite:counters.db"
+
+            [server]
+            port = 9000
+            static_dir = "/srv/limitrs"
+
+            [log]
+            level = "info"
+
+            [[counters]]
+            name = "seats"
+            value = 10
+            min = 0
+            max = 100
+            overflow = "Saturate"
+
+            [rate_limits.routes."/api/count"]
+            algorithm = "fixed_window"
+            limit = 5
+            window_seconds = 1.0
+            "#,
+        );
+        let config = Config::load(
+            Some(file.path()),
+            env(&[
+                ("LIMITRS_SERVER__PORT", "9001"),
+                ("LIMITRS_CLUSTER__MDNS", "true"),
+                ("LIMITRS_CLUSTER__PEERS", r#"["/ip4/10.0.0.2/tcp/4001"]"#),
+                ("LIMITRS_CONFIG", "ignored.toml"),
+                ("PATH", "/usr/bin"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.server.port, 9001);
+        assert_eq!(config.server.static_dir, "/srv/limitrs");
+        assert_eq!(config.log.level, "info");
+        assert_eq!(config.store, StoreConfig::Sqlite("counters.db".into()));
+        assert!(config.cluster.mdns);
+        assert_eq!(config.cluster.peers.len(), 1);
+        assert_eq!(
+            config.counters[0].bounds(),
+            Bounds::new(0, 100).overflow(OverflowPolicy::Saturate)
+        );
+        assert_eq!(
+            config.rate_limits,
+            RateLimitConfig::default().route(
+                "/api/count",
+                Policy::fixed_window(5, Duration::from_secs(1))
+            )
+        );
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn the_example_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("limitrs.example.toml");
+        let config = Config::load(Some(&path), Vec::new()).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.counters[0].name, "seats");
+    }
+
+    #[test]
+    fn unknown_and_mistyped_keys_are_refused() {
+        let err = Config::load(None, env(&[("LIMITRS_SERVER__PROT", "1")])).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("server.prot: unknown field `prot`"),
+            "{}",
+            err
+        );
+
+        let err = Config::load(None, env(&[("LIMITRS_SERVER__PORT", "http")])).unwrap_err();
+        assert!(
+            err.to_string().contains("server.port: invalid type"),
+            "{}",
+            err
+        );
+
+        let err = Config::load(None, env(&[("LIMITRS_STORE__PATH", "x")])).unwrap_err();
+        assert!(
+            err.to_string().contains("store: invalid type: map"),
+            "{}",
+            err
+        );
+
+        let variables = env(&[("LIMITRS_LOG", "1"), ("LIMITRS_LOG__LEVEL", "info")]);
+        let err = Config::load(None, variables).unwrap_err();
+        assert!(matches!(err, ConfigError::Env { .. }), "{}", err);
+
+        let file = file("[server\nport = 1");
+        let err = Config::load(Some(file.path()), Vec::new()).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { .. }), "{}", err);
+    }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let mut config = Config::default();
+        config.server.grpc_port = Some(8080);
+        config.log.level = "loud".into();
+        config.rate_limits =
+            RateLimitConfig::default().route("/api/count", Policy::token_bucket(0, 1.0));
+        config.counters = vec![CounterConfig {
+            name: "seats".into(),
+            value: 5,
+            min: 10,
+            max: 20,
+            overflow: OverflowPolicy::Reject,
+        }];
+        config.admin.tokens = vec!["two words".into()];
+        config.tls = Some(TlsConfig {
+            cert: "/nonexistent/cert.pem".into(),
+            key: "/nonexistent/key.pem".into(),
+        });
+
+        let problems = match config.validate() {
+            Err(ConfigError::Invalid(problems)) => problems,
+            other => panic!("expected problems, got {:?}", other),
+        };
+        assert_eq!(problems.len(), 7, "{:#?}", problems);
+        assert_eq!(problems[0], "server.grpc_port is the same as server.port");
+        assert_eq!(
+            problems[2],
+            r#"rate_limits.routes."/api/count": capacity must be at least 1"#
+        );
+        assert_eq!(problems[3], "counters.seats: value is outside min..=max");
+        assert_eq!(
+            problems[4],
+            "admin.tokens must not be empty or contain spaces"
+        );
+    }
+}
@@ -0,0 +1,929 @@
+use crate::history::History;
+use crate::stats::Stats;
+use client::OverflowPolicy;
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// The node name used when the backend is not part of a cluster.
+pub const LOCAL_NODE: &str = "local";
+
+/// The counter served by the unnamed `/api/count` routes.
+pub const DEFAULT_COUNTER: &str = "default";
+/// Whom the starting values of configured counters are credited to, the
+/// same on every node.
+const SEED_NODE: &str = "seed";
+/// How many counters clients can create on a node by default.
+pub const DEFAULT_MAX_COUNTERS: usize = 10_000;
+
+/// A PN-Counter CRDT: every node only ever grows its own increment and
+/// decrement totals, so replicas can merge each other's state in any order,
+/// any number of times, and still converge on the same value.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PnCounter {
+    increments: HashMap<String, u64>,
+    decrements: HashMap<String, u64>,
+}
+
+/// The largest total any one node may hold, so a few totals from a peer
+/// cannot overflow the value.
+const MAX_TOTAL: u64 = i64::MAX as u64;
+
+impl PnCounter {
+    pub fn value(&self) -> i64 {
+        let increments: i128 = self.increments.values().map(|&total| total as i128).sum();
+        let decrements: i128 = self.decrements.values().map(|&total| total as i128).sum();
+        (increments - decrements).clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    pub fn increment(&mut self, node: &str, by: u64) {
+        grow(self.increments.entry(node.to_owned()).or_insert(0), by);
+    }
+
+    pub fn decrement(&mut self, node: &str, by: u64) {
+        grow(self.decrements.entry(node.to_owned()).or_insert(0), by);
+    }
+
+    /// Fold `other` into this counter, returning whether anything changed.
+    pub fn merge(&mut self, other: &PnCounter) -> bool {
+        merge_max(&mut self.increments, &other.increments)
+            | merge_max(&mut self.decrements, &other.decrements)
+    }
+}
+
+fn grow(total: &mut u64, by: u64) {
+    *total = total.saturating_add(by).min(MAX_TOTAL);
+}
+
+fn merge_max(ours: &mut HashMap<String, u64>, theirs: &HashMap<String, u64>) -> bool {
+    let mut changed = false;
+    for (node, &total) in theirs {
+        // peers are trusted to be correct, not to be sane
+        let total = total.min(MAX_TOTAL);
+        let entry = ours.entry(node.clone()).or_insert(0);
+        if total > *entry {
+            *entry = total;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Identifies one life of a named counter. Creating or replacing a counter
+/// starts a new epoch, and the latest epoch wins wherever replicas disagree,
+/// so totals from an older life are never merged into a newer one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Epoch {
+    /// Milliseconds since the Unix epoch.
+    pub created: u64,
+    pub node: String,
+}
+
+impl Epoch {
+    /// A new epoch on `node`, later than `after`.
+    fn next(node: &str, after: Option<&Epoch>) -> Epoch {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        let floor = after.map(|epoch| epoch.created + 1).unwrap_or_default();
+        Epoch {
+            created: now.max(floor),
+            node: node.to_owned(),
+        }
+    }
+}
+
+/// The inclusive range a counter may be moved within, and what to do with
+/// changes that would leave it.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: i32,
+    pub max: i32,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds::new(i32::MIN, i32::MAX)
+    }
+}
+
+/// A counter refused because `max` counters already exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooManyCounters {
+    pub max: usize,
+}
+
+/// A change refused by [`OverflowPolicy::Reject`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoundsError {
+    AboveMaximum,
+    BelowMinimum,
+}
+
+impl Bounds {
+    /// Bounds which reject changes that would leave them.
+    pub fn new(min: i32, max: i32) -> Bounds {
+        Bounds {
+            min,
+            max,
+            overflow: OverflowPolicy::Reject,
+        }
+    }
+
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        (self.min as i64..=self.max as i64).contains(&value)
+    }
+
+    /// `value` itself, if it is within bounds. The overflow policy is for
+    /// moves, so a value set outright is never saturated or wrapped.
+    pub fn check(&self, value: i64) -> Result<i64, BoundsError> {
+        if value > self.max as i64 {
+            Err(BoundsError::AboveMaximum)
+        } else if value < self.min as i64 {
+            Err(BoundsError::BelowMinimum)
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// The value after adding `delta` to `current`, according to the
+    /// overflow policy. A value already out of bounds, as merging with peers
+    /// can leave it, may always move back towards them.
+    pub fn apply(&self, current: i64, delta: i64) -> Result<i64, BoundsError> {
+        let (min, max) = (self.min as i64, self.max as i64);
+        let next = current.saturating_add(delta);
+        // saturating never moves a value that is already out of bounds further out
+        let (error, saturated) = if next > max && delta > 0 {
+            (BoundsError::AboveMaximum, current.max(max))
+        } else if next < min && delta < 0 {
+            (BoundsError::BelowMinimum, current.min(min))
+        } else {
+            return Ok(next);
+        };
+        match self.overflow {
+            OverflowPolicy::Reject => Err(error),
+            OverflowPolicy::Saturate => Ok(saturated),
+            OverflowPolicy::Wrap => {
+                let span = max as i128 - min as i128 + 1;
+                let wrapped = (current as i128 + delta as i128 - min as i128).rem_euclid(span);
+                Ok((min as i128 + wrapped) as i64)
+            }
+        }
+    }
+}
+
+/// Everything peers need to rebuild a counter.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Replica {
+    pub epoch: Epoch,
+    pub bounds: Bounds,
+    pub counts: PnCounter,
+}
+
+/// This node's replica of one shared counter.
+pub struct Counter {
+    node: String,
+    replica: RwLock<Replica>,
+    // bumped on every local change, so replication knows when to gossip
+    version: Arc<AtomicU64>,
+    // the version of this node's last change to this counter
+    changed: AtomicU64,
+    // the latest value, published on every local change or merge
+    changes: watch::Sender<i32>,
+    history: History,
+    stats: Stats,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter::new(LOCAL_NODE)
+    }
+}
+
+impl Counter {
+    pub fn new(node: impl Into<String>) -> Counter {
+        Counter::from_replica(node.into(), Replica::default(), Arc::default())
+    }
+
+    fn from_replica(node: String, replica: Replica, version: Arc<AtomicU64>) -> Counter {
+        let value = clamp(replica.counts.value());
+        Counter {
+            node,
+            replica: RwLock::new(replica),
+            version,
+            changed: AtomicU64::new(0),
+            changes: watch::channel(value).0,
+            history: History::default(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// The current value, clamped to the range clients can represent.
+    pub fn value(&self) -> i32 {
+        clamp(
+            self.replica
+                .read()
+                .expect("counter lock poisoned")
+                .counts
+                .value(),
+        )
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        self.replica.read().expect("counter lock poisoned").bounds
+    }
+
+    /// Watch the value, which is pushed to the receiver whenever it changes.
+    pub fn subscribe(&self) -> watch::Receiver<i32> {
+        self.changes.subscribe()
+    }
+
+    /// The changes recently made to this counter through this backend.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// How fast this counter has been moving through this backend.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Apply `update` to the value while holding the write lock, so that
+    /// checking the bounds and changing the value happen as one step.
+    ///
+    /// Bounds are only enforced against this replica: concurrent changes on
+    /// other backends can still carry the merged value past them.
+    pub fn update<E>(&self, update: impl FnOnce(i64, Bounds) -> Result<i64, E>) -> Result<i32, E> {
+        let mut replica = self.replica.write().expect("counter lock poisoned");
+        let current = replica.counts.value();
+        let next = update(current, replica.bounds)?;
+        if next > current {
+            replica
+                .counts
+                .increment(&self.node, (next - current) as u64);
+        } else if next < current {
+            replica
+                .counts
+                .decrement(&self.node, (current - next) as u64);
+        }
+        if next != current {
+            self.touch();
+            self.stats.record(next - current, Instant::now());
+            // publish under the lock so concurrent updates cannot be seen out of order
+            self.changes.send_replace(clamp(next));
+        }
+        Ok(clamp(next))
+    }
+
+    /// Start a new epoch holding `value` within `bounds`, discarding the old counts.
+    fn reset(&self, epoch: Epoch, bounds: Bounds, value: i32) {
+        let mut replica = self.replica.write().expect("counter lock poisoned");
+        let mut counts = PnCounter::default();
+        if value > 0 {
+            counts.increment(&self.node, value as u64);
+        } else if value < 0 {
+            counts.decrement(&self.node, value.unsigned_abs() as u64);
+        }
+        *replica = Replica {
+            epoch,
+            bounds,
+            counts,
+        };
+        self.touch();
+        self.changes.send_replace(value);
+    }
+
+    /// Record a local change, which callers make while holding the write
+    /// lock so that `changed_since` cannot miss it.
+    fn touch(&self) {
+        let version = self.version.fetch_add(1, Ordering::AcqRel) + 1;
+        self.changed.store(version, Ordering::Release);
+    }
+
+    /// Merge a peer's replica into ours, returning whether anything changed.
+    pub fn merge(&self, other: &Replica) -> bool {
+        let mut replica = self.replica.write().expect("counter lock poisoned");
+        let changed = match other.epoch.cmp(&replica.epoch) {
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Equal => replica.counts.merge(&other.counts),
+            std::cmp::Ordering::Greater => {
+                *replica = other.clone();
+                true
+            }
+        };
+        if changed {
+            self.changes.send_if_modified(|value| {
+                let merged = clamp(replica.counts.value());
+                std::mem::replace(value, merged) != merged
+            });
+        }
+        changed
+    }
+
+    /// A copy of the full replica, for sending to peers.
+    pub fn snapshot(&self) -> Replica {
+        self.replica.read().expect("counter lock poisoned").clone()
+    }
+
+    /// A copy of the full replica if this node has changed it since `version`.
+    fn changed_since(&self, version: u64) -> Option<Replica> {
+        let replica = self.replica.read().expect("counter lock poisoned");
+        (self.changed.load(Ordering::Acquire) > version).then(|| replica.clone())
+    }
+
+    fn epoch(&self) -> Epoch {
+        self.replica
+            .read()
+            .expect("counter lock poisoned")
+            .epoch
+            .clone()
+    }
+
+    /// Changes made on this node so far.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+/// The epoch a counter was deleted in, and the version of the deletion if
+/// it was made on this node.
+struct Tombstone {
+    epoch: Epoch,
+    version: u64,
+}
+
+/// Every counter this node knows about, by name, alongside the epochs of
+/// those that have been deleted so that stale gossip cannot revive them.
+///
+/// Clients can create at most `max_counters`, the default counter included.
+/// Counters seeded from configuration or merged from peers are always kept.
+pub struct Counters {
+    node: String,
+    max_counters: usize,
+    counters: DashMap<String, Arc<Counter>>,
+    deleted: DashMap<String, Tombstone>,
+    // shared with every counter, and bumped on creation and deletion too
+    version: Arc<AtomicU64>,
+    // bumped whenever a peer's state changes ours
+    merges: AtomicU64,
+}
+
+/// The state of every counter, as gossiped between backends.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CountersSnapshot {
+    pub counters: HashMap<String, Replica>,
+    pub deleted: HashMap<String, Epoch>,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters::new(LOCAL_NODE)
+    }
+}
+
+impl Counters {
+    /// A registry holding just the default counter, which every node starts
+    /// in the same epoch so that their default counters merge from the outset.
+    pub fn new(node: impl Into<String>) -> Counters {
+        let node = node.into();
+        let version = Arc::new(AtomicU64::new(0));
+        let counters = DashMap::new();
+        counters.insert(
+            DEFAULT_COUNTER.to_owned(),
+            Arc::new(Counter::from_replica(
+                node.clone(),
+                Replica::default(),
+                version.clone(),
+            )),
+        );
+        Counters {
+            node,
+            max_counters: DEFAULT_MAX_COUNTERS,
+            counters,
+            deleted: DashMap::new(),
+            version,
+            merges: AtomicU64::new(0),
+        }
+    }
+
+    /// Let clients create at most `max` counters, the default counter included.
+    pub fn max_counters(mut self, max: usize) -> Self {
+        self.max_counters = max.max(1);
+        self
+    }
+
+    /// Refuse to create another counter if there are already enough.
+    fn check_room(&self) -> Result<(), TooManyCounters> {
+        if self.counters.len() >= self.max_counters {
+            return Err(TooManyCounters {
+                max: self.max_counters,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Counter>> {
+        self.counters.get(name).map(|counter| counter.clone())
+    }
+
+    /// The counter behind the unnamed `/api/count` routes, which always exists.
+    pub fn default_counter(&self) -> Arc<Counter> {
+        self.get(DEFAULT_COUNTER).expect("default counter missing")
+    }
+
+    /// Every counter, sorted by name.
+    pub fn list(&self) -> Vec<(String, Arc<Counter>)> {
+        let mut counters: Vec<_> = self
+            .counters
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        counters.sort_by(|a, b| a.0.cmp(&b.0));
+        counters
+    }
+
+    /// Create the counter `name` holding `value`, or replace it if it already
+    /// exists. Returns whether it was created.
+    pub fn put(&self, name: &str, value: i32, bounds: Bounds) -> Result<bool, TooManyCounters> {
+        if !self.counters.contains_key(name) {
+            self.check_room()?;
+        }
+        let tombstone = self
+            .deleted
+            .remove(name)
+            .map(|(_, tombstone)| tombstone.epoch);
+        let created = match self.counters.entry(name.to_owned()) {
+            Entry::Occupied(entry) => {
+                let counter = entry.get();
+                let epoch = Epoch::next(&self.node, Some(&counter.epoch()));
+                counter.reset(epoch, bounds, value);
+                false
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(self.create(tombstone.as_ref(), value, bounds));
+                true
+            }
+        };
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(created)
+    }
+
+    /// Create the counter `name` holding `value`, unless it already exists or
+    /// was deleted. Returns whether it was created.
+    ///
+    /// Every node seeds a configured counter the same way, in the first epoch
+    /// and with `value` credited to no node in particular, so restarting a
+    /// node neither resets the counter on its peers nor adds `value` again.
+    pub fn seed(&self, name: &str, value: i32, bounds: Bounds) -> bool {
+        if self.deleted.contains_key(name) {
+            return false;
+        }
+        let mut counts = PnCounter::default();
+        if value > 0 {
+            counts.increment(SEED_NODE, value as u64);
+        } else if value < 0 {
+            counts.decrement(SEED_NODE, value.unsigned_abs() as u64);
+        }
+        match self.counters.entry(name.to_owned()) {
+            Entry::Occupied(_) => return false,
+            Entry::Vacant(entry) => {
+                let counter = Counter::from_replica(
+                    self.node.clone(),
+                    Replica {
+                        epoch: Epoch::default(),
+                        bounds,
+                        counts,
+                    },
+                    self.version.clone(),
+                );
+                counter.touch();
+                entry.insert(Arc::new(counter));
+            }
+        }
+        true
+    }
+
+    /// A new counter, in an epoch after that of any counter deleted under its name.
+    fn create(&self, tombstone: Option<&Epoch>, value: i32, bounds: Bounds) -> Arc<Counter> {
+        let counter = Counter::from_replica(
+            self.node.clone(),
+            Replica {
+                epoch: Epoch::next(&self.node, tombstone),
+                ..Replica::default()
+            },
+            self.version.clone(),
+        );
+        counter.reset(counter.epoch(), bounds, value);
+        Arc::new(counter)
+    }
+
+    /// Delete the counter `name`, returning it if it existed. The default
+    /// counter cannot be deleted.
+    pub fn remove(&self, name: &str) -> Option<Arc<Counter>> {
+        if name == DEFAULT_COUNTER {
+            return None;
+        }
+        let (_, counter) = self.counters.remove(name)?;
+        let version = self.version.fetch_add(1, Ordering::AcqRel) + 1;
+        let epoch = counter.epoch();
+        self.deleted
+            .insert(name.to_owned(), Tombstone { epoch, version });
+        Some(counter)
+    }
+
+    /// A copy of every replica and deletion, for sending to peers.
+    pub fn snapshot(&self) -> CountersSnapshot {
+        CountersSnapshot {
+            counters: self
+                .counters
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().snapshot()))
+                .collect(),
+            deleted: self
+                .deleted
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().epoch.clone()))
+                .collect(),
+        }
+    }
+
+    /// The replicas and deletions this node has changed since `version`,
+    /// for sending to peers who already have the rest.
+    pub fn changes_since(&self, version: u64) -> CountersSnapshot {
+        CountersSnapshot {
+            counters: self
+                .counters
+                .iter()
+                .filter_map(|entry| {
+                    let replica = entry.value().changed_since(version)?;
+                    Some((entry.key().clone(), replica))
+                })
+                .collect(),
+            deleted: self
+                .deleted
+                .iter()
+                .filter(|entry| entry.value().version > version)
+                .map(|entry| (entry.key().clone(), entry.value().epoch.clone()))
+                .collect(),
+        }
+    }
+
+    /// Merge a peer's counters into ours, returning whether anything changed.
+    pub fn merge(&self, other: &CountersSnapshot) -> bool {
+        let mut changed = false;
+        for (name, epoch) in &other.deleted {
+            if name == DEFAULT_COUNTER {
+                continue;
+            }
+            let peer_tombstone = Tombstone {
+                epoch: epoch.clone(),
+                version: 0,
+            };
+            let tombstone = match self.deleted.entry(name.clone()) {
+                Entry::Occupied(mut entry) => {
+                    if *epoch > entry.get().epoch {
+                        entry.insert(peer_tombstone);
+                        changed = true;
+                    }
+                    entry.into_ref()
+                }
+                Entry::Vacant(entry) => {
+                    changed = true;
+                    entry.insert(peer_tombstone)
+                }
+            };
+            changed |= self
+                .counters
+                .remove_if(name, |_, counter| counter.epoch() <= tombstone.epoch)
+                .is_some();
+        }
+        for (name, replica) in &other.counters {
+            if self
+                .deleted
+                .get(name)
+                .is_some_and(|tombstone| replica.epoch <= tombstone.epoch)
+            {
+                continue;
+            }
+            match self.counters.entry(name.clone()) {
+                Entry::Occupied(entry) => changed |= entry.get().merge(replica),
+                Entry::Vacant(entry) => {
+                    entry.insert(Arc::new(Counter::from_replica(
+                        self.node.clone(),
+                        replica.clone(),
+                        self.version.clone(),
+                    )));
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.merges.fetch_add(1, Ordering::Release);
+        }
+        changed
+    }
+
+    /// Changes made on this node so far, to any counter.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Changes made on this node or merged from peers so far, which is
+    /// everything that needs persisting.
+    pub fn revision(&self) -> u64 {
+        self.version() + self.merges.load(Ordering::Acquire)
+    }
+}
+
+fn clamp(value: i64) -> i32 {
+    value.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_is_increments_minus_decrements() {
+        let mut counter = PnCounter::default();
+        counter.increment("a", 3);
+        counter.increment("b", 2);
+        counter.decrement("a", 4);
+        assert_eq!(counter.value(), 1);
+    }
+
+    #[test]
+    fn merge_is_commutative_and_idempotent() {
+        let mut a = PnCounter::default();
+        a.increment("a", 2);
+        let mut b = PnCounter::default();
+        b.increment("b", 5);
+        b.decrement("b", 1);
+
+        let mut ab = a.clone();
+        assert!(ab.merge(&b));
+        let mut ba = b.clone();
+        assert!(ba.merge(&a));
+        assert_eq!(ab, ba);
+        assert_eq!(ab.value(), 6);
+
+        assert!(!ab.merge(&b));
+        assert_eq!(ab.value(), 6);
+    }
+
+    #[test]
+    fn merge_keeps_the_latest_total_per_node() {
+        let mut stale = PnCounter::default();
+        stale.increment("a", 1);
+        let mut fresh = stale.clone();
+        fresh.increment("a", 1);
+
+        let mut merged = fresh.clone();
+        assert!(!merged.merge(&stale));
+        assert_eq!(merged.value(), 2);
+    }
+
+    #[test]
+    fn huge_peer_totals_are_clamped() {
+        let mut peer = PnCounter::default();
+        peer.increment("a", u64::MAX);
+        peer.increment("b", u64::MAX);
+        assert_eq!(peer.value(), i64::MAX);
+
+        let mut ours = PnCounter::default();
+        ours.decrement("c", 1);
+        let huge = PnCounter {
+            increments: HashMap::from([("a".to_owned(), u64::MAX)]),
+            decrements: HashMap::from([("b".to_owned(), u64::MAX)]),
+        };
+        assert!(ours.merge(&huge));
+        assert_eq!(ours.increments["a"], i64::MAX as u64);
+        assert_eq!(ours.value(), -1);
+    }
+
+    #[test]
+    fn update_records_the_difference_for_this_node() {
+        let counter = Counter::new("a");
+        assert_eq!(counter.update::<()>(|value, _| Ok(value + 5)), Ok(5));
+        assert_eq!(counter.update::<()>(|value, _| Ok(value - 7)), Ok(-2));
+        assert_eq!(counter.version(), 2);
+
+        let mut expected = PnCounter::default();
+        expected.increment("a", 5);
+        expected.decrement("a", 7);
+        assert_eq!(counter.snapshot().counts, expected);
+    }
+
+    #[test]
+    fn changes_are_published_to_subscribers() {
+        let counter = Counter::new("a");
+        let mut changes = counter.subscribe();
+        assert!(!changes.has_changed().unwrap());
+
+        counter.update::<()>(|value, _| Ok(value + 1)).unwrap();
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(*changes.borrow_and_update(), 1);
+
+        let mut remote = counter.snapshot();
+        remote.counts.increment("b", 2);
+        counter.merge(&remote);
+        assert_eq!(*changes.borrow_and_update(), 3);
+    }
+
+    #[test]
+    fn failed_update_changes_nothing() {
+        let counter = Counter::new("a");
+        assert_eq!(counter.update(|_, _| Err("nope")), Err("nope"));
+        assert_eq!(counter.value(), 0);
+        assert_eq!(counter.version(), 0);
+    }
+
+    #[test]
+    fn bounds_apply_the_overflow_policy() {
+        let reject = Bounds::new(-2, 2);
+        assert_eq!(reject.apply(1, 1), Ok(2));
+        assert_eq!(reject.apply(2, 1), Err(BoundsError::AboveMaximum));
+        assert_eq!(reject.apply(-2, -1), Err(BoundsError::BelowMinimum));
+
+        let saturate = reject.overflow(OverflowPolicy::Saturate);
+        assert_eq!(saturate.apply(1, 5), Ok(2));
+        assert_eq!(saturate.apply(-1, -5), Ok(-2));
+
+        let wrap = reject.overflow(OverflowPolicy::Wrap);
+        assert_eq!(wrap.apply(2, 1), Ok(-2));
+        assert_eq!(wrap.apply(-2, -1), Ok(2));
+        assert_eq!(wrap.apply(0, 12), Ok(2));
+    }
+
+    #[test]
+    fn values_set_outright_ignore_the_overflow_policy() {
+        let wrap = Bounds::new(-2, 2).overflow(OverflowPolicy::Wrap);
+        assert_eq!(wrap.check(2), Ok(2));
+        assert_eq!(wrap.check(3), Err(BoundsError::AboveMaximum));
+        assert_eq!(wrap.check(-3), Err(BoundsError::BelowMinimum));
+    }
+
+    #[test]
+    fn full_range_bounds_wrap_like_an_i32() {
+        let wrap = Bounds::default().overflow(OverflowPolicy::Wrap);
+        assert_eq!(wrap.apply(i32::MAX as i64, 1), Ok(i32::MIN as i64));
+        assert_eq!(wrap.apply(i32::MIN as i64, -1), Ok(i32::MAX as i64));
+    }
+
+    #[test]
+    fn out_of_bounds_values_may_move_back() {
+        let bounds = Bounds::new(0, 10);
+        assert_eq!(bounds.apply(12, -1), Ok(11));
+        assert_eq!(bounds.apply(12, 1), Err(BoundsError::AboveMaximum));
+        let saturate = bounds.overflow(OverflowPolicy::Saturate);
+        assert_eq!(saturate.apply(12, 1), Ok(12));
+    }
+
+    #[test]
+    fn newer_epoch_replaces_older_counts() {
+        let counter = Counter::new("a");
+        counter.update::<()>(|value, _| Ok(value + 5)).unwrap();
+
+        let newer = Replica {
+            epoch: Epoch {
+                created: 1,
+                node: "b".into(),
+            },
+            ..Replica::default()
+        };
+        assert!(counter.merge(&newer));
+        assert_eq!(counter.value(), 0);
+
+        // totals from the old epoch no longer count
+        let mut stale = Replica::default();
+        stale.counts.increment("c", 3);
+        assert!(!counter.merge(&stale));
+        assert_eq!(counter.value(), 0);
+    }
+
+    #[test]
+    fn put_creates_then_replaces_counters() {
+        let counters = Counters::new("a");
+        let bounds = Bounds::new(0, 10);
+        assert_eq!(counters.put("apples", 3, bounds), Ok(true));
+        let apples = counters.get("apples").unwrap();
+        assert_eq!(apples.value(), 3);
+        assert_eq!(apples.bounds(), bounds);
+
+        assert_eq!(counters.put("apples", 7, Bounds::default()), Ok(false));
+        assert_eq!(apples.value(), 7);
+        assert_eq!(apples.bounds(), Bounds::default());
+
+        let names: Vec<String> = counters.list().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["apples", DEFAULT_COUNTER]);
+    }
+
+    #[test]
+    fn clients_cannot_create_more_than_the_maximum() {
+        let counters = Counters::new("a").max_counters(2);
+        assert_eq!(counters.put("apples", 1, Bounds::default()), Ok(true));
+        let full = Err(TooManyCounters { max: 2 });
+        assert_eq!(counters.put("pears", 1, Bounds::default()), full);
+        // replacing a counter does not need room for another
+        assert_eq!(counters.put("apples", 2, Bounds::default()), Ok(false));
+
+        counters.remove("apples");
+        assert_eq!(counters.put("pears", 1, Bounds::default()), Ok(true));
+    }
+
+    #[test]
+    fn default_counter_cannot_be_removed() {
+        let counters = Counters::new("a");
+        assert!(counters.remove(DEFAULT_COUNTER).is_none());
+        assert_eq!(counters.default_counter().value(), 0);
+    }
+
+    #[test]
+    fn counters_replicate_creation_and_deletion() {
+        let a = Counters::new("a");
+        let b = Counters::new("b");
+        a.put("apples", 3, Bounds::default()).unwrap();
+        assert!(b.merge(&a.snapshot()));
+        assert_eq!(b.get("apples").unwrap().value(), 3);
+
+        let before_delete = b.snapshot();
+        assert!(a.remove("apples").is_some());
+        assert!(b.merge(&a.snapshot()));
+        assert!(b.get("apples").is_none());
+
+        // stale gossip does not bring a deleted counter back
+        assert!(!a.merge(&before_delete));
+        assert!(a.get("apples").is_none());
+
+        // but creating it again does
+        a.put("apples", 1, Bounds::default()).unwrap();
+        b.merge(&a.snapshot());
+        assert_eq!(b.get("apples").unwrap().value(), 1);
+    }
+
+    #[test]
+    fn seeding_on_every_node_keeps_what_peers_counted() {
+        let a = Counters::new("a");
+        let b = Counters::new("b");
+        assert!(a.seed("apples", 5, Bounds::default()));
+        b.merge(&a.snapshot());
+        let apples = b.get("apples").unwrap();
+        apples.update::<()>(|value, _| Ok(value + 2)).unwrap();
+
+        // a restarts and seeds the counter again before hearing from b
+        let restarted = Counters::new("a");
+        assert!(restarted.seed("apples", 5, Bounds::default()));
+        assert!(!restarted.seed("apples", 9, Bounds::default()));
+        restarted.merge(&b.snapshot());
+        b.merge(&restarted.snapshot());
+        assert_eq!(restarted.get("apples").unwrap().value(), 7);
+        assert_eq!(apples.value(), 7);
+
+        // nor does seeding bring back a deleted counter
+        b.remove("apples");
+        restarted.merge(&b.snapshot());
+        assert!(!restarted.seed("apples", 5, Bounds::default()));
+        assert!(restarted.get("apples").is_none());
+    }
+
+    #[test]
+    fn changes_since_holds_only_what_changed_here() {
+        let counters = Counters::new("a");
+        counters.put("apples", 1, Bounds::default()).unwrap();
+        counters.put("pears", 1, Bounds::default()).unwrap();
+        let version = counters.version();
+
+        counters
+            .get("apples")
+            .unwrap()
+            .update::<()>(|value, _| Ok(value + 1))
+            .unwrap();
+        counters.remove("pears");
+        let peer = Counters::new("b");
+        peer.put("plums", 1, Bounds::default()).unwrap();
+        counters.merge(&peer.snapshot());
+
+        let changes = counters.changes_since(version);
+        let names: Vec<&String> = changes.counters.keys().collect();
+        assert_eq!(names, ["apples"]);
+        assert!(changes.deleted.contains_key("pears"));
+        assert!(counters
+            .changes_since(counters.version())
+            .counters
+            .is_empty());
+    }
+}
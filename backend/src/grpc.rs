@@ -0,0 +1,257 @@
+use crate::counter::{Counter, DEFAULT_COUNTER};
+use crate::history::{Origin, X_REQUEST_ID};
+use crate::limiter::{
+    key::{ClientId, KeyError},
+    layer::whole_seconds,
+    Refusal,
+};
+use crate::routes::{
+    count::{self, try_alter_count, ServerError},
+    counters,
+};
+use crate::state::AppState;
+use axum::{extract::ConnectInfo, http::Method};
+use client::CountRequest;
+use futures::stream::{self, BoxStream, StreamExt};
+use proto::{
+    alter_count_request::{CompareAndSwap, Op},
+    counter_service_server::{CounterService, CounterServiceServer},
+    AlterCountRequest, CountReply, GetCountRequest, WatchCountRequest,
+};
+use std::{net::TcpListener, sync::Arc};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{Code, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("limitrs.counter");
+}
+
+/// Serve the counters over gRPC on `listener`, alongside the HTTP API.
+///
+/// Every call is held to the access lists, and `AlterCount` to the policy of
+/// the matching HTTP route, keyed the same way as HTTP requests, with any API key sent as
+/// metadata. The server stops with the HTTP API, ending any `WatchCount` streams.
+pub fn serve(listener: TcpListener, state: AppState) {
+    listener
+        .set_nonblocking(true)
+        .expect("failed to make gRPC listener non-blocking");
+    let listener =
+        tokio::net::TcpListener::from_std(listener).expect("failed to register gRPC listener");
+    let shutdown = state.shutdown.clone();
+    let draining = shutdown.guard();
+    tokio::spawn(async move {
+        let result = tonic::transport::Server::builder()
+            .add_service(CounterServiceServer::new(Counters { state }))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown.triggered())
+            .await;
+        drop(draining);
+        if let Err(err) = result {
+            log::error!("gRPC server failed: {}", err);
+        }
+    });
+}
+
+struct Counters {
+    state: AppState,
+}
+
+impl Counters {
+    // `Status` is what every RPC returns, large or not
+    #[allow(clippy::result_large_err)]
+    fn counter(&self, name: &str) -> Result<Arc<Counter>, Status> {
+        let name = if name.is_empty() {
+            DEFAULT_COUNTER
+        } else {
+            name
+        };
+        self.state
+            .counters
+            .get(name)
+            .ok_or_else(|| Status::not_found(format!("No counter named {}", name)))
+    }
+
+    /// Refuse callers on the deny list, as the HTTP API does.
+    #[allow(clippy::result_large_err)]
+    fn check_access<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let config = self.state.limiters.config();
+        let ip = config.key.client_ip(&http_request(request));
+        if self.state.forbids(ip) {
+            return Err(refusal_status(Refusal::Forbidden));
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl CounterService for Counters {
+    async fn get_count(
+        &self,
+        request: Request<GetCountRequest>,
+    ) -> Result<Response<CountReply>, Status> {
+        self.check_access(&request)?;
+        let counter = self.counter(&request.get_ref().name)?;
+        Ok(Response::new(CountReply {
+            count: counter.value(),
+        }))
+    }
+
+    async fn alter_count(
+        &self,
+        request: Request<AlterCountRequest>,
+    ) -> Result<Response<CountReply>, Status> {
+        // key the call as the HTTP layer would the same request
+        let http = http_request(&request);
+        let config = self.state.limiters.config();
+        let key = config.key.extract(&http).map_err(key_status)?;
+        let ip = config.key.client_ip(&http);
+        let origin = origin(&request, config.key.client_id(&key));
+
+        let request = request.into_inner();
+        let op = request
+            .op
+            .ok_or_else(|| Status::invalid_argument("AlterCountRequest needs an op"))?;
+        let counter = self.counter(&request.name)?;
+        let route = if request.name.is_empty() {
+            count::MUTATION_ROUTE
+        } else {
+            counters::MUTATION_ROUTE
+        };
+        self.state
+            .admit(&Method::POST, route, &key, ip)
+            .map_err(refusal_status)?;
+        let count = try_alter_count(&counter, count_request(op), &origin).map_err(status)?;
+        Ok(Response::new(CountReply { count }))
+    }
+
+    type WatchCountStream = BoxStream<'static, Result<CountReply, Status>>;
+
+    async fn watch_count(
+        &self,
+        request: Request<WatchCountRequest>,
+    ) -> Result<Response<Self::WatchCountStream>, Status> {
+        self.check_access(&request)?;
+        let changes = self.counter(&request.get_ref().name)?.subscribe();
+        let shutdown = self.state.shutdown.clone();
+        // the first reply is the current count, then every change as it is published
+        let replies = stream::unfold((changes, false), move |(mut changes, started)| {
+            let shutdown = shutdown.clone();
+            async move {
+                if started {
+                    tokio::select! {
+                        changed = changes.changed() => changed.ok()?,
+                        _ = shutdown.triggered() => return None,
+                    }
+                }
+                let count = *changes.borrow_and_update();
+                Some((Ok(CountReply { count }), (changes, true)))
+            }
+        });
+        Ok(Response::new(replies.boxed()))
+    }
+}
+
+fn origin<T>(request: &Request<T>, ClientId(client): ClientId) -> Origin {
+    Origin {
+        // everyone shares the empty key when calls are not told apart
+        client: Some(client).filter(|client| !client.is_empty()),
+        request_id: request
+            .metadata()
+            .get(X_REQUEST_ID.as_str())
+            .and_then(|id| id.to_str().ok())
+            .map(str::to_owned),
+    }
+}
+
+/// The HTTP request a call amounts to, as far as keying it is concerned.
+fn http_request<T>(request: &Request<T>) -> axum::http::Request<()> {
+    let mut http = axum::http::Request::new(());
+    *http.headers_mut() = request.metadata().clone().into_headers();
+    if let Some(addr) = request.remote_addr() {
+        http.extensions_mut().insert(ConnectInfo(addr));
+    }
+    http
+}
+
+fn count_request(op: Op) -> CountRequest {
+    match op {
+        Op::Increment(_) => CountRequest::Increment,
+        Op::Decrement(_) => CountRequest::Decrement,
+        Op::Add(amount) => CountRequest::Add { amount },
+        Op::Subtract(amount) => CountRequest::Subtract { amount },
+        Op::Set(value) => CountRequest::Set { value },
+        Op::Reset(_) => CountRequest::Reset,
+        Op::CompareAndSwap(CompareAndSwap { expected, new }) => {
+            CountRequest::CompareAndSwap { expected, new }
+        }
+    }
+}
+
+fn status(err: ServerError) -> Status {
+    let code = match err {
+        ServerError::MaximumValueError | ServerError::MinimumValueError => Code::OutOfRange,
+        ServerError::ValueMismatchError { .. } => Code::FailedPrecondition,
+        ServerError::SerialisationError => Code::Internal,
+    };
+    let (_, _, message) = err.describe();
+    Status::new(code, message)
+}
+
+fn key_status(err: KeyError) -> Status {
+    Status::unauthenticated(err.to_string())
+}
+
+fn refusal_status(refusal: Refusal) -> Status {
+    match refusal {
+        Refusal::Forbidden => Status::permission_denied("Forbidden"),
+        Refusal::RateLimited { retry_after } => Status::resource_exhausted(format!(
+            "Too Many Requests, retry after {} seconds",
+            whole_seconds(retry_after)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn ops_map_onto_count_requests() {
+        assert_eq!(count_request(Op::Add(3)), CountRequest::Add { amount: 3 });
+        assert_eq!(
+            count_request(Op::CompareAndSwap(CompareAndSwap {
+                expected: 1,
+                new: 2
+            })),
+            CountRequest::CompareAndSwap {
+                expected: 1,
+                new: 2
+            }
+        );
+    }
+
+    #[test]
+    fn bound_violations_are_out_of_range() {
+        assert_eq!(
+            status(ServerError::MaximumValueError).code(),
+            Code::OutOfRange
+        );
+        assert_eq!(
+            status(ServerError::ValueMismatchError { current: 1 }).code(),
+            Code::FailedPrecondition
+        );
+    }
+
+    #[test]
+    fn refusals_map_onto_status_codes() {
+        assert_eq!(
+            refusal_status(Refusal::Forbidden).code(),
+            Code::PermissionDenied
+        );
+        let status = refusal_status(Refusal::RateLimited {
+            retry_after: Duration::from_millis(1500),
+        });
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.message().contains("2 seconds"));
+    }
+}
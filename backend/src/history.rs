@@ -0,0 +1,208 @@
+use crate::limiter::key::ClientId;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderName},
+};
+use client::{CountEvent, HistoryQuery, HistoryResponse};
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many events each counter remembers by default.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Who asked for a change: the `ClientId` of the key they are rate-limited
+/// under, never a secret API key itself, and the id of the request they
+/// asked with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Origin {
+    pub client: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Origin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let client = parts
+            .extensions
+            .get::<ClientId>()
+            .map(|ClientId(id)| id.clone())
+            // everyone shares the empty key when requests are not told apart
+            .filter(|key| !key.is_empty());
+        let request_id = parts
+            .headers
+            .get(&X_REQUEST_ID)
+            .and_then(|id| id.to_str().ok())
+            .map(str::to_owned);
+        Ok(Origin { client, request_id })
+    }
+}
+
+/// The most recent changes made to a counter through this backend, oldest
+/// first. Changes merged from peers are not recorded: each backend only
+/// vouches for what it was asked to do.
+pub struct History {
+    capacity: usize,
+    log: Mutex<Log>,
+}
+
+#[derive(Default)]
+struct Log {
+    last_id: u64,
+    events: VecDeque<CountEvent>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl History {
+    /// A history which forgets the oldest event once it holds `capacity`.
+    pub fn new(capacity: usize) -> History {
+        History {
+            capacity,
+            log: Mutex::new(Log::default()),
+        }
+    }
+
+    /// Record a change by `delta` which left the counter at `count`,
+    /// returning the id of the new event.
+    pub fn record(&self, delta: i64, count: i32, origin: &Origin) -> u64 {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as u64)
+            .unwrap_or_default();
+        self.record_at(timestamp, delta, count, origin)
+    }
+
+    fn record_at(&self, timestamp: u64, delta: i64, count: i32, origin: &Origin) -> u64 {
+        let mut log = self.log.lock().expect("history lock poisoned");
+        log.last_id += 1;
+        let id = log.last_id;
+        if log.events.len() == self.capacity {
+            log.events.pop_front();
+        }
+        log.events.push_back(CountEvent {
+            id,
+            timestamp,
+            delta,
+            count,
+            client: origin.client.clone(),
+            request_id: origin.request_id.clone(),
+        });
+        id
+    }
+
+    /// The id of the latest event, or zero before the first.
+    pub fn last_id(&self) -> u64 {
+        self.log.lock().expect("history lock poisoned").last_id
+    }
+
+    /// Every event still remembered with an id greater than `id`.
+    pub fn after(&self, id: u64) -> Vec<CountEvent> {
+        let log = self.log.lock().expect("history lock poisoned");
+        log.events
+            .iter()
+            .filter(|event| event.id > id)
+            .cloned()
+            .collect()
+    }
+
+    /// The page of events matching `query`.
+    pub fn page(&self, query: &HistoryQuery) -> HistoryResponse {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let log = self.log.lock().expect("history lock poisoned");
+        let mut events = log.events.iter().filter(|event| {
+            query.after.is_none_or(|after| event.id > after)
+                && query.since.is_none_or(|since| event.timestamp >= since)
+                && query.until.is_none_or(|until| event.timestamp < until)
+        });
+
+        let page: Vec<CountEvent> = events.by_ref().take(limit).cloned().collect();
+        let next = match (events.next(), page.last()) {
+            (Some(_), Some(last)) => Some(last.id),
+            _ => None,
+        };
+        HistoryResponse { events: page, next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(response: &HistoryResponse) -> Vec<u64> {
+        response.events.iter().map(|event| event.id).collect()
+    }
+
+    #[test]
+    fn oldest_events_are_forgotten_at_capacity() {
+        let history = History::new(3);
+        for count in 1..=5 {
+            history.record(1, count, &Origin::default());
+        }
+        let page = history.page(&HistoryQuery::default());
+        assert_eq!(ids(&page), [3, 4, 5]);
+        assert_eq!(page.events[2].count, 5);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn pages_follow_on_from_next() {
+        let history = History::default();
+        for count in 1..=5 {
+            history.record(1, count, &Origin::default());
+        }
+        let mut query = HistoryQuery {
+            limit: Some(2),
+            ..HistoryQuery::default()
+        };
+        let first = history.page(&query);
+        assert_eq!(ids(&first), [1, 2]);
+
+        query.after = first.next;
+        let second = history.page(&query);
+        assert_eq!(ids(&second), [3, 4]);
+
+        query.after = second.next;
+        let last = history.page(&query);
+        assert_eq!(ids(&last), [5]);
+        assert_eq!(last.next, None);
+    }
+
+    #[test]
+    fn pages_are_limited_to_a_time_range() {
+        let history = History::default();
+        let origin = Origin {
+            client: Some("10.0.0.1".into()),
+            request_id: Some("abc".into()),
+        };
+        for timestamp in [100, 200, 300, 400] {
+            history.record_at(timestamp, -1, 0, &origin);
+        }
+        let page = history.page(&HistoryQuery {
+            since: Some(200),
+            until: Some(400),
+            ..HistoryQuery::default()
+        });
+        assert_eq!(ids(&page), [2, 3]);
+        assert_eq!(page.events[0].client.as_deref(), Some("10.0.0.1"));
+        assert_eq!(page.events[0].request_id.as_deref(), Some("abc"));
+    }
+}
@@ -0,0 +1,565 @@
+use crate::limiter::key::ClientKey;
+use crate::routes::error_response;
+use axum::{
+    body::{boxed, Body, Bytes, Full, HttpBody},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    response::Response,
+};
+use client::{ErrorKind, IDEMPOTENCY_KEY_HEADER};
+use dashmap::{mapref::entry::Entry as MapEntry, DashMap};
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+
+/// How long responses are remembered by default, as suggested by the IETF draft.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many keys are remembered at most by default.
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+const MAX_KEY_LENGTH: usize = 255;
+// mutations are tiny, so anything bigger is not worth buffering to hash
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// The method and path a key was first used with, the client key of whoever
+/// sent it, and the key itself. Keys are scoped to the request they were
+/// sent with, so a client reusing one against another route, or another
+/// client happening on the same one, is not answered with an unrelated
+/// response.
+type CacheKey = (Method, String, String, String);
+
+/// A digest of the body sent with a key, to tell when the key is reused for
+/// a different request.
+type BodyHash = [u8; 32];
+
+enum Entry {
+    InFlight {
+        started: Instant,
+        body: BodyHash,
+    },
+    Done {
+        stored: Instant,
+        body: BodyHash,
+        response: StoredResponse,
+    },
+}
+
+impl Entry {
+    fn started(&self) -> Instant {
+        match self {
+            Entry::InFlight { started, .. } => *started,
+            Entry::Done { stored, .. } => *stored,
+        }
+    }
+
+    fn body(&self) -> &BodyHash {
+        match self {
+            Entry::InFlight { body, .. } | Entry::Done { body, .. } => body,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn replay(&self) -> Response {
+        let mut response = Response::new(boxed(Full::new(self.body.clone())));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert(
+            IDEMPOTENT_REPLAYED.clone(),
+            HeaderValue::from_static("true"),
+        );
+        response
+    }
+}
+
+/// What a request's key was last used for.
+enum Lookup {
+    /// The key is new, or its response has expired: go ahead, and `store` the result.
+    Proceed,
+    /// An earlier request with this key is still being handled.
+    InFlight,
+    Replay(StoredResponse),
+    /// The key was first sent with a different body.
+    Mismatch,
+}
+
+/// Responses to mutations, remembered by `Idempotency-Key` for `ttl`.
+///
+/// At most `max_entries` keys are remembered: beyond that, the oldest are
+/// forgotten early.
+pub struct IdempotencyCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: DashMap<CacheKey, Entry>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        IdempotencyCache::new(DEFAULT_TTL)
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> IdempotencyCache {
+        IdempotencyCache {
+            ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            entries: DashMap::new(),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Remember at most `max` keys, forgetting the oldest beyond that.
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = max.max(1);
+        self
+    }
+
+    /// Claim `key` for a request with `body` starting `now`, unless it is
+    /// already claimed.
+    fn begin(&self, key: CacheKey, body: BodyHash, now: Instant) -> Lookup {
+        self.sweep(now);
+        if !self.entries.contains_key(&key) {
+            self.make_room(now);
+        }
+        let claim = Entry::InFlight { started: now, body };
+        match self.entries.entry(key) {
+            MapEntry::Occupied(mut entry) => {
+                if now.duration_since(entry.get().started()) >= self.ttl {
+                    entry.insert(claim);
+                    return Lookup::Proceed;
+                }
+                if *entry.get().body() != body {
+                    return Lookup::Mismatch;
+                }
+                match entry.get() {
+                    Entry::InFlight { .. } => Lookup::InFlight,
+                    Entry::Done { response, .. } => Lookup::Replay(response.clone()),
+                }
+            }
+            MapEntry::Vacant(entry) => {
+                entry.insert(claim);
+                Lookup::Proceed
+            }
+        }
+    }
+
+    /// Remember the response to the request with `body` which claimed `key`.
+    fn store(&self, key: CacheKey, body: BodyHash, response: StoredResponse, now: Instant) {
+        self.entries.insert(
+            key,
+            Entry::Done {
+                stored: now,
+                body,
+                response,
+            },
+        );
+    }
+
+    /// Release `key` without a response, so the request can be retried.
+    fn abandon(&self, key: &CacheKey) {
+        self.entries
+            .remove_if(key, |_, entry| matches!(entry, Entry::InFlight { .. }));
+    }
+
+    fn sweep(&self, now: Instant) {
+        let mut last_sweep = self.last_sweep.lock().expect("sweep lock poisoned");
+        if now.duration_since(*last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        *last_sweep = now;
+        self.forget_expired(now);
+    }
+
+    fn forget_expired(&self, now: Instant) {
+        self.entries
+            .retain(|_, entry| now.duration_since(entry.started()) < self.ttl);
+    }
+
+    /// Forget expired keys, then the oldest, until there is room for another.
+    fn make_room(&self, now: Instant) {
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+        self.forget_expired(now);
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+        // forget a tenth beyond what is needed, so this is not done on every request
+        let mut ages: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.value(), Entry::Done { .. }))
+            .map(|entry| (entry.value().started(), entry.key().clone()))
+            .collect();
+        ages.sort_by_key(|(started, _)| *started);
+        let excess = self.entries.len() + 1 - self.max_entries + self.max_entries / 10;
+        for (_, key) in ages.into_iter().take(excess) {
+            self.entries.remove(&key);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Applies each `POST` sent with an `Idempotency-Key` header at most once:
+/// the response is remembered, and requests repeating the key are answered
+/// with it instead of being handled again. Server errors are not
+/// remembered, since the mutation may not have happened.
+///
+/// Keys belong to the client key the rate limiter found, so the layer must
+/// sit inside `RateLimitLayer`. A key repeated with a different body is
+/// refused with `422 Unprocessable Entity`.
+///
+/// Responses are remembered by this backend alone, so behind the balancer
+/// retries should stick to one backend, as the consistent-hash strategy does.
+#[derive(Clone)]
+pub struct IdempotencyLayer {
+    cache: Arc<IdempotencyCache>,
+}
+
+impl IdempotencyLayer {
+    pub fn new(cache: Arc<IdempotencyCache>) -> IdempotencyLayer {
+        IdempotencyLayer { cache }
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = Idempotency<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Idempotency {
+            inner,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Idempotency<S> {
+    inner: S,
+    cache: Arc<IdempotencyCache>,
+}
+
+impl<S> Service<Request<Body>> for Idempotency<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if request.method() != Method::POST {
+            return Box::pin(self.inner.call(request));
+        }
+        let key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+            Some(key) => key,
+            None => return Box::pin(self.inner.call(request)),
+        };
+        let key = match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_owned(),
+            _ => {
+                let response = error_response(
+                    StatusCode::BAD_REQUEST,
+                    ErrorKind::InvalidRequest,
+                    format!(
+                        "{} must be 1 to {} visible ASCII characters",
+                        IDEMPOTENCY_KEY_HEADER, MAX_KEY_LENGTH
+                    ),
+                );
+                return Box::pin(async move { Ok(response) });
+            }
+        };
+
+        let client = request
+            .extensions()
+            .get::<ClientKey>()
+            .map(|ClientKey(client)| client.clone())
+            .unwrap_or_default();
+        let key = (
+            request.method().clone(),
+            request.uri().path().to_owned(),
+            client,
+            key,
+        );
+        let cache = self.cache.clone();
+        // the body is read before the inner service is called, so call the
+        // clone which is not yet ready and keep the one which is
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = match read_body(body).await {
+                Ok(body) => body,
+                Err(status) => {
+                    let message = if status == StatusCode::PAYLOAD_TOO_LARGE {
+                        format!(
+                            "Requests with an {} must be at most {} bytes",
+                            IDEMPOTENCY_KEY_HEADER, MAX_BODY_LENGTH
+                        )
+                    } else {
+                        "Failed to read the request body".into()
+                    };
+                    return Ok(error_response(status, ErrorKind::InvalidRequest, message));
+                }
+            };
+            let hash: BodyHash = Sha256::digest(&body).into();
+
+            match cache.begin(key.clone(), hash, Instant::now()) {
+                Lookup::Proceed => (),
+                Lookup::InFlight => {
+                    return Ok(error_response(
+                        StatusCode::CONFLICT,
+                        ErrorKind::Conflict,
+                        "A request with this Idempotency-Key is still in progress".into(),
+                    ));
+                }
+                Lookup::Replay(stored) => {
+                    log::debug!("replaying response to {:?}", key);
+                    return Ok(stored.replay());
+                }
+                Lookup::Mismatch => {
+                    return Ok(error_response(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        ErrorKind::InvalidRequest,
+                        "This Idempotency-Key was used with a different request body".into(),
+                    ));
+                }
+            }
+
+            // dropping the claim early releases the key, including when the
+            // client goes away before we finish
+            let claim = Claim {
+                cache,
+                key: Some(key),
+                body: hash,
+            };
+            let response = inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await?;
+            if response.status().is_server_error() {
+                return Ok(response);
+            }
+
+            let (parts, body) = response.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(err) => {
+                    log::error!("failed to buffer response: {}", err);
+                    let mut response = Response::new(boxed(Full::new(Bytes::new())));
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    return Ok(response);
+                }
+            };
+            claim.store(StoredResponse {
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+            });
+            Ok(Response::from_parts(parts, boxed(Full::new(body))))
+        })
+    }
+}
+
+/// The whole of `body`, unless it is too long to buffer or cannot be read.
+async fn read_body(mut body: Body) -> Result<Bytes, StatusCode> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if buffer.len() + chunk.len() > MAX_BODY_LENGTH {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer.into())
+}
+
+/// A key claimed by a request in flight, released unless a response is stored.
+struct Claim {
+    cache: Arc<IdempotencyCache>,
+    key: Option<CacheKey>,
+    body: BodyHash,
+}
+
+impl Claim {
+    fn store(mut self, response: StoredResponse) {
+        if let Some(key) = self.key.take() {
+            self.cache.store(key, self.body, response, Instant::now());
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.abandon(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: BodyHash = [0; 32];
+
+    fn key(key: &str) -> CacheKey {
+        (
+            Method::POST,
+            "/api/count".into(),
+            "alice".into(),
+            key.into(),
+        )
+    }
+
+    fn ok_response() -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(br#"{"count":1}"#),
+        }
+    }
+
+    #[test]
+    fn stored_responses_are_replayed_until_they_expire() {
+        let cache = IdempotencyCache::new(Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(matches!(
+            cache.begin(key("a"), BODY, start),
+            Lookup::Proceed
+        ));
+        assert!(matches!(
+            cache.begin(key("a"), BODY, start),
+            Lookup::InFlight
+        ));
+
+        cache.store(key("a"), BODY, ok_response(), start);
+        match cache.begin(key("a"), BODY, start + Duration::from_secs(9)) {
+            Lookup::Replay(stored) => assert_eq!(stored.body, ok_response().body),
+            _ => panic!("expected a replay"),
+        }
+        assert!(matches!(
+            cache.begin(key("a"), BODY, start + Duration::from_secs(10)),
+            Lookup::Proceed
+        ));
+    }
+
+    #[test]
+    fn abandoned_keys_can_be_retried() {
+        let cache = IdempotencyCache::default();
+        let now = Instant::now();
+        assert!(matches!(cache.begin(key("a"), BODY, now), Lookup::Proceed));
+        cache.abandon(&key("a"));
+        assert!(matches!(cache.begin(key("a"), BODY, now), Lookup::Proceed));
+    }
+
+    #[test]
+    fn dropped_claims_release_their_key() {
+        let cache = Arc::new(IdempotencyCache::default());
+        let now = Instant::now();
+        cache.begin(key("a"), BODY, now);
+        drop(Claim {
+            cache: cache.clone(),
+            key: Some(key("a")),
+            body: BODY,
+        });
+        assert!(matches!(cache.begin(key("a"), BODY, now), Lookup::Proceed));
+    }
+
+    #[test]
+    fn keys_are_scoped_to_their_route() {
+        let cache = IdempotencyCache::default();
+        let now = Instant::now();
+        cache.begin(key("a"), BODY, now);
+        let other_route = (
+            Method::POST,
+            "/api/counters/apples".into(),
+            "alice".into(),
+            "a".into(),
+        );
+        assert!(matches!(
+            cache.begin(other_route, BODY, now),
+            Lookup::Proceed
+        ));
+        let other_client = (Method::POST, "/api/count".into(), "bob".into(), "a".into());
+        assert!(matches!(
+            cache.begin(other_client, BODY, now),
+            Lookup::Proceed
+        ));
+    }
+
+    #[test]
+    fn keys_reused_with_another_body_are_refused() {
+        let cache = IdempotencyCache::default();
+        let now = Instant::now();
+        cache.begin(key("a"), BODY, now);
+        cache.store(key("a"), BODY, ok_response(), now);
+        assert!(matches!(
+            cache.begin(key("a"), [1; 32], now),
+            Lookup::Mismatch
+        ));
+        assert!(matches!(
+            cache.begin(key("a"), BODY, now),
+            Lookup::Replay(_)
+        ));
+    }
+
+    #[test]
+    fn the_oldest_keys_are_forgotten_beyond_the_maximum() {
+        let cache = IdempotencyCache::default().max_entries(10);
+        let start = Instant::now();
+        for i in 0..10 {
+            let at = start + Duration::from_millis(i);
+            let name = format!("key-{}", i);
+            cache.begin(key(&name), BODY, at);
+            cache.store(key(&name), BODY, ok_response(), at);
+        }
+        cache.begin(key("newcomer"), BODY, start + Duration::from_millis(10));
+        assert!(cache.len() <= 10);
+        assert!(matches!(
+            cache.begin(key("key-9"), BODY, start + Duration::from_millis(11)),
+            Lookup::Replay(_)
+        ));
+        assert!(matches!(
+            cache.begin(key("key-0"), BODY, start + Duration::from_millis(11)),
+            Lookup::Proceed
+        ));
+    }
+
+    #[test]
+    fn sweeping_drops_expired_entries() {
+        let cache = IdempotencyCache::new(Duration::from_secs(1));
+        let start = Instant::now();
+        cache.begin(key("a"), BODY, start);
+        cache.begin(key("b"), BODY, start + SWEEP_INTERVAL);
+        assert_eq!(cache.len(), 1);
+    }
+}
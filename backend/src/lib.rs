@@ -1,4 +1,18 @@
+pub mod cluster;
+pub mod config;
+pub mod counter;
+pub mod grpc;
+pub mod history;
+pub mod idempotency;
+pub mod limiter;
+pub mod logging;
+pub mod metrics;
+pub mod reload;
+pub mod resp;
 pub mod startup;
 pub mod routes;
+pub mod shutdown;
 pub mod state;
+pub mod stats;
+pub mod store;
 
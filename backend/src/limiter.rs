@@ -0,0 +1,355 @@
+pub mod fixed_window;
+pub mod gcra;
+pub mod key;
+pub mod keyed;
+pub mod layer;
+pub mod leaky_bucket;
+pub mod sliding_window_counter;
+pub mod sliding_window_log;
+pub mod token_bucket;
+
+use axum::http::Method;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+pub use client::{Policy, Quota};
+pub use fixed_window::FixedWindow;
+pub use gcra::Gcra;
+pub use key::KeyExtractor;
+pub use keyed::KeyedLimiters;
+pub use layer::RateLimitLayer;
+pub use leaky_bucket::LeakyBucket;
+pub use sliding_window_counter::SlidingWindowCounter;
+pub use sliding_window_log::SlidingWindowLog;
+pub use token_bucket::TokenBucket;
+
+/// The outcome of asking a limiter to admit one request, along with the
+/// state of the caller's quota afterwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// How many more requests would be admitted right now.
+    pub remaining: u32,
+    /// How long until the whole quota is available again.
+    pub reset: Duration,
+    /// How long a denied request should wait before retrying; zero when allowed.
+    pub retry_after: Duration,
+}
+
+impl Decision {
+    pub fn allowed(remaining: u32, reset: Duration) -> Decision {
+        Decision {
+            allowed: true,
+            remaining,
+            reset,
+            retry_after: Duration::ZERO,
+        }
+    }
+
+    pub fn denied(retry_after: Duration, reset: Duration) -> Decision {
+        Decision {
+            allowed: false,
+            remaining: 0,
+            reset: reset.max(retry_after),
+            retry_after,
+        }
+    }
+
+    pub fn is_allowed(&self) -> bool {
+        self.allowed
+    }
+}
+
+/// A rate-limiting algorithm. Implementations keep their own state behind
+/// interior mutability so a single limiter can be shared between requests.
+pub trait RateLimiter: Send + Sync {
+    /// Decide whether one request arriving at `now` may proceed, recording it if so.
+    fn check(&self, now: Instant) -> Decision;
+}
+
+/// Create a fresh limiter implementing `policy`.
+pub fn build(policy: &Policy) -> Box<dyn RateLimiter> {
+    match *policy {
+        Policy::TokenBucket {
+            capacity,
+            refill_per_second,
+        } => Box::new(TokenBucket::new(capacity, refill_per_second)),
+        Policy::FixedWindow {
+            limit,
+            window_seconds,
+        } => Box::new(FixedWindow::new(limit, seconds(window_seconds))),
+        Policy::SlidingWindowLog {
+            limit,
+            window_seconds,
+        } => Box::new(SlidingWindowLog::new(limit, seconds(window_seconds))),
+        Policy::SlidingWindowCounter {
+            limit,
+            window_seconds,
+        } => Box::new(SlidingWindowCounter::new(limit, seconds(window_seconds))),
+        Policy::LeakyBucket {
+            capacity,
+            leak_per_second,
+        } => Box::new(LeakyBucket::new(capacity, leak_per_second)),
+        Policy::Gcra {
+            limit,
+            period_seconds,
+        } => Box::new(Gcra::new(limit, seconds(period_seconds))),
+    }
+}
+
+// validated policies always fit in a `Duration`, but unvalidated ones should not panic
+fn seconds(seconds: f64) -> Duration {
+    Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX)
+}
+
+/// Rate limits keyed by the route pattern they apply to, e.g. `/api/count/:direction`,
+/// or by a method and pattern, e.g. `POST /api/count`, which takes precedence
+/// over the bare pattern for requests with that method.
+///
+/// Routes without an entry are not limited. Within a route, every client key
+/// produced by `key` is limited separately.
+///
+/// Client addresses, as `key` finds them, can also be allowed past every
+/// limit or denied outright by CIDR range, e.g. `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub key: KeyExtractor,
+    pub routes: HashMap<String, Policy>,
+    /// Ranges which are never limited.
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    /// Ranges which are refused on every route.
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+/// What the access lists of a `RateLimitConfig` say about a client address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Allow,
+    Deny,
+}
+
+/// Whether `route` can key `RateLimitConfig::routes`: a pattern such as
+/// `/api/count`, optionally after a method in capitals, as in `POST /api/count`.
+pub fn is_route(route: &str) -> bool {
+    let path = match route.split_once(' ') {
+        Some((method, path)) => {
+            if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+                return false;
+            }
+            path
+        }
+        None => route,
+    };
+    path.starts_with('/') && !path.contains(' ')
+}
+
+/// Why a request arriving outside the HTTP rate-limiting layer was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    /// The client is on the deny list.
+    Forbidden,
+    /// The client is out of requests, and may try again after `retry_after`.
+    RateLimited { retry_after: Duration },
+}
+
+impl RateLimitConfig {
+    /// Group requests into client keys with `key`.
+    pub fn key(mut self, key: KeyExtractor) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Add or replace the policy for `route`.
+    pub fn route(mut self, route: &str, policy: Policy) -> Self {
+        self.routes.insert(route.to_owned(), policy);
+        self
+    }
+
+    /// Never limit clients in `range`.
+    pub fn allow(mut self, range: IpNet) -> Self {
+        self.allow.push(range);
+        self
+    }
+
+    /// Refuse clients in `range`.
+    pub fn deny(mut self, range: IpNet) -> Self {
+        self.deny.push(range);
+        self
+    }
+
+    /// The entry in `routes` limiting `method` requests to the route `path`,
+    /// if any: the one for that method, or else the one for every method.
+    pub fn route_for(&self, method: &Method, path: &str) -> Option<String> {
+        let with_method = format!("{} {}", method, path);
+        if self.routes.contains_key(&with_method) {
+            Some(with_method)
+        } else if self.routes.contains_key(path) {
+            Some(path.to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Whether `ip` is on the allow or deny list, or `None` if on neither.
+    ///
+    /// The most specific range containing `ip` decides, so a single address
+    /// can be allowed out of a denied network, and denial wins a tie.
+    pub fn access(&self, ip: IpAddr) -> Option<Access> {
+        // IPv4 clients of a dual-stack listener arrive as `::ffff:a.b.c.d`
+        let ip = ip.to_canonical();
+        let longest = |ranges: &[IpNet]| {
+            ranges
+                .iter()
+                .filter(|range| range.contains(&ip))
+                .map(IpNet::prefix_len)
+                .max()
+        };
+        match (longest(&self.allow), longest(&self.deny)) {
+            (Some(allow), Some(deny)) if allow > deny => Some(Access::Allow),
+            (_, Some(_)) => Some(Access::Deny),
+            (Some(_), None) => Some(Access::Allow),
+            (None, None) => None,
+        }
+    }
+
+    /// The limits applied when the server is started from the command line.
+    ///
+    /// `get_count` is polled, so it gets a generous smoothed budget, while
+    /// every route changing a counter is held to a steady rate.
+    pub fn counter_defaults() -> Self {
+        RateLimitConfig::default()
+            .key(KeyExtractor::PeerIp)
+            .route(
+                "/api/count",
+                Policy::sliding_window_counter(600, Duration::from_secs(60)),
+            )
+            .route("POST /api/count", Policy::gcra(10, Duration::from_secs(1)))
+            .route(
+                "/api/count/history",
+                Policy::sliding_window_counter(60, Duration::from_secs(60)),
+            )
+            .route(
+                "/api/count/stats",
+                Policy::sliding_window_counter(60, Duration::from_secs(60)),
+            )
+            .route(
+                "/api/count/:direction",
+                Policy::gcra(10, Duration::from_secs(1)),
+            )
+            .route(
+                "POST /api/counters/:name",
+                Policy::gcra(10, Duration::from_secs(1)),
+            )
+            .route(
+                "PUT /api/counters/:name",
+                Policy::gcra(10, Duration::from_secs(1)),
+            )
+            .route(
+                "DELETE /api/counters/:name",
+                Policy::gcra(10, Duration::from_secs(1)),
+            )
+            .route(
+                "/api/counters/:name/:direction",
+                Policy::gcra(10, Duration::from_secs(1)),
+            )
+            .route("/ws/count", Policy::token_bucket(10, 1.0))
+            .route("/sse/count", Policy::token_bucket(10, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_routes_take_precedence() {
+        let config = RateLimitConfig::default()
+            .route("/api/count", Policy::token_bucket(1, 1.0))
+            .route("POST /api/count", Policy::gcra(1, Duration::from_secs(1)));
+        assert_eq!(
+            config.route_for(&Method::POST, "/api/count").as_deref(),
+            Some("POST /api/count")
+        );
+        assert_eq!(
+            config.route_for(&Method::GET, "/api/count").as_deref(),
+            Some("/api/count")
+        );
+        assert_eq!(config.route_for(&Method::GET, "/health_check"), None);
+
+        assert!(is_route("/api/count"));
+        assert!(is_route("DELETE /api/counters/:name"));
+        assert!(!is_route("api/count"));
+        assert!(!is_route("post /api/count"));
+        assert!(!is_route("POST  /api/count"));
+    }
+
+    #[test]
+    fn the_most_specific_range_decides_access() {
+        let config = RateLimitConfig::default()
+            .deny("10.0.0.0/8".parse().unwrap())
+            .allow("10.1.2.3/32".parse().unwrap())
+            .allow("192.168.0.0/16".parse().unwrap())
+            .deny("192.168.0.0/16".parse().unwrap())
+            .deny("2001:db8::/32".parse().unwrap());
+        let access = |ip: &str| config.access(ip.parse().unwrap());
+        assert_eq!(access("10.9.9.9"), Some(Access::Deny));
+        assert_eq!(access("10.1.2.3"), Some(Access::Allow));
+        assert_eq!(access("::ffff:10.1.2.3"), Some(Access::Allow));
+        assert_eq!(access("192.168.1.1"), Some(Access::Deny));
+        assert_eq!(access("2001:db8::1"), Some(Access::Deny));
+        assert_eq!(access("127.0.0.1"), None);
+        assert_eq!(access("::1"), None);
+    }
+
+    #[test]
+    fn policy_deserialises_from_tagged_config() {
+        let json = r#"{
+            "key": { "kind": "header", "name": "x-client-id" },
+            "routes": {
+                "/api/count": { "algorithm": "sliding_window_log", "limit": 5, "window_seconds": 2.5 },
+                "/ws/count": { "algorithm": "gcra", "limit": 3, "period_seconds": 1.0 }
+            }
+        }"#;
+        let config: RateLimitConfig = serde_json::from_str(json).unwrap();
+        let expected = RateLimitConfig::default()
+            .key(KeyExtractor::Header {
+                name: "x-client-id".into(),
+            })
+            .route(
+                "/api/count",
+                Policy::sliding_window_log(5, Duration::from_millis(2500)),
+            )
+            .route("/ws/count", Policy::gcra(3, Duration::from_secs(1)));
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn every_policy_builds_a_limiter_that_admits_up_to_its_limit() {
+        let policies = [
+            Policy::token_bucket(3, 0.001),
+            Policy::fixed_window(3, Duration::from_secs(60)),
+            Policy::sliding_window_log(3, Duration::from_secs(60)),
+            Policy::sliding_window_counter(3, Duration::from_secs(60)),
+            Policy::leaky_bucket(3, 0.001),
+            Policy::gcra(3, Duration::from_secs(60)),
+        ];
+        for policy in policies {
+            let limiter = build(&policy);
+            let now = Instant::now();
+            for remaining in (0..3).rev() {
+                let decision = limiter.check(now);
+                assert!(decision.is_allowed(), "{:?}", policy);
+                assert_eq!(decision.remaining, remaining, "{:?}", policy);
+            }
+            let decision = limiter.check(now);
+            assert!(!decision.is_allowed(), "{:?}", policy);
+            assert!(decision.retry_after > Duration::ZERO, "{:?}", policy);
+        }
+    }
+}
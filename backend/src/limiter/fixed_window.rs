@@ -0,0 +1,117 @@
+use crate::limiter::{Decision, RateLimiter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Admits `limit` requests per `window`, resetting the count at each window
+/// boundary. Cheap, but allows bursts of up to twice the limit across a boundary.
+pub struct FixedWindow {
+    limit: u32,
+    window: Duration,
+    state: Mutex<WindowState>,
+}
+
+struct WindowState {
+    started: Option<Instant>,
+    count: u32,
+}
+
+impl FixedWindow {
+    pub fn new(limit: u32, window: Duration) -> FixedWindow {
+        FixedWindow {
+            limit,
+            window,
+            state: Mutex::new(WindowState {
+                started: None,
+                count: 0,
+            }),
+        }
+    }
+}
+
+impl RateLimiter for FixedWindow {
+    fn check(&self, now: Instant) -> Decision {
+        let mut state = self.state.lock().expect("fixed window lock poisoned");
+
+        let started = match state.started {
+            Some(started) if now.saturating_duration_since(started) < self.window => started,
+            Some(started) => {
+                // skip forward whole windows so boundaries stay aligned
+                let elapsed = now.saturating_duration_since(started).as_nanos();
+                state.count = 0;
+                match u32::try_from(elapsed / self.window.as_nanos()) {
+                    Ok(windows) => started + self.window * windows,
+                    // too many windows to count, so start afresh
+                    Err(_) => now,
+                }
+            }
+            None => now,
+        };
+        state.started = Some(started);
+
+        // a window too long to end on the clock never resets
+        let reset = started
+            .checked_add(self.window)
+            .map_or(self.window, |end| end.saturating_duration_since(now));
+        if state.count < self.limit {
+            state.count += 1;
+            Decision::allowed(self.limit - state.count, reset)
+        } else {
+            Decision::denied(reset, reset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_resets_at_boundary() {
+        let start = Instant::now();
+        let limiter = FixedWindow::new(2, Duration::from_secs(10));
+        assert!(limiter.check(start).is_allowed());
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(1)),
+            Decision::allowed(0, Duration::from_secs(9))
+        );
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(4)),
+            Decision::denied(Duration::from_secs(6), Duration::from_secs(6))
+        );
+        assert!(limiter.check(start + Duration::from_secs(10)).is_allowed());
+    }
+
+    #[test]
+    fn boundaries_stay_aligned_after_idle_windows() {
+        let start = Instant::now();
+        let limiter = FixedWindow::new(1, Duration::from_secs(10));
+        assert!(limiter.check(start).is_allowed());
+        assert!(limiter.check(start + Duration::from_secs(35)).is_allowed());
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(36)).retry_after,
+            Duration::from_secs(4)
+        );
+    }
+
+    #[test]
+    fn windows_too_long_to_end_do_not_panic() {
+        let start = Instant::now();
+        let window = Duration::from_secs_f64(1e19);
+        let limiter = FixedWindow::new(1, window);
+        assert_eq!(limiter.check(start), Decision::allowed(0, window));
+        assert!(!limiter.check(start).is_allowed());
+    }
+
+    #[test]
+    fn windows_restart_after_too_many_to_count() {
+        let start = Instant::now();
+        let limiter = FixedWindow::new(1, Duration::from_nanos(1));
+        assert!(limiter.check(start).is_allowed());
+        let later = start + Duration::from_secs(5);
+        assert_eq!(
+            limiter.check(later),
+            Decision::allowed(0, Duration::from_nanos(1))
+        );
+        assert!(!limiter.check(later).is_allowed());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::limiter::{Decision, RateLimiter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The Generic Cell Rate Algorithm: admits `limit` requests per `period`,
+/// with bursts of up to `limit`, while storing only a single timestamp
+/// (the theoretical arrival time of the next conforming request).
+pub struct Gcra {
+    emission_interval: Duration,
+    burst_tolerance: Duration,
+    state: Mutex<GcraState>,
+}
+
+struct GcraState {
+    origin: Option<Instant>,
+    // theoretical arrival time, as an offset from `origin`
+    tat: Duration,
+}
+
+impl Gcra {
+    pub fn new(limit: u32, period: Duration) -> Gcra {
+        let emission_interval = period / limit.max(1);
+        Gcra {
+            emission_interval,
+            burst_tolerance: emission_interval * limit.saturating_sub(1),
+            state: Mutex::new(GcraState {
+                origin: None,
+                tat: Duration::ZERO,
+            }),
+        }
+    }
+}
+
+impl RateLimiter for Gcra {
+    fn check(&self, now: Instant) -> Decision {
+        let mut state = self.state.lock().expect("gcra lock poisoned");
+
+        let origin = *state.origin.get_or_insert(now);
+        let now = now.saturating_duration_since(origin);
+
+        let tat = state.tat.max(now);
+        let allow_at = tat.saturating_sub(self.burst_tolerance);
+        if now < allow_at {
+            return Decision::denied(allow_at - now, tat - now);
+        }
+
+        state.tat = tat.saturating_add(self.emission_interval);
+        let reset = state.tat - now;
+        // each emission interval of unused tolerance is one more request
+        let slack = self
+            .burst_tolerance
+            .saturating_add(self.emission_interval)
+            .saturating_sub(reset);
+        let remaining = slack.as_nanos() / self.emission_interval.as_nanos();
+        Decision::allowed(remaining as u32, reset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_steady_rate() {
+        let start = Instant::now();
+        let limiter = Gcra::new(2, Duration::from_secs(2));
+        assert_eq!(
+            limiter.check(start),
+            Decision::allowed(1, Duration::from_secs(1))
+        );
+        assert_eq!(
+            limiter.check(start),
+            Decision::allowed(0, Duration::from_secs(2))
+        );
+        assert_eq!(
+            limiter.check(start),
+            Decision::denied(Duration::from_secs(1), Duration::from_secs(2))
+        );
+        assert!(limiter.check(start + Duration::from_secs(1)).is_allowed());
+        assert!(!limiter
+            .check(start + Duration::from_millis(1500))
+            .is_allowed());
+        assert!(limiter.check(start + Duration::from_secs(2)).is_allowed());
+    }
+}
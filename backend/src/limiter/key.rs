@@ -0,0 +1,341 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, Request},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+const FORWARDED: &str = "forwarded";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
+
+/// How requests are grouped into rate-limiting keys. Every key gets its own
+/// limiter, so two clients with different keys never share a budget.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum KeyExtractor {
+    /// One limiter shared by every client.
+    #[default]
+    Global,
+    /// Key on the IP address of the connected peer.
+    PeerIp,
+    /// Key on the client address reported by `Forwarded` or `X-Forwarded-For`,
+    /// trusting those headers only when they were added by one of `trusted_proxies`.
+    ForwardedFor { trusted_proxies: Vec<IpAddr> },
+    /// Key on the value of an arbitrary header; requests without it share one limiter.
+    Header { name: String },
+    /// Key on an API key, rejecting requests that do not present one of `keys`.
+    ApiKey {
+        #[serde(default = "default_api_key_header")]
+        header: String,
+        keys: HashSet<String>,
+    },
+}
+
+fn default_api_key_header() -> String {
+    DEFAULT_API_KEY_HEADER.to_owned()
+}
+
+/// The key a request was rate-limited under, left in its extensions by the
+/// rate-limiting layer for handlers that want to know who is calling.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientKey(pub String);
+
+/// How a client appears in records anyone can read, such as counter history:
+/// by its key, unless that is a secret API key, when by a digest of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientId(pub String);
+
+/// The address of the client behind a request, when known, left alongside
+/// its `ClientKey` so that later checks can consult the access lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyError {
+    MissingApiKey,
+    UnknownApiKey,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::MissingApiKey => write!(f, "Missing API Key"),
+            KeyError::UnknownApiKey => write!(f, "Unknown API Key"),
+        }
+    }
+}
+
+impl KeyExtractor {
+    pub fn api_key<I: IntoIterator<Item = S>, S: Into<String>>(keys: I) -> KeyExtractor {
+        KeyExtractor::ApiKey {
+            header: default_api_key_header(),
+            keys: keys.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Work out which key `request` should be limited under.
+    pub fn extract<B>(&self, request: &Request<B>) -> Result<String, KeyError> {
+        let headers = request.headers();
+
+        match self {
+            KeyExtractor::Global => Ok(String::new()),
+            KeyExtractor::PeerIp | KeyExtractor::ForwardedFor { .. } => {
+                Ok(ip_key(self.client_ip(request)))
+            }
+            KeyExtractor::Header { name } => Ok(headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_owned()),
+            KeyExtractor::ApiKey { header, keys } => {
+                let key = headers
+                    .get(header)
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or(KeyError::MissingApiKey)?;
+                if keys.contains(key) {
+                    Ok(key.to_owned())
+                } else {
+                    Err(KeyError::UnknownApiKey)
+                }
+            }
+        }
+    }
+
+    /// The `ClientId` of requests limited under `key`.
+    pub fn client_id(&self, key: &str) -> ClientId {
+        match self {
+            KeyExtractor::ApiKey { .. } => ClientId(digest(key)),
+            _ => ClientId(key.to_owned()),
+        }
+    }
+
+    /// The key a client is named by, in paths that take either its
+    /// `ClientId` or the key itself.
+    pub fn named(&self, name: &str) -> String {
+        match self {
+            KeyExtractor::ApiKey { keys, .. } => keys
+                .iter()
+                .find(|key| digest(key) == name)
+                .cloned()
+                .unwrap_or_else(|| name.to_owned()),
+            _ => name.to_owned(),
+        }
+    }
+
+    /// The address of the client behind `request`: the one reported by
+    /// trusted proxies when keying on `ForwardedFor`, or else the peer's.
+    pub fn client_ip<B>(&self, request: &Request<B>) -> Option<IpAddr> {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        match self {
+            KeyExtractor::ForwardedFor { trusted_proxies } => {
+                forwarded_client(peer, request.headers(), trusted_proxies)
+            }
+            _ => peer,
+        }
+    }
+}
+
+// enough of a hash to tell keys apart, without giving them away
+fn digest(key: &str) -> String {
+    let hash = Sha256::digest(key.as_bytes());
+    let hex: String = hash[..6]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("key:{}", hex)
+}
+
+fn ip_key(ip: Option<IpAddr>) -> String {
+    ip.map(|ip| ip.to_string()).unwrap_or_default()
+}
+
+/// Find the original client behind a chain of trusted proxies.
+///
+/// Addresses are read right to left, since each proxy appends the address it
+/// received the request from; the first untrusted address is the client.
+fn forwarded_client(
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let peer = peer?;
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let mut chain = forwarded_for(headers);
+    if chain.is_empty() {
+        chain = x_forwarded_for(headers);
+    }
+
+    let mut client = peer;
+    for hop in chain.into_iter().rev() {
+        client = hop;
+        if !trusted_proxies.contains(&hop) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// Parse every `for=` parameter of the RFC 7239 `Forwarded` headers, in order.
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                if name.eq_ignore_ascii_case("for") {
+                    parse_node(value)
+                } else {
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_node)
+        .collect()
+}
+
+/// Parse a node such as `192.0.2.1`, `"[2001:db8::1]:4711"` or `203.0.113.9:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|rest| rest.split(']').next())
+        .and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn request_from(peer: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let mut request = builder.body(()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        request
+    }
+
+    fn trusting(proxies: &[&str]) -> KeyExtractor {
+        KeyExtractor::ForwardedFor {
+            trusted_proxies: proxies.iter().map(|p| p.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn peer_ip_ignores_port() {
+        let request = request_from("10.0.0.1:5555", &[]);
+        assert_eq!(
+            KeyExtractor::PeerIp.extract(&request),
+            Ok("10.0.0.1".into())
+        );
+    }
+
+    #[test]
+    fn forwarded_headers_from_untrusted_peer_are_ignored() {
+        let request = request_from("10.0.0.1:5555", &[("x-forwarded-for", "1.2.3.4")]);
+        let extractor = trusting(&["10.0.0.2"]);
+        assert_eq!(extractor.extract(&request), Ok("10.0.0.1".into()));
+    }
+
+    #[test]
+    fn x_forwarded_for_skips_trusted_hops() {
+        let request = request_from(
+            "10.0.0.1:5555",
+            &[("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.2")],
+        );
+        let extractor = trusting(&["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(extractor.extract(&request), Ok("1.2.3.4".into()));
+    }
+
+    #[test]
+    fn forwarded_is_preferred_over_x_forwarded_for() {
+        let request = request_from(
+            "10.0.0.1:5555",
+            &[
+                (
+                    "forwarded",
+                    r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.2"#,
+                ),
+                ("x-forwarded-for", "1.2.3.4"),
+            ],
+        );
+        let extractor = trusting(&["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(extractor.extract(&request), Ok("2001:db8::1".into()));
+    }
+
+    #[test]
+    fn parse_node_accepts_ports() {
+        assert_eq!(
+            parse_node("203.0.113.9:80"),
+            Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9)))
+        );
+        assert_eq!(parse_node("unknown"), None);
+    }
+
+    #[test]
+    fn api_key_must_be_known() {
+        let extractor = KeyExtractor::api_key(["secret"]);
+        let missing = request_from("10.0.0.1:5555", &[]);
+        let unknown = request_from("10.0.0.1:5555", &[("x-api-key", "guess")]);
+        let known = request_from("10.0.0.1:5555", &[("x-api-key", "secret")]);
+        assert_eq!(extractor.extract(&missing), Err(KeyError::MissingApiKey));
+        assert_eq!(extractor.extract(&unknown), Err(KeyError::UnknownApiKey));
+        assert_eq!(extractor.extract(&known), Ok("secret".into()));
+    }
+
+    #[test]
+    fn api_keys_are_identified_by_digest() {
+        let extractor = KeyExtractor::api_key(["secret"]);
+        let ClientId(id) = extractor.client_id("secret");
+        assert!(id.starts_with("key:"));
+        assert!(!id.contains("secret"));
+        assert_eq!(extractor.client_id("secret"), ClientId(id));
+        assert_ne!(extractor.client_id("other"), extractor.client_id("secret"));
+
+        assert_eq!(
+            KeyExtractor::PeerIp.client_id("10.0.0.1"),
+            ClientId("10.0.0.1".into())
+        );
+    }
+
+    #[test]
+    fn api_keys_are_named_by_digest_or_themselves() {
+        let extractor = KeyExtractor::api_key(["secret"]);
+        let ClientId(id) = extractor.client_id("secret");
+        assert_eq!(extractor.named(&id), "secret");
+        assert_eq!(extractor.named("secret"), "secret");
+        assert_eq!(extractor.named("key:000000000000"), "key:000000000000");
+        assert_eq!(KeyExtractor::PeerIp.named("10.0.0.1"), "10.0.0.1");
+    }
+}
@@ -0,0 +1,732 @@
+use crate::limiter::{
+    build, layer::whole_seconds, Decision, Policy, Quota, RateLimitConfig, RateLimiter,
+};
+use client::{AccessList, KeyOverride, KeyState, KeyUsage, OverrideKind, RouteState};
+use dashmap::DashMap;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// How many route and key pairs may hold a limiter by default.
+pub const DEFAULT_MAX_LIMITERS: usize = 100_000;
+/// How often limiters with nothing left to remember are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Requests admitted under one route and key, as reported between peers.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Consumption {
+    pub route: String,
+    pub key: String,
+    pub count: u32,
+}
+
+/// One limiter per route and key, created lazily from the route's policy
+/// the first time a key is seen.
+///
+/// The configuration can be replaced while requests are being checked. Each
+/// limiter remembers the policy it was built from and is rebuilt when that
+/// policy changes, so routes whose policy is unchanged keep their state.
+///
+/// Keys can also be banned or exempted for a while, and topped up with
+/// requests beyond their policy. These are local to this backend: peers in
+/// a cluster only share what was admitted.
+///
+/// Limiters whose quota has refilled completely are dropped every so often,
+/// since a fresh one would decide the same. Should there still be more than
+/// `max_limiters`, the least recently used are dropped too.
+pub struct KeyedLimiters {
+    config: RwLock<Arc<RateLimitConfig>>,
+    limiters: DashMap<(String, String), Limiter>,
+    max_limiters: usize,
+    last_sweep: Mutex<Instant>,
+    // bans and exemptions by key, with when they lapse
+    overrides: DashMap<String, (OverrideKind, Instant)>,
+    // admitted requests not yet reported to peers, when sharing with a cluster
+    unreported: Option<DashMap<(String, String), u32>>,
+}
+
+struct Limiter {
+    policy: Policy,
+    limiter: Box<dyn RateLimiter>,
+    allowed: u64,
+    denied: u64,
+    // requests to admit once the policy denies them
+    credit: u32,
+    last: Option<(Instant, Decision)>,
+}
+
+impl Limiter {
+    fn new(policy: &Policy) -> Limiter {
+        Limiter {
+            policy: *policy,
+            limiter: build(policy),
+            allowed: 0,
+            denied: 0,
+            credit: 0,
+            last: None,
+        }
+    }
+
+    fn check(&mut self, now: Instant) -> Decision {
+        let mut decision = self.limiter.check(now);
+        if !decision.is_allowed() && self.credit > 0 {
+            self.credit -= 1;
+            decision = Decision::allowed(0, decision.reset);
+        }
+        if decision.is_allowed() {
+            self.allowed += 1;
+        } else {
+            self.denied += 1;
+        }
+        self.last = Some((now, decision));
+        decision
+    }
+
+    /// Whether a fresh limiter would decide the same from `now` on.
+    fn is_idle(&self, now: Instant) -> bool {
+        self.credit == 0
+            && self
+                .last
+                .is_none_or(|(at, last)| now.saturating_duration_since(at) >= last.reset)
+    }
+
+    fn state(&self, route: &str, now: Instant) -> RouteState {
+        let quota = self.policy.quota();
+        // the quota refills completely by the reset the last decision gave
+        let (remaining, reset) = match self.last {
+            Some((at, last)) if now.saturating_duration_since(at) < last.reset => (
+                last.remaining,
+                last.reset - now.saturating_duration_since(at),
+            ),
+            _ => (quota.limit, Duration::ZERO),
+        };
+        RouteState {
+            route: route.to_owned(),
+            policy: self.policy,
+            allowed: self.allowed,
+            denied: self.denied,
+            remaining,
+            reset_seconds: whole_seconds(reset),
+            credit: self.credit,
+        }
+    }
+}
+
+impl Default for KeyedLimiters {
+    fn default() -> Self {
+        KeyedLimiters::new(&RateLimitConfig::default())
+    }
+}
+
+impl KeyedLimiters {
+    pub fn new(config: &RateLimitConfig) -> KeyedLimiters {
+        KeyedLimiters {
+            config: RwLock::new(Arc::new(config.clone())),
+            limiters: DashMap::new(),
+            max_limiters: DEFAULT_MAX_LIMITERS,
+            last_sweep: Mutex::new(Instant::now()),
+            overrides: DashMap::new(),
+            unreported: None,
+        }
+    }
+
+    /// Hold at most `max` limiters, dropping the least recently used beyond that.
+    pub fn max_limiters(mut self, max: usize) -> Self {
+        self.max_limiters = max.max(1);
+        self
+    }
+
+    /// Limiters whose admitted requests are collected for reporting to peers
+    /// with `take_unreported`, so that a cluster enforces one global budget.
+    pub fn shared(config: &RateLimitConfig) -> KeyedLimiters {
+        KeyedLimiters {
+            unreported: Some(DashMap::new()),
+            ..KeyedLimiters::new(config)
+        }
+    }
+
+    /// Check a request to `route` under `key`, returning the route's quota
+    /// alongside the decision, or `None` if the route is not limited.
+    ///
+    /// Banned keys are denied until their ban lapses, and exempt keys are
+    /// admitted without consuming anything.
+    pub fn check(&self, route: &str, key: &str, now: Instant) -> Option<(Quota, Decision)> {
+        let config = self.config();
+        let policy = config.routes.get(route)?;
+        let quota = policy.quota();
+        match self.override_for(key, now) {
+            Some((OverrideKind::Ban, left)) => return Some((quota, Decision::denied(left, left))),
+            Some((OverrideKind::Exempt, _)) => {
+                return Some((quota, Decision::allowed(quota.limit, Duration::ZERO)))
+            }
+            None => {}
+        }
+        let map_key = (route.to_owned(), key.to_owned());
+        let decision = self.limiter(map_key.clone(), policy, now).check(now);
+
+        if let (true, Some(unreported)) = (decision.is_allowed(), &self.unreported) {
+            *unreported.entry(map_key).or_insert(0) += 1;
+        }
+        Some((quota, decision))
+    }
+
+    /// Drain the requests admitted since the last call.
+    pub fn take_unreported(&self) -> Vec<Consumption> {
+        let unreported = match &self.unreported {
+            Some(unreported) => unreported,
+            None => return Vec::new(),
+        };
+
+        let keys: Vec<_> = unreported.iter().map(|entry| entry.key().clone()).collect();
+        keys.into_iter()
+            .filter_map(|map_key| unreported.remove(&map_key))
+            .map(|((route, key), count)| Consumption { route, key, count })
+            .collect()
+    }
+
+    /// Charge requests admitted by a peer against the local limiter for the same key.
+    pub fn record_remote(&self, consumption: &Consumption, now: Instant) {
+        let config = self.config();
+        let policy = match config.routes.get(&consumption.route) {
+            Some(policy) => policy,
+            None => return,
+        };
+        let map_key = (consumption.route.clone(), consumption.key.clone());
+        let mut limiter = self.limiter(map_key, policy, now);
+        for _ in 0..consumption.count {
+            let decision = limiter.limiter.check(now);
+            limiter.last = Some((now, decision));
+            if !decision.is_allowed() {
+                break;
+            }
+        }
+    }
+
+    /// The limiter for `map_key`, built afresh if it does not exist yet or
+    /// was built from a policy since replaced.
+    fn limiter(
+        &self,
+        map_key: (String, String),
+        policy: &Policy,
+        now: Instant,
+    ) -> dashmap::mapref::one::RefMut<'_, (String, String), Limiter> {
+        if !self.limiters.contains_key(&map_key) {
+            self.make_room(now);
+        }
+        let mut limiter = self
+            .limiters
+            .entry(map_key)
+            .or_insert_with(|| Limiter::new(policy));
+        if limiter.policy != *policy {
+            *limiter = Limiter::new(policy);
+        }
+        limiter
+    }
+
+    /// Drop idle limiters and lapsed overrides, at most once per sweep
+    /// interval, and the least recently used limiters beyond the maximum.
+    fn make_room(&self, now: Instant) {
+        let swept = {
+            let mut last_sweep = self.last_sweep.lock().expect("sweep lock poisoned");
+            if now.saturating_duration_since(*last_sweep) >= SWEEP_INTERVAL {
+                *last_sweep = now;
+                true
+            } else {
+                false
+            }
+        };
+        if swept {
+            self.sweep(now);
+        }
+        if self.limiters.len() < self.max_limiters {
+            return;
+        }
+        if !swept {
+            self.sweep(now);
+        }
+        if self.limiters.len() < self.max_limiters {
+            return;
+        }
+
+        // evict a tenth beyond what is needed, so a flood of new keys does
+        // not pay for this on every request
+        let mut used: Vec<_> = self
+            .limiters
+            .iter()
+            .map(|entry| (entry.value().last.map(|(at, _)| at), entry.key().clone()))
+            .collect();
+        used.sort();
+        let excess = self.limiters.len() + 1 - self.max_limiters + self.max_limiters / 10;
+        for (_, map_key) in used.into_iter().take(excess) {
+            self.limiters.remove(&map_key);
+        }
+    }
+
+    /// Drop every limiter a fresh one would stand in for, and lapsed overrides.
+    pub fn sweep(&self, now: Instant) {
+        self.limiters.retain(|_, limiter| !limiter.is_idle(now));
+        self.overrides.retain(|_, (_, until)| *until > now);
+    }
+
+    /// The configuration requests are currently checked against.
+    pub fn config(&self) -> Arc<RateLimitConfig> {
+        self.config
+            .read()
+            .expect("rate limit lock poisoned")
+            .clone()
+    }
+
+    /// Check requests against `config` from now on, returning what changed,
+    /// e.g. `rate_limits.routes./api/count`.
+    ///
+    /// Limiters for routes whose policy is unchanged keep their state, unless
+    /// the way requests are keyed changed, when every limiter starts afresh.
+    pub fn reconfigure(&self, config: &RateLimitConfig) -> Vec<String> {
+        self.reconfigure_with(|current| *current = config.clone())
+    }
+
+    /// Limit `route` with `policy` from now on, returning whether the route
+    /// was not limited before.
+    pub fn set_policy(&self, route: &str, policy: Policy) -> bool {
+        let mut created = false;
+        self.reconfigure_with(|config| {
+            created = config.routes.insert(route.to_owned(), policy).is_none();
+        });
+        created
+    }
+
+    /// Put `range` on `list`, returning whether it was not there already.
+    pub fn add_access(&self, list: AccessList, range: IpNet) -> bool {
+        let mut added = false;
+        self.reconfigure_with(|config| {
+            let ranges = access_list(config, list);
+            if !ranges.contains(&range) {
+                ranges.push(range);
+                added = true;
+            }
+        });
+        added
+    }
+
+    /// Take `range` off `list`, returning whether it was there.
+    pub fn remove_access(&self, list: AccessList, range: IpNet) -> bool {
+        let mut removed = false;
+        self.reconfigure_with(|config| {
+            let ranges = access_list(config, list);
+            let before = ranges.len();
+            ranges.retain(|listed| *listed != range);
+            removed = ranges.len() < before;
+        });
+        removed
+    }
+
+    fn reconfigure_with(&self, change: impl FnOnce(&mut RateLimitConfig)) -> Vec<String> {
+        let (previous, config) = {
+            let mut current = self.config.write().expect("rate limit lock poisoned");
+            let mut config = RateLimitConfig::clone(&current);
+            change(&mut config);
+            let config = Arc::new(config);
+            (std::mem::replace(&mut *current, config.clone()), config)
+        };
+
+        let mut changed = Vec::new();
+        if previous.key != config.key {
+            changed.push("rate_limits.key".to_owned());
+            self.limiters.clear();
+        }
+        if previous.allow != config.allow {
+            changed.push("rate_limits.allow".to_owned());
+        }
+        if previous.deny != config.deny {
+            changed.push("rate_limits.deny".to_owned());
+        }
+        let routes: BTreeSet<&String> =
+            previous.routes.keys().chain(config.routes.keys()).collect();
+        for route in routes {
+            if previous.routes.get(route) != config.routes.get(route) {
+                changed.push(format!("rate_limits.routes.{}", route));
+            }
+        }
+        // free the state of routes no longer limited, or limited differently
+        self.limiters
+            .retain(|(route, _), limiter| config.routes.get(route) == Some(&limiter.policy));
+        changed
+    }
+
+    /// The keys which made the most requests, across every route or just
+    /// `route`, busiest first.
+    pub fn top_keys(&self, route: Option<&str>, limit: usize) -> Vec<KeyUsage> {
+        let mut usage: HashMap<String, KeyUsage> = HashMap::new();
+        for entry in self.limiters.iter() {
+            let ((limited, key), limiter) = entry.pair();
+            if route.is_some_and(|route| route != limited) {
+                continue;
+            }
+            let usage = usage.entry(key.clone()).or_insert_with(|| KeyUsage {
+                key: key.clone(),
+                allowed: 0,
+                denied: 0,
+            });
+            usage.allowed += limiter.allowed;
+            usage.denied += limiter.denied;
+        }
+        let mut usage: Vec<_> = usage.into_values().collect();
+        usage.sort_by(|a, b| {
+            (b.allowed + b.denied)
+                .cmp(&(a.allowed + a.denied))
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        usage.truncate(limit);
+        usage
+    }
+
+    /// Everything known about `key`, or `None` if it holds no limiter and
+    /// is neither banned nor exempt.
+    pub fn key_state(&self, key: &str, now: Instant) -> Option<KeyState> {
+        let mut routes: Vec<_> = self
+            .limiters
+            .iter()
+            .filter(|entry| entry.key().1 == key)
+            .map(|entry| entry.value().state(&entry.key().0, now))
+            .collect();
+        routes.sort_by(|a, b| a.route.cmp(&b.route));
+        let override_ = self.override_for(key, now).map(|(kind, left)| KeyOverride {
+            kind,
+            seconds: whole_seconds(left),
+        });
+        if routes.is_empty() && override_.is_none() {
+            return None;
+        }
+        Some(KeyState {
+            key: key.to_owned(),
+            routes,
+            override_,
+        })
+    }
+
+    /// Forget `key`'s state on `route`, or on every route, so it starts
+    /// afresh. Returns how many limiters were dropped.
+    pub fn reset(&self, key: &str, route: Option<&str>) -> usize {
+        let map_keys: Vec<_> = self
+            .limiters
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|(limited, k)| k == key && route.is_none_or(|route| route == limited))
+            .collect();
+        map_keys
+            .iter()
+            .filter(|map_key| self.limiters.remove(*map_key).is_some())
+            .count()
+    }
+
+    /// Admit `requests` more from `key` on `route`, or on every limited
+    /// route, once its policy would deny them. Returns how many routes were
+    /// topped up, none if `route` is not limited.
+    pub fn top_up(&self, key: &str, route: Option<&str>, requests: u32) -> usize {
+        let config = self.config();
+        let routes = config
+            .routes
+            .iter()
+            .filter(|(limited, _)| route.is_none_or(|route| route == limited.as_str()));
+        let mut topped_up = 0;
+        for (limited, policy) in routes {
+            let mut limiter =
+                self.limiter((limited.clone(), key.to_owned()), policy, Instant::now());
+            limiter.credit = limiter.credit.saturating_add(requests);
+            topped_up += 1;
+        }
+        topped_up
+    }
+
+    /// Ban or exempt `key` for `duration`, replacing any earlier override.
+    /// Returns false, changing nothing, if `duration` is too long to reckon
+    /// an end for.
+    pub fn set_override(
+        &self,
+        key: &str,
+        kind: OverrideKind,
+        duration: Duration,
+        now: Instant,
+    ) -> bool {
+        match now.checked_add(duration) {
+            Some(until) => {
+                self.overrides.insert(key.to_owned(), (kind, until));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lift a ban or exemption early, returning whether there was one.
+    pub fn clear_override(&self, key: &str, now: Instant) -> bool {
+        let lifted = self.override_for(key, now).is_some();
+        self.overrides.remove(key);
+        lifted
+    }
+
+    /// The override in force for `key`, and how long it has left.
+    fn override_for(&self, key: &str, now: Instant) -> Option<(OverrideKind, Duration)> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+        let (kind, until) = *self.overrides.get(key)?;
+        if until <= now {
+            self.overrides.remove_if(key, |_, (_, until)| *until <= now);
+            return None;
+        }
+        Some((kind, until - now))
+    }
+
+    /// How many distinct route and key pairs currently hold a limiter.
+    pub fn len(&self) -> usize {
+        self.limiters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limiters.is_empty()
+    }
+}
+
+fn access_list(config: &mut RateLimitConfig, list: AccessList) -> &mut Vec<IpNet> {
+    match list {
+        AccessList::Allow => &mut config.allow,
+        AccessList::Deny => &mut config.deny,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn one_per_key() -> RateLimitConfig {
+        RateLimitConfig::default().route("/a", Policy::token_bucket(1, 0.001))
+    }
+
+    #[test]
+    fn keys_are_limited_independently() {
+        let limiters = KeyedLimiters::new(&one_per_key());
+        let now = Instant::now();
+        let allowed = |key| limiters.check("/a", key, now).unwrap().1.is_allowed();
+        assert!(allowed("alice"));
+        assert!(!allowed("alice"));
+        assert!(allowed("bob"));
+        assert_eq!(limiters.len(), 2);
+    }
+
+    #[test]
+    fn refilled_limiters_are_swept() {
+        let config = RateLimitConfig::default().route("/a", Policy::token_bucket(1, 1.0));
+        let limiters = KeyedLimiters::new(&config);
+        let now = Instant::now();
+        limiters.check("/a", "alice", now);
+        limiters.check("/a", "bob", now + Duration::from_millis(500));
+        limiters.top_up("carol", Some("/a"), 1);
+
+        limiters.sweep(now + Duration::from_secs(1));
+        assert!(limiters.key_state("alice", now).is_none());
+        assert!(limiters.key_state("bob", now).is_some());
+        // top-ups are kept until they are used
+        assert!(limiters.key_state("carol", now).is_some());
+        assert_eq!(limiters.len(), 2);
+    }
+
+    #[test]
+    fn the_least_recently_used_limiters_are_evicted() {
+        let limiters = KeyedLimiters::new(&one_per_key()).max_limiters(10);
+        let now = Instant::now();
+        for i in 0..10 {
+            let key = format!("client-{}", i);
+            limiters.check("/a", &key, now + Duration::from_millis(i));
+        }
+        assert_eq!(limiters.len(), 10);
+
+        limiters.check("/a", "newcomer", now + Duration::from_millis(10));
+        assert!(limiters.len() <= 10);
+        assert!(limiters.key_state("client-0", now).is_none());
+        assert!(limiters.key_state("client-9", now).is_some());
+        assert!(limiters.key_state("newcomer", now).is_some());
+    }
+
+    #[test]
+    fn unlimited_routes_hold_no_state() {
+        let limiters = KeyedLimiters::new(&RateLimitConfig::default());
+        assert_eq!(limiters.check("/a", "alice", Instant::now()), None);
+        assert!(limiters.is_empty());
+    }
+
+    #[test]
+    fn shared_limiters_report_admitted_requests_once() {
+        let limiters = KeyedLimiters::shared(&one_per_key());
+        let now = Instant::now();
+        limiters.check("/a", "alice", now);
+        limiters.check("/a", "alice", now);
+        assert_eq!(
+            limiters.take_unreported(),
+            vec![Consumption {
+                route: "/a".into(),
+                key: "alice".into(),
+                count: 1
+            }]
+        );
+        assert!(limiters.take_unreported().is_empty());
+    }
+
+    #[test]
+    fn reconfiguring_keeps_the_state_of_unchanged_routes() {
+        let config = one_per_key().route("/b", Policy::token_bucket(1, 0.001));
+        let limiters = KeyedLimiters::new(&config);
+        let now = Instant::now();
+        let allowed = |route| limiters.check(route, "alice", now).unwrap().1.is_allowed();
+        assert!(allowed("/a"));
+        assert!(allowed("/b"));
+
+        let changed = limiters.reconfigure(
+            &one_per_key()
+                .route("/b", Policy::token_bucket(2, 0.001))
+                .route("/c", Policy::token_bucket(1, 0.001)),
+        );
+        assert_eq!(changed, ["rate_limits.routes./b", "rate_limits.routes./c"]);
+        // "/a" is still exhausted, while "/b" starts afresh under its new policy
+        assert!(!allowed("/a"));
+        assert!(allowed("/b"));
+        assert!(allowed("/b"));
+        assert!(!allowed("/b"));
+        assert!(allowed("/c"));
+    }
+
+    #[test]
+    fn reconfiguring_the_key_resets_every_limiter() {
+        let limiters = KeyedLimiters::new(&one_per_key());
+        limiters.check("/a", "alice", Instant::now());
+        let changed =
+            limiters.reconfigure(&one_per_key().key(crate::limiter::KeyExtractor::PeerIp));
+        assert_eq!(changed, ["rate_limits.key"]);
+        assert!(limiters.is_empty());
+        assert!(limiters.reconfigure(&limiters.config()).is_empty());
+    }
+
+    #[test]
+    fn top_ups_admit_requests_beyond_the_policy() {
+        let limiters = KeyedLimiters::new(&one_per_key());
+        let now = Instant::now();
+        let allowed = || limiters.check("/a", "alice", now).unwrap().1.is_allowed();
+        assert!(allowed());
+        assert_eq!(limiters.top_up("alice", Some("/b"), 1), 0);
+        assert_eq!(limiters.top_up("alice", None, 2), 1);
+        assert!(allowed());
+        assert!(allowed());
+        assert!(!allowed());
+
+        let state = limiters.key_state("alice", now).unwrap();
+        assert_eq!((state.routes[0].allowed, state.routes[0].denied), (3, 1));
+        assert_eq!(state.routes[0].credit, 0);
+    }
+
+    #[test]
+    fn bans_and_exemptions_lapse() {
+        let limiters = KeyedLimiters::new(&one_per_key());
+        let now = Instant::now();
+        let later = now + Duration::from_secs(61);
+        let allowed = |key, now| limiters.check("/a", key, now).unwrap().1.is_allowed();
+
+        assert!(limiters.set_override("alice", OverrideKind::Ban, Duration::from_secs(60), now));
+        let (_, decision) = limiters.check("/a", "alice", now).unwrap();
+        assert_eq!(
+            decision,
+            Decision::denied(Duration::from_secs(60), Duration::from_secs(60))
+        );
+        assert!(allowed("alice", later));
+
+        assert!(limiters.set_override("bob", OverrideKind::Exempt, Duration::from_secs(60), now));
+        assert!(allowed("bob", now));
+        assert!(allowed("bob", now));
+        assert!(allowed("bob", later));
+        assert!(!allowed("bob", later));
+        assert!(!limiters.clear_override("bob", later));
+
+        // an override without an end in sight is refused, not left half set
+        assert!(!limiters.set_override("carol", OverrideKind::Ban, Duration::MAX, now));
+        assert!(allowed("carol", now));
+    }
+
+    #[test]
+    fn top_keys_are_the_busiest() {
+        let limiters =
+            KeyedLimiters::new(&one_per_key().route("/b", Policy::token_bucket(1, 0.001)));
+        let now = Instant::now();
+        for (route, key) in [
+            ("/a", "alice"),
+            ("/a", "alice"),
+            ("/b", "alice"),
+            ("/b", "bob"),
+        ] {
+            limiters.check(route, key, now);
+        }
+        let keys = |route, limit| -> Vec<_> {
+            limiters
+                .top_keys(route, limit)
+                .into_iter()
+                .map(|usage| (usage.key, usage.allowed, usage.denied))
+                .collect()
+        };
+        assert_eq!(
+            keys(None, 10),
+            [("alice".into(), 2, 1), ("bob".into(), 1, 0)]
+        );
+        assert_eq!(keys(Some("/b"), 1), [("alice".into(), 1, 0)]);
+
+        assert_eq!(limiters.reset("alice", Some("/a")), 1);
+        assert_eq!(
+            keys(None, 10),
+            [("alice".into(), 1, 0), ("bob".into(), 1, 0)]
+        );
+        assert_eq!(limiters.reset("carol", None), 0);
+    }
+
+    #[test]
+    fn policies_can_be_set_at_runtime() {
+        let limiters = KeyedLimiters::new(&one_per_key());
+        let now = Instant::now();
+        assert!(limiters.check("/b", "alice", now).is_none());
+        assert!(limiters.set_policy("/b", Policy::token_bucket(1, 0.001)));
+        assert!(limiters.check("/b", "alice", now).is_some());
+        assert!(!limiters.set_policy("/b", Policy::token_bucket(2, 0.001)));
+        assert_eq!(
+            limiters.config().routes["/b"],
+            Policy::token_bucket(2, 0.001)
+        );
+    }
+
+    #[test]
+    fn access_lists_change_in_place() {
+        let limiters = KeyedLimiters::new(&one_per_key());
+        let range: IpNet = "10.0.0.0/8".parse().unwrap();
+        assert!(limiters.add_access(AccessList::Deny, range));
+        assert!(!limiters.add_access(AccessList::Deny, range));
+        assert_eq!(limiters.config().deny, [range]);
+        assert!(limiters.remove_access(AccessList::Deny, range));
+        assert!(!limiters.remove_access(AccessList::Allow, range));
+
+        let changed = limiters.reconfigure(&one_per_key().allow(range));
+        assert_eq!(changed, ["rate_limits.allow"]);
+    }
+
+    #[test]
+    fn remote_consumption_is_charged_locally() {
+        let limiters = KeyedLimiters::shared(&one_per_key());
+        let now = Instant::now();
+        limiters.record_remote(
+            &Consumption {
+                route: "/a".into(),
+                key: "alice".into(),
+                count: 1,
+            },
+            now,
+        );
+        assert!(!limiters.check("/a", "alice", now).unwrap().1.is_allowed());
+        // remote requests are never reported back out
+        assert!(limiters.take_unreported().is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::limiter::{
+    key::{ClientIp, ClientKey},
+    Access, Decision, KeyedLimiters, Quota,
+};
+use crate::metrics::Metrics;
+use axum::{
+    extract::MatchedPath,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use client::{ErrorKind, ErrorResponse};
+use futures::future::BoxFuture;
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+
+static RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+static RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+static RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+static RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+/// Applies a rate limiter per route and client key, rejecting requests with
+/// `429 Too Many Requests` once their limiter denies them. Requests to
+/// limited routes which the key extractor cannot authenticate are rejected
+/// with `401 Unauthorized`; unlimited routes need no key.
+///
+/// Clients on the deny list are refused with `403 Forbidden` before anything
+/// else, on every route, while those on the allow list skip the limiter.
+///
+/// Every response from a limited route carries the `RateLimit-*` headers
+/// from the IETF draft, so clients can pace themselves.
+///
+/// Routes are identified by their matched pattern, so the layer must be
+/// added with `Router::layer` rather than wrapping the whole router.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiters: Arc<KeyedLimiters>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl RateLimitLayer {
+    /// Limit requests as `limiters` are configured, keying them with the
+    /// extractor in that configuration.
+    pub fn new(limiters: Arc<KeyedLimiters>) -> RateLimitLayer {
+        RateLimitLayer {
+            limiters,
+            metrics: None,
+        }
+    }
+
+    /// Count every decision in `metrics`, by route.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiters: self.limiters.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiters: Arc<KeyedLimiters>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl<S, B> Service<Request<B>> for RateLimit<S>
+where
+    S: Service<Request<B>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let config = self.limiters.config();
+        let client_ip = config.key.client_ip(&request);
+        let access = client_ip.and_then(|ip| config.access(ip));
+        if access == Some(Access::Deny) {
+            log::debug!("refused {:?} access to {}", client_ip, request.uri());
+            let response = error_response(
+                StatusCode::FORBIDDEN,
+                ErrorResponse {
+                    error: ErrorKind::Forbidden,
+                    message: "Forbidden".to_owned(),
+                    retry_after: None,
+                },
+            );
+            return Box::pin(async move { Ok(response) });
+        }
+
+        if let Some(ip) = client_ip {
+            request.extensions_mut().insert(ClientIp(ip));
+        }
+
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .and_then(|path| config.route_for(request.method(), path.as_str()));
+        let route = match route {
+            Some(route) => route,
+            None => {
+                // handlers may still want to know who is calling, but only limited
+                // routes demand a key, so health checks and the like stay open
+                if let Ok(key) = config.key.extract(&request) {
+                    request.extensions_mut().insert(config.key.client_id(&key));
+                    request.extensions_mut().insert(ClientKey(key));
+                }
+                return Box::pin(self.inner.call(request));
+            }
+        };
+
+        let key = match config.key.extract(&request) {
+            Ok(key) => key,
+            Err(err) => {
+                log::debug!("rejected request to {}: {:?}", request.uri(), err);
+                let response = error_response(
+                    StatusCode::UNAUTHORIZED,
+                    ErrorResponse {
+                        error: ErrorKind::Unauthorized,
+                        message: err.to_string(),
+                        retry_after: None,
+                    },
+                );
+                return Box::pin(async move { Ok(response) });
+            }
+        };
+
+        let id = config.key.client_id(&key);
+        request.extensions_mut().insert(id.clone());
+        request.extensions_mut().insert(ClientKey(key.clone()));
+        if access == Some(Access::Allow) {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let (quota, decision) = match self.limiters.check(&route, &key, Instant::now()) {
+            Some(checked) => checked,
+            None => return Box::pin(self.inner.call(request)),
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.record_decision(&route, decision.is_allowed());
+        }
+
+        if !decision.is_allowed() {
+            log::debug!("rate limited {:?} on {}", id.0, request.uri());
+            let retry_after = whole_seconds(decision.retry_after);
+            let mut response = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorResponse {
+                    error: ErrorKind::RateLimited,
+                    message: "Too Many Requests".to_owned(),
+                    retry_after: Some(retry_after),
+                },
+            );
+            let headers = response.headers_mut();
+            insert_rate_limit_headers(headers, quota, decision);
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            return Box::pin(async move { Ok(response) });
+        }
+
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = future.await?;
+            insert_rate_limit_headers(response.headers_mut(), quota, decision);
+            Ok(response)
+        })
+    }
+}
+
+fn error_response(status: StatusCode, body: ErrorResponse) -> Response {
+    (status, Json(body)).into_response()
+}
+
+fn insert_rate_limit_headers(headers: &mut HeaderMap, quota: Quota, decision: Decision) {
+    headers.insert(RATELIMIT_LIMIT.clone(), HeaderValue::from(quota.limit));
+    headers.insert(
+        RATELIMIT_REMAINING.clone(),
+        HeaderValue::from(decision.remaining),
+    );
+    headers.insert(
+        RATELIMIT_RESET.clone(),
+        HeaderValue::from(whole_seconds(decision.reset)),
+    );
+    let policy = format!("{};w={}", quota.limit, whole_seconds(quota.window));
+    if let Ok(policy) = HeaderValue::from_str(&policy) {
+        headers.insert(RATELIMIT_POLICY.clone(), policy);
+    }
+}
+
+/// Round up to whole seconds, as the headers only carry integers and
+/// rounding down would invite clients to retry too early.
+pub(crate) fn whole_seconds(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_add(u64::from(duration.subsec_nanos() > 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_seconds_rounds_up() {
+        assert_eq!(whole_seconds(Duration::ZERO), 0);
+        assert_eq!(whole_seconds(Duration::from_millis(1)), 1);
+        assert_eq!(whole_seconds(Duration::from_secs(2)), 2);
+        assert_eq!(whole_seconds(Duration::from_millis(2001)), 3);
+        assert_eq!(whole_seconds(Duration::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn headers_describe_quota_and_decision() {
+        let mut headers = HeaderMap::new();
+        insert_rate_limit_headers(
+            &mut headers,
+            Quota {
+                limit: 10,
+                window: Duration::from_secs(60),
+            },
+            Decision::allowed(7, Duration::from_millis(1500)),
+        );
+        assert_eq!(headers["ratelimit-limit"], "10");
+        assert_eq!(headers["ratelimit-remaining"], "7");
+        assert_eq!(headers["ratelimit-reset"], "2");
+        assert_eq!(headers["ratelimit-policy"], "10;w=60");
+    }
+}
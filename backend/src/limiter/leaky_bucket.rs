@@ -0,0 +1,98 @@
+use crate::limiter::{Decision, RateLimiter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A leaky bucket used as a meter: each request pours one unit into a bucket
+/// of `capacity` units that drains at `leak_per_second`. Requests that would
+/// overflow the bucket are rejected rather than queued.
+pub struct LeakyBucket {
+    capacity: f64,
+    leak_per_second: f64,
+    state: Mutex<LevelState>,
+}
+
+struct LevelState {
+    level: f64,
+    last_leak: Option<Instant>,
+}
+
+impl LeakyBucket {
+    pub fn new(capacity: u32, leak_per_second: f64) -> LeakyBucket {
+        LeakyBucket {
+            capacity: capacity as f64,
+            leak_per_second,
+            state: Mutex::new(LevelState {
+                level: 0.0,
+                last_leak: None,
+            }),
+        }
+    }
+
+    fn seconds_to_drain(&self, level: f64) -> Duration {
+        // a slow enough rate can take longer than any `Duration`
+        Duration::try_from_secs_f64((level / self.leak_per_second).max(0.0))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl RateLimiter for LeakyBucket {
+    fn check(&self, now: Instant) -> Decision {
+        let mut state = self.state.lock().expect("leaky bucket lock poisoned");
+
+        if let Some(last_leak) = state.last_leak {
+            let elapsed = now.saturating_duration_since(last_leak).as_secs_f64();
+            state.level = (state.level - elapsed * self.leak_per_second).max(0.0);
+        }
+        state.last_leak = Some(now);
+
+        if state.level + 1.0 <= self.capacity {
+            state.level += 1.0;
+            Decision::allowed(
+                (self.capacity - state.level).floor() as u32,
+                self.seconds_to_drain(state.level),
+            )
+        } else {
+            Decision::denied(
+                self.seconds_to_drain(state.level + 1.0 - self.capacity),
+                self.seconds_to_drain(state.level),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_drains_at_leak_rate() {
+        let start = Instant::now();
+        let limiter = LeakyBucket::new(2, 4.0);
+        assert!(limiter.check(start).is_allowed());
+        assert_eq!(
+            limiter.check(start),
+            Decision::allowed(0, Duration::from_millis(500))
+        );
+        assert_eq!(
+            limiter.check(start),
+            Decision::denied(Duration::from_millis(250), Duration::from_millis(500))
+        );
+        assert!(limiter
+            .check(start + Duration::from_millis(250))
+            .is_allowed());
+        assert!(!limiter
+            .check(start + Duration::from_millis(300))
+            .is_allowed());
+    }
+
+    #[test]
+    fn a_stalled_leak_waits_as_long_as_a_duration_can() {
+        let start = Instant::now();
+        let limiter = LeakyBucket::new(1, 1e-300);
+        assert!(limiter.check(start).is_allowed());
+        assert_eq!(
+            limiter.check(start),
+            Decision::denied(Duration::MAX, Duration::MAX)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use crate::limiter::{Decision, RateLimiter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Approximates a sliding window from two fixed-window counts: the previous
+/// window's count is weighted by how much of it still overlaps the trailing
+/// `window`. Constant memory, and smooths out fixed-window boundary bursts.
+pub struct SlidingWindowCounter {
+    limit: u32,
+    window: Duration,
+    state: Mutex<CounterState>,
+}
+
+struct CounterState {
+    started: Option<Instant>,
+    previous: u32,
+    current: u32,
+}
+
+impl SlidingWindowCounter {
+    pub fn new(limit: u32, window: Duration) -> SlidingWindowCounter {
+        SlidingWindowCounter {
+            limit,
+            window,
+            state: Mutex::new(CounterState {
+                started: None,
+                previous: 0,
+                current: 0,
+            }),
+        }
+    }
+}
+
+impl RateLimiter for SlidingWindowCounter {
+    fn check(&self, now: Instant) -> Decision {
+        let mut state = self
+            .state
+            .lock()
+            .expect("sliding window counter lock poisoned");
+
+        let mut started = state.started.unwrap_or(now);
+        let windows = now.saturating_duration_since(started).as_nanos() / self.window.as_nanos();
+        if windows == 1 {
+            state.previous = state.current;
+            state.current = 0;
+        } else if windows > 1 {
+            state.previous = 0;
+            state.current = 0;
+        }
+        started = match u32::try_from(windows) {
+            Ok(windows) => started + self.window * windows,
+            // too many windows to count, so start afresh
+            Err(_) => now,
+        };
+        state.started = Some(started);
+
+        let elapsed = now.saturating_duration_since(started).as_secs_f64();
+        let window = self.window.as_secs_f64();
+        let overlap = 1.0 - elapsed / window;
+        let estimate = state.previous as f64 * overlap + state.current as f64;
+
+        let allowed = estimate + 1.0 <= self.limit as f64;
+        if allowed {
+            state.current += 1;
+        }
+
+        // requests stay in view until the window after the one they were counted in
+        let window_end = started
+            .checked_add(self.window)
+            .map_or(self.window, |end| end.saturating_duration_since(now));
+        let reset = if state.current > 0 {
+            window_end.saturating_add(self.window)
+        } else if state.previous > 0 {
+            window_end
+        } else {
+            Duration::ZERO
+        };
+
+        if allowed {
+            let remaining = (self.limit as f64 - estimate - 1.0).floor() as u32;
+            return Decision::allowed(remaining, reset);
+        }
+
+        let retry_after = if state.current >= self.limit || state.previous == 0 {
+            // nothing in the previous window to age out, so wait for the next one
+            window_end
+        } else {
+            // wait until enough of the previous window has slid out of view
+            let headroom = (self.limit - state.current) as f64 - 1.0;
+            let target_overlap = headroom / state.previous as f64;
+            Duration::from_secs_f64(((1.0 - target_overlap) * window - elapsed).max(0.0))
+        };
+        Decision::denied(retry_after, reset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previous_window_is_weighted_by_overlap() {
+        let start = Instant::now();
+        let limiter = SlidingWindowCounter::new(4, Duration::from_secs(10));
+        for _ in 0..4 {
+            assert!(limiter.check(start).is_allowed());
+        }
+        // halfway through the next window, half of the previous 4 still count
+        let halfway = start + Duration::from_secs(15);
+        assert_eq!(limiter.check(halfway).remaining, 1);
+        assert_eq!(limiter.check(halfway).remaining, 0);
+        assert!(!limiter.check(halfway).is_allowed());
+    }
+
+    #[test]
+    fn retry_after_accounts_for_sliding_overlap() {
+        let start = Instant::now();
+        let limiter = SlidingWindowCounter::new(2, Duration::from_secs(10));
+        assert!(limiter.check(start).is_allowed());
+        assert!(limiter.check(start).is_allowed());
+        // 2 * 0.8 = 1.6 requests still in view; one more fits once it drops to 1
+        let decision = limiter.check(start + Duration::from_secs(12));
+        assert!(!decision.is_allowed());
+        assert_eq!(decision.retry_after, Duration::from_secs(3));
+        assert!(limiter.check(start + Duration::from_secs(15)).is_allowed());
+    }
+
+    #[test]
+    fn idle_windows_forget_history() {
+        let start = Instant::now();
+        let limiter = SlidingWindowCounter::new(1, Duration::from_secs(10));
+        assert!(limiter.check(start).is_allowed());
+        assert!(limiter.check(start + Duration::from_secs(25)).is_allowed());
+    }
+
+    #[test]
+    fn windows_too_long_to_end_do_not_panic() {
+        let start = Instant::now();
+        let limiter = SlidingWindowCounter::new(1, Duration::from_secs_f64(1e19));
+        assert!(limiter.check(start).is_allowed());
+        assert_eq!(limiter.check(start).reset, Duration::MAX);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::limiter::{Decision, RateLimiter};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Remembers the timestamp of every admitted request and admits a new one
+/// only if fewer than `limit` fall within the trailing `window`. Exact, at the
+/// cost of memory proportional to the requests in the window.
+pub struct SlidingWindowLog {
+    limit: u32,
+    window: Duration,
+    log: Mutex<VecDeque<Instant>>,
+}
+
+impl SlidingWindowLog {
+    pub fn new(limit: u32, window: Duration) -> SlidingWindowLog {
+        SlidingWindowLog {
+            limit,
+            window,
+            log: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn expiry(&self, admitted: Option<&Instant>, now: Instant) -> Duration {
+        admitted
+            .map(|admitted| match admitted.checked_add(self.window) {
+                Some(expires) => expires.saturating_duration_since(now),
+                // too far off to reach on the clock, so it never expires
+                None => self.window,
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl RateLimiter for SlidingWindowLog {
+    fn check(&self, now: Instant) -> Decision {
+        let mut log = self.log.lock().expect("sliding window log lock poisoned");
+
+        while let Some(oldest) = log.front() {
+            if now.saturating_duration_since(*oldest) >= self.window {
+                log.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if log.len() < self.limit as usize {
+            log.push_back(now);
+            return Decision::allowed(self.limit - log.len() as u32, self.expiry(log.back(), now));
+        }
+
+        Decision::denied(self.expiry(log.front(), now), self.expiry(log.back(), now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_expire_individually() {
+        let start = Instant::now();
+        let limiter = SlidingWindowLog::new(2, Duration::from_secs(10));
+        assert!(limiter.check(start).is_allowed());
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(5)),
+            Decision::allowed(0, Duration::from_secs(10))
+        );
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(8)),
+            Decision::denied(Duration::from_secs(2), Duration::from_secs(7))
+        );
+        // only the first request has left the window
+        assert!(limiter.check(start + Duration::from_secs(10)).is_allowed());
+        assert!(!limiter.check(start + Duration::from_secs(11)).is_allowed());
+    }
+
+    #[test]
+    fn windows_too_long_to_end_do_not_panic() {
+        let start = Instant::now();
+        let window = Duration::from_secs_f64(1e19);
+        let limiter = SlidingWindowLog::new(1, window);
+        assert_eq!(limiter.check(start), Decision::allowed(0, window));
+        assert!(!limiter.check(start).is_allowed());
+    }
+}
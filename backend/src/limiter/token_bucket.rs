@@ -0,0 +1,116 @@
+use crate::limiter::{Decision, RateLimiter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A classic token bucket: holds up to `capacity` tokens and regains
+/// `refill_per_second` tokens every second. Each request takes one token.
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_second: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, refill_per_second: f64) -> TokenBucket {
+        TokenBucket {
+            capacity: capacity as f64,
+            refill_per_second,
+            state: Mutex::new(BucketState {
+                tokens: capacity as f64,
+                last_refill: None,
+            }),
+        }
+    }
+
+    fn seconds_to_refill(&self, tokens: f64) -> Duration {
+        // a slow enough rate can take longer than any `Duration`
+        Duration::try_from_secs_f64((tokens / self.refill_per_second).max(0.0))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl RateLimiter for TokenBucket {
+    fn check(&self, now: Instant) -> Decision {
+        let mut state = self.state.lock().expect("token bucket lock poisoned");
+
+        if let Some(last_refill) = state.last_refill {
+            let elapsed = now.saturating_duration_since(last_refill).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        }
+        state.last_refill = Some(now);
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            Decision::allowed(
+                state.tokens.floor() as u32,
+                self.seconds_to_refill(self.capacity - state.tokens),
+            )
+        } else {
+            Decision::denied(
+                self.seconds_to_refill(1.0 - state.tokens),
+                self.seconds_to_refill(self.capacity - state.tokens),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_drains_bucket_to_capacity() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(3, 1.0);
+        assert_eq!(
+            bucket.check(start),
+            Decision::allowed(2, Duration::from_secs(1))
+        );
+        assert!(bucket.check(start).is_allowed());
+        assert!(bucket.check(start).is_allowed());
+        assert_eq!(
+            bucket.check(start),
+            Decision::denied(Duration::from_secs(1), Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn check_succeeds_again_after_refill() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(1, 2.0);
+        assert!(bucket.check(start).is_allowed());
+        assert!(!bucket
+            .check(start + Duration::from_millis(250))
+            .is_allowed());
+        assert!(bucket
+            .check(start + Duration::from_millis(500))
+            .is_allowed());
+    }
+
+    #[test]
+    fn refill_never_exceeds_capacity() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(2, 100.0);
+        assert!(bucket.check(start).is_allowed());
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.check(later).is_allowed());
+        assert!(bucket.check(later).is_allowed());
+        assert!(!bucket.check(later).is_allowed());
+    }
+
+    #[test]
+    fn a_stalled_refill_waits_as_long_as_a_duration_can() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(1, 1e-300);
+        assert!(bucket.check(start).is_allowed());
+        assert_eq!(
+            bucket.check(start),
+            Decision::denied(Duration::MAX, Duration::MAX)
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::config::LogConfig;
+use std::sync::OnceLock;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    filter::{ParseError, Targets},
+    prelude::*,
+    reload, Registry,
+};
+
+/// Directives in the form `Targets` parses, e.g. `warn,backend=debug`, which
+/// take the place of the configured level at startup.
+const LOGGING_VARIABLE: &str = "RUST_LOG";
+
+/// Libraries whose debug output drowns ours, kept at `info` unless the
+/// level is quieter than that.
+const NOISY_TARGETS: [&str; 2] = ["hyper", "mio"];
+
+static FILTER: OnceLock<reload::Handle<Targets, Registry>> = OnceLock::new();
+
+/// Log to the console as `RUST_LOG` directs, or else at the level in
+/// `config`. Either way, `set_level` can change the level later on.
+pub fn init(config: &LogConfig) {
+    let directives = std::env::var(LOGGING_VARIABLE).ok();
+    let (initial, invalid) = initial_targets(directives.as_deref(), &config.level);
+    let (filter, handle) = reload::Layer::new(initial);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_ansi(config.ansi))
+        .init();
+    let _ = FILTER.set(handle);
+    if let Some(err) = invalid {
+        log::warn!("ignoring {}: {}", LOGGING_VARIABLE, err);
+    }
+}
+
+/// The targets `directives` describe, falling back to `level` when there are
+/// none or they do not parse, along with why they did not.
+fn initial_targets(directives: Option<&str>, level: &str) -> (Targets, Option<ParseError>) {
+    match directives.map(str::parse) {
+        Some(Ok(targets)) => (targets, None),
+        Some(Err(err)) => (targets(level), Some(err)),
+        None => (targets(level), None),
+    }
+}
+
+/// Log at `level` from now on, one of those `LogConfig::level` accepts.
+/// Does nothing if `init` was never called, as in tests.
+pub fn set_level(level: &str) {
+    if let Some(handle) = FILTER.get() {
+        if let Err(err) = handle.reload(targets(level)) {
+            log::error!("failed to change the log level: {}", err);
+        }
+    }
+}
+
+fn targets(level: &str) -> Targets {
+    let level: LevelFilter = level.parse().unwrap_or(LevelFilter::DEBUG);
+    NOISY_TARGETS
+        .iter()
+        .fold(Targets::new().with_default(level), |targets, target| {
+            targets.with_target(*target, level.min(LevelFilter::INFO))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+
+    #[test]
+    fn noisy_libraries_stay_at_info() {
+        let trace = targets("trace");
+        assert!(trace.would_enable("backend::startup", &Level::TRACE));
+        assert!(!trace.would_enable("hyper::proto", &Level::DEBUG));
+        assert!(trace.would_enable("hyper::proto", &Level::INFO));
+
+        let warn = targets("warn");
+        assert!(!warn.would_enable("backend::startup", &Level::INFO));
+        assert!(!warn.would_enable("hyper::proto", &Level::INFO));
+    }
+
+    #[test]
+    fn rust_log_takes_the_place_of_the_level() {
+        let (targets, invalid) = initial_targets(Some("warn,backend=trace"), "info");
+        assert!(invalid.is_none());
+        assert!(targets.would_enable("backend::startup", &Level::TRACE));
+        assert!(!targets.would_enable("hyper::proto", &Level::INFO));
+
+        let (targets, invalid) = initial_targets(Some("backend=loud"), "info");
+        assert!(invalid.is_some());
+        assert!(targets.would_enable("backend::startup", &Level::INFO));
+        assert!(!targets.would_enable("backend::startup", &Level::DEBUG));
+
+        let (targets, invalid) = initial_targets(None, "debug");
+        assert!(invalid.is_none());
+        assert!(targets.would_enable("backend::startup", &Level::DEBUG));
+    }
+}
@@ -1,52 +1,204 @@
-use backend::startup::run;
+use backend::{
+    cluster::{Cluster, ClusterConfig},
+    config::{Config, ConfigError, CONFIG_VARIABLE},
+    logging,
+    reload::ConfigSource,
+    shutdown,
+    startup::run,
+    store::StoreConfig,
+};
+use libp2p::Multiaddr;
 use clap::Parser;
-use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener};
-use std::str::FromStr;
-
-const LOGGING_VARIABLE: &str = "RUST_LOG";
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process;
 
 // Setup the command line interface with clap.
-#[derive(Parser, Debug)]
+// Flags left out fall back to the configuration file, then the defaults.
+#[derive(Parser, Debug, Clone)]
 #[clap(name = "server", about = "A server for our wasm project!")]
 struct Opt {
-    /// set the log level
-    #[clap(short = 'l', long = "log-level", default_value = "debug")]
-    log_level: String,
+    /// read settings from this TOML file (default: $LIMITRS_CONFIG)
+    #[clap(short = 'c', long = "config")]
+    config: Option<PathBuf>,
+
+    /// validate the configuration and exit without starting the server
+    #[clap(long = "check-config")]
+    check_config: bool,
+
+    /// set the log level [default: debug]
+    #[clap(short = 'l', long = "log-level")]
+    log_level: Option<String>,
+
+    /// set the listen addr [default: ::1]
+    #[clap(short = 'a', long = "addr")]
+    addr: Option<IpAddr>,
+
+    /// set the listen port [default: 8080]
+    #[clap(short = 'p', long = "port")]
+    port: Option<u16>,
+
+    /// set the directory where static files are to be found [default: ./dist]
+    #[clap(long = "static-dir")]
+    static_dir: Option<String>,
+
+    /// listen for backend peers on this multiaddr, e.g. /ip4/0.0.0.0/tcp/4001
+    #[clap(long = "peer-listen")]
+    peer_listen: Vec<Multiaddr>,
+
+    /// dial this backend peer at startup
+    #[clap(long = "peer")]
+    peers: Vec<Multiaddr>,
+
+    /// discover backend peers on the local network
+    #[clap(long = "mdns")]
+    mdns: bool,
+
+    /// keep this backend's peer id in this file, creating it if missing
+    #[clap(long = "peer-identity")]
+    peer_identity: Option<PathBuf>,
+
+    /// where to keep counters between restarts: memory, file:<path> or sqlite:<path> [default: memory]
+    #[clap(long = "store")]
+    store: Option<StoreConfig>,
+
+    /// how many seconds to remember responses to requests sent with an Idempotency-Key [default: 86400]
+    #[clap(long = "idempotency-ttl")]
+    idempotency_ttl: Option<u64>,
+
+    /// also serve the counters over gRPC on this port
+    #[clap(long = "grpc-port")]
+    grpc_port: Option<u16>,
+
+    /// also serve the counters over the Redis protocol (RESP) on this port
+    #[clap(long = "resp-port")]
+    resp_port: Option<u16>,
 
-    /// set the listen addr
-    #[clap(short = 'a', long = "addr", default_value = "::1")]
-    addr: String,
+    /// how many seconds open connections get to finish when shutting down [default: 10]
+    #[clap(long = "drain-timeout")]
+    drain_timeout: Option<u64>,
+}
+
+impl Opt {
+    /// Layer the flags that were given over `config`.
+    fn apply(self, config: &mut Config) {
+        let server = &mut config.server;
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+        if let Some(addr) = self.addr {
+            server.addr = addr;
+        }
+        if let Some(port) = self.port {
+            server.port = port;
+        }
+        if let Some(static_dir) = self.static_dir {
+            server.static_dir = static_dir;
+        }
+        if let Some(ttl) = self.idempotency_ttl {
+            server.idempotency_ttl_seconds = ttl;
+        }
+        if let Some(port) = self.grpc_port {
+            server.grpc_port = Some(port);
+        }
+        if let Some(port) = self.resp_port {
+            server.resp_port = Some(port);
+        }
+        if let Some(timeout) = self.drain_timeout {
+            server.drain_timeout_seconds = timeout;
+        }
+        if let Some(store) = self.store {
+            config.store = store;
+        }
+        if !self.peer_listen.is_empty() {
+            config.cluster.listen = self.peer_listen;
+        }
+        if !self.peers.is_empty() {
+            config.cluster.peers = self.peers;
+        }
+        config.cluster.mdns |= self.mdns;
+        if let Some(identity) = self.peer_identity {
+            config.cluster.identity = Some(identity);
+        }
+    }
+}
 
-    /// set the listen port
-    #[clap(short = 'p', long = "port", default_value = "8080")]
-    port: u16,
+const DEFAULT_PEER_LISTEN: &str = "/ip4/0.0.0.0/tcp/0";
 
-    /// set the directory where static files are to be found
-    #[clap(long = "static-dir", default_value = "./dist")]
-    static_dir: String,
+fn config_path(opt: &Opt) -> Option<PathBuf> {
+    opt.config
+        .clone()
+        .or_else(|| std::env::var_os(CONFIG_VARIABLE).map(PathBuf::from))
+}
+
+fn load_config(opt: Opt) -> Result<Config, ConfigError> {
+    let mut config = Config::load(config_path(&opt).as_deref(), std::env::vars())?;
+    opt.apply(&mut config);
+    config.validate()?;
+    Ok(config)
 }
 
 #[tokio::main]
 async fn main() {
     let opt = Opt::parse();
+    let check_config = opt.check_config;
+    let path = config_path(&opt);
 
-    // set up logging
-    match std::env::var(LOGGING_VARIABLE) {
-        Ok(_) => (),
-        Err(_) => std::env::set_var(
-            LOGGING_VARIABLE,
-            format!("{},hyper=info,mio=info", opt.log_level),
-        ),
+    let config = match load_config(opt.clone()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    if check_config {
+        println!("configuration OK");
+        return;
     }
+
     // log to the console
-    tracing_subscriber::fmt::init();
+    logging::init(&config.log);
 
-    let sock_addr = SocketAddr::from((
-        IpAddr::from_str(opt.addr.as_str()).unwrap_or(IpAddr::V6(Ipv6Addr::LOCALHOST)),
-        opt.port,
-    ));
+    let sock_addr = SocketAddr::new(config.server.addr, config.server.port);
     let listener = TcpListener::bind(sock_addr).expect("failed to bind to socket");
 
-    log::info!("listening on http://{}", sock_addr);
-    run(listener, opt.static_dir).await
+    let store = config.store.open().expect("failed to open counter store");
+    // read the configuration again the same way, flags and all, on every reload
+    let source = ConfigSource::new(path, config.clone(), move || load_config(opt.clone()));
+    let mut settings = config.settings().store(store).reload(source);
+
+    if let Some(port) = config.server.grpc_port {
+        let grpc_addr = SocketAddr::new(sock_addr.ip(), port);
+        let grpc = TcpListener::bind(grpc_addr).expect("failed to bind gRPC socket");
+        log::info!("serving gRPC on {}", grpc_addr);
+        settings = settings.grpc(grpc);
+    }
+
+    if let Some(port) = config.server.resp_port {
+        let resp_addr = SocketAddr::new(sock_addr.ip(), port);
+        let resp = TcpListener::bind(resp_addr).expect("failed to bind RESP socket");
+        log::info!("serving RESP on {}", resp_addr);
+        settings = settings.resp(resp);
+    }
+
+    if config.cluster.enabled() {
+        let mut listen = config.cluster.listen;
+        if listen.is_empty() {
+            listen.push(DEFAULT_PEER_LISTEN.parse().expect("invalid default peer address"));
+        }
+        let config = ClusterConfig {
+            listen,
+            bootstrap: config.cluster.peers,
+            mdns: config.cluster.mdns,
+            identity: config.cluster.identity,
+        };
+        let cluster = Cluster::start(&config)
+            .await
+            .expect("failed to start peer-to-peer networking");
+        settings = settings.cluster(cluster);
+    }
+
+    let scheme = if config.tls.is_some() { "https" } else { "http" };
+    log::info!("listening on {}://{}", scheme, sock_addr);
+    run(listener, settings, shutdown::signal()).await
 }
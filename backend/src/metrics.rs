@@ -0,0 +1,380 @@
+use crate::counter::Counters;
+use axum::{
+    extract::MatchedPath,
+    http::{Request, Response},
+};
+use futures::future::BoxFuture;
+use prometheus_client::{
+    collector::Collector,
+    encoding::{text, DescriptorEncoder, EncodeLabelSet, EncodeMetric},
+    metrics::{
+        counter::{ConstCounter, Counter},
+        family::Family,
+        gauge::{ConstGauge, Gauge},
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tower::{Layer, Service};
+
+/// The content type of the exposition `Metrics::encode` renders.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The route label for requests which matched no route, so that probing
+/// arbitrary paths cannot mint new series.
+const UNMATCHED: &str = "unmatched";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct HttpLabels {
+    pub method: String,
+    pub route: String,
+    pub status: u16,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct CounterLabels {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RateLimitLabels {
+    pub route: String,
+    pub decision: &'static str,
+}
+
+type HistogramFamily<S> = Family<S, Histogram, fn() -> Histogram>;
+
+/// Every metric the backend exports. New metrics are added as fields here
+/// and registered in `Metrics::new`, so recording them is checked by the
+/// compiler rather than looked up by name.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: Family<HttpLabels, Counter>,
+    pub http_request_duration: HistogramFamily<HttpLabels>,
+    pub counter_values: Family<CounterLabels, Gauge>,
+    pub websocket_connections: Gauge,
+    pub rate_limit_decisions: Family<RateLimitLabels, Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let metrics = Metrics {
+            registry: Registry::default(),
+            http_requests: Family::default(),
+            http_request_duration: Family::new_with_constructor(|| {
+                // from a millisecond up to about half a minute
+                Histogram::new(exponential_buckets(0.001, 2.0, 16))
+            }),
+            counter_values: Family::default(),
+            websocket_connections: Gauge::default(),
+            rate_limit_decisions: Family::default(),
+        };
+        let mut registry = Registry::default();
+        registry.register(
+            "http_requests",
+            "HTTP requests handled, by route and status",
+            metrics.http_requests.clone(),
+        );
+        registry.register(
+            "http_request_duration_seconds",
+            "Time taken to respond to HTTP requests, by route and status",
+            metrics.http_request_duration.clone(),
+        );
+        registry.register(
+            "limitrs_counter_value",
+            "The current value of each counter",
+            metrics.counter_values.clone(),
+        );
+        registry.register(
+            "limitrs_websocket_connections",
+            "WebSocket connections currently open",
+            metrics.websocket_connections.clone(),
+        );
+        registry.register(
+            "limitrs_rate_limit_decisions",
+            "Requests allowed or denied by each route's rate limit policy",
+            metrics.rate_limit_decisions.clone(),
+        );
+        registry.register_collector(Box::new(ProcessCollector::new()));
+        Metrics {
+            registry,
+            ..metrics
+        }
+    }
+
+    /// Record the outcome of a rate limit check on `route`.
+    pub fn record_decision(&self, route: &str, allowed: bool) {
+        let decision = if allowed { "allowed" } else { "denied" };
+        self.rate_limit_decisions
+            .get_or_create(&RateLimitLabels {
+                route: route.to_owned(),
+                decision,
+            })
+            .inc();
+    }
+
+    /// Count an open WebSocket until the returned guard is dropped.
+    pub fn track_websocket(&self) -> Tracked {
+        self.websocket_connections.inc();
+        Tracked(self.websocket_connections.clone())
+    }
+
+    /// Render every metric, reading the counters' values as they are now.
+    pub fn encode(&self, counters: &Counters) -> String {
+        // rebuilt from scratch, so deleted counters drop out
+        self.counter_values.clear();
+        for (name, counter) in counters.list() {
+            self.counter_values
+                .get_or_create(&CounterLabels { name })
+                .set(counter.value().into());
+        }
+        let mut body = String::new();
+        text::encode(&mut body, &self.registry).expect("writing to a String cannot fail");
+        body
+    }
+}
+
+/// Decrements a gauge when dropped.
+pub struct Tracked(Gauge);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Statistics about the process itself, read from `/proc` on each scrape.
+/// Only the start time is available on other platforms.
+#[derive(Debug)]
+struct ProcessCollector {
+    start_time: f64,
+}
+
+impl ProcessCollector {
+    fn new() -> ProcessCollector {
+        let start_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs_f64())
+            .unwrap_or_default();
+        ProcessCollector { start_time }
+    }
+}
+
+impl Collector for ProcessCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let start_time = ConstGauge::new(self.start_time);
+        start_time.encode(encoder.encode_descriptor(
+            "process_start_time_seconds",
+            "When the process started, in seconds since the Unix epoch",
+            None,
+            start_time.metric_type(),
+        )?)?;
+
+        #[cfg(target_os = "linux")]
+        {
+            let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+            let gauges = [
+                (
+                    "process_resident_memory_bytes",
+                    "Resident memory size in bytes",
+                    status_field(&status, "VmRSS:").map(|kib| kib * 1024),
+                ),
+                (
+                    "process_virtual_memory_bytes",
+                    "Virtual memory size in bytes",
+                    status_field(&status, "VmSize:").map(|kib| kib * 1024),
+                ),
+                (
+                    "process_threads",
+                    "Number of OS threads in the process",
+                    status_field(&status, "Threads:"),
+                ),
+                (
+                    "process_open_fds",
+                    "Number of open file descriptors",
+                    std::fs::read_dir("/proc/self/fd")
+                        .ok()
+                        .map(|fds| fds.count() as i64),
+                ),
+            ];
+            for (name, help, value) in gauges {
+                if let Some(value) = value {
+                    let gauge = ConstGauge::new(value);
+                    gauge.encode(encoder.encode_descriptor(
+                        name,
+                        help,
+                        None,
+                        gauge.metric_type(),
+                    )?)?;
+                }
+            }
+
+            if let Some(seconds) = cpu_seconds() {
+                let cpu = ConstCounter::new(seconds);
+                cpu.encode(encoder.encode_descriptor(
+                    "process_cpu_seconds",
+                    "Total user and system CPU time spent in seconds",
+                    None,
+                    cpu.metric_type(),
+                )?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number after `key` in `/proc/self/status`, e.g. `VmRSS:  1234 kB`.
+#[cfg(target_os = "linux")]
+fn status_field(status: &str, key: &str) -> Option<i64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_seconds() -> Option<f64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the command name may contain spaces, so count fields from the `)` closing it
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    // utime and stime are the 14th and 15th fields, counting the pid and name
+    let ticks: u64 = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    // SAFETY: sysconf only reads a configuration value
+    let per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    (per_second > 0).then(|| ticks as f64 / per_second as f64)
+}
+
+/// Counts every request, and times how long it takes to respond, by method,
+/// matched route and status.
+///
+/// Like `RateLimitLayer`, routes are identified by their matched pattern, so
+/// the layer must be added with `Router::layer`.
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<Metrics>) -> MetricsLayer {
+        MetricsLayer { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = RecordMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RecordMetrics<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B, R> Service<Request<B>> for RecordMetrics<S>
+where
+    S: Service<Request<B>, Response = Response<R>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let started = Instant::now();
+        let method = request.method().to_string();
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or(UNMATCHED, |path| path.as_str())
+            .to_owned();
+        let metrics = self.metrics.clone();
+
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+            let labels = HttpLabels {
+                method,
+                route,
+                status: response.status().as_u16(),
+            };
+            metrics.http_requests.get_or_create(&labels).inc();
+            metrics
+                .http_request_duration
+                .get_or_create(&labels)
+                .observe(started.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_values_follow_the_counters() {
+        let metrics = Metrics::new();
+        let counters = Counters::default();
+        counters
+            .default_counter()
+            .update(|_, _| Ok::<_, ()>(3))
+            .unwrap();
+        counters.put("apples", -2, Default::default()).unwrap();
+
+        let body = metrics.encode(&counters);
+        assert!(body.contains("limitrs_counter_value{name=\"default\"} 3\n"));
+        assert!(body.contains("limitrs_counter_value{name=\"apples\"} -2\n"));
+
+        counters.remove("apples");
+        let body = metrics.encode(&counters);
+        assert!(!body.contains("name=\"apples\""));
+    }
+
+    #[test]
+    fn websocket_guards_close_their_connection() {
+        let metrics = Metrics::new();
+        let first = metrics.track_websocket();
+        let second = metrics.track_websocket();
+        assert_eq!(metrics.websocket_connections.get(), 2);
+        drop(first);
+        drop(second);
+        assert_eq!(metrics.websocket_connections.get(), 0);
+    }
+
+    #[test]
+    fn decisions_are_counted_per_route() {
+        let metrics = Metrics::new();
+        metrics.record_decision("/api/count", true);
+        metrics.record_decision("/api/count", false);
+        metrics.record_decision("/api/count", false);
+
+        let body = metrics.encode(&Counters::default());
+        assert!(body.contains(
+            "limitrs_rate_limit_decisions_total{route=\"/api/count\",decision=\"denied\"} 2\n"
+        ));
+        assert!(body.contains("process_start_time_seconds "));
+    }
+}
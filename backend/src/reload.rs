@@ -0,0 +1,322 @@
+use crate::config::{Config, ConfigError};
+use crate::limiter::KeyedLimiters;
+use crate::logging;
+use crate::shutdown::Shutdown;
+use client::{ConfigReload, ConfigStatus, ReloadTrigger};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// How often the configuration file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How many reloads `/admin/config` remembers.
+const HISTORY_LENGTH: usize = 20;
+
+type Load = Box<dyn Fn() -> Result<Config, ConfigError> + Send + Sync>;
+
+/// Where the running configuration came from, so it can be read again.
+pub struct ConfigSource {
+    path: Option<PathBuf>,
+    // what the file held when `initial` was read, to tell when it changes
+    contents: Option<Vec<u8>>,
+    initial: Config,
+    load: Load,
+}
+
+impl ConfigSource {
+    /// `initial` was read from the file at `path`, if any, and `load` reads
+    /// it again the same way, overrides and all.
+    pub fn new(
+        path: Option<PathBuf>,
+        initial: Config,
+        load: impl Fn() -> Result<Config, ConfigError> + Send + Sync + 'static,
+    ) -> ConfigSource {
+        ConfigSource {
+            contents: path.as_deref().and_then(contents),
+            path,
+            initial,
+            load: Box::new(load),
+        }
+    }
+
+    /// Read the file at `path`, without any environment variables or flags.
+    pub fn file(path: impl Into<PathBuf>) -> Result<ConfigSource, ConfigError> {
+        let path = path.into();
+        let load = {
+            let path = path.clone();
+            move || Config::load(Some(&path), std::iter::empty())
+        };
+        let initial = load()?;
+        initial.validate()?;
+        Ok(ConfigSource::new(Some(path), initial, load))
+    }
+}
+
+/// Applies changes to the configuration while the server runs.
+///
+/// Rate-limit policies and the log level are swapped in place; anything
+/// else that changed is reported as needing a restart and otherwise left
+/// alone. A configuration which fails to load or validate is refused as a
+/// whole, keeping the one already running.
+pub struct Reloader {
+    path: Option<PathBuf>,
+    contents: Option<Vec<u8>>,
+    load: Load,
+    limiters: Arc<KeyedLimiters>,
+    status: Mutex<Status>,
+}
+
+struct Status {
+    // what is actually running, which lags the file for settings needing a restart
+    running: Config,
+    generation: u64,
+    reloads: VecDeque<ConfigReload>,
+}
+
+impl Reloader {
+    pub fn new(source: ConfigSource, limiters: Arc<KeyedLimiters>) -> Reloader {
+        Reloader {
+            path: source.path,
+            contents: source.contents,
+            load: source.load,
+            limiters,
+            status: Mutex::new(Status {
+                running: source.initial,
+                generation: 0,
+                reloads: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// The file the configuration is read from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Read the configuration again and apply whatever can be applied.
+    pub fn reload(&self, trigger: ReloadTrigger) -> ConfigReload {
+        let mut status = self.status.lock().expect("reload lock poisoned");
+        let mut reload = ConfigReload {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            trigger,
+            applied: Vec::new(),
+            ignored: Vec::new(),
+            error: None,
+        };
+
+        match (self.load)().and_then(|config| config.validate().map(|()| config)) {
+            Ok(config) => {
+                let running = &mut status.running;
+                reload.applied = self.limiters.reconfigure(&config.rate_limits);
+                running.rate_limits = config.rate_limits.clone();
+                if running.log.level != config.log.level {
+                    logging::set_level(&config.log.level);
+                    running.log.level = config.log.level.clone();
+                    reload.applied.push("log.level".into());
+                }
+                reload.ignored = needs_restart(running, &config);
+                status.generation += 1;
+                log::info!(
+                    "reloaded configuration on {:?}, applied: {:?}",
+                    trigger,
+                    reload.applied
+                );
+                if !reload.ignored.is_empty() {
+                    log::warn!(
+                        "configuration changes need a restart to take effect: {:?}",
+                        reload.ignored
+                    );
+                }
+            }
+            Err(err) => {
+                log::error!(
+                    "kept the running configuration, as the new one is invalid: {}",
+                    err
+                );
+                reload.error = Some(err.to_string());
+            }
+        }
+
+        if status.reloads.len() == HISTORY_LENGTH {
+            status.reloads.pop_front();
+        }
+        status.reloads.push_back(reload.clone());
+        reload
+    }
+
+    pub fn status(&self) -> ConfigStatus {
+        let status = self.status.lock().expect("reload lock poisoned");
+        ConfigStatus {
+            path: self.path.as_ref().map(|path| path.display().to_string()),
+            generation: status.generation,
+            reloads: status.reloads.iter().cloned().collect(),
+        }
+    }
+}
+
+/// The sections of `config` which differ from `running` but can only
+/// change with a restart.
+fn needs_restart(running: &Config, config: &Config) -> Vec<String> {
+    let changed = [
+        ("server", running.server != config.server),
+        ("log.ansi", running.log.ansi != config.log.ansi),
+        ("counters", running.counters != config.counters),
+        ("store", running.store != config.store),
+        ("cluster", running.cluster != config.cluster),
+        ("tls", running.tls != config.tls),
+        ("admin", running.admin != config.admin),
+    ];
+    changed
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(section, _)| section.to_owned())
+        .collect()
+}
+
+/// Reload whenever the configuration file changes or, on Unix, the server
+/// is sent SIGHUP, until `shutdown` is triggered.
+pub fn watch(reloader: Arc<Reloader>, shutdown: Shutdown) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let path = reloader.path().map(Path::to_owned);
+        let mut seen = reloader.contents.clone();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut hangup = Hangup::new();
+        loop {
+            let trigger = tokio::select! {
+                _ = shutdown.triggered() => break,
+                _ = hangup.recv() => ReloadTrigger::Signal,
+                _ = interval.tick(), if path.is_some() => {
+                    let now = path.as_deref().and_then(contents);
+                    if now == seen {
+                        continue;
+                    }
+                    seen = now;
+                    ReloadTrigger::File
+                }
+            };
+            let reloader = reloader.clone();
+            if let Err(err) = tokio::task::spawn_blocking(move || reloader.reload(trigger)).await {
+                log::error!("failed to reload configuration: {}", err);
+            }
+        }
+    })
+}
+
+// a missing or unreadable file counts as a change, so the reload reports why
+fn contents(path: &Path) -> Option<Vec<u8>> {
+    fs::read(path).ok()
+}
+
+/// SIGHUP, on platforms which have it.
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Hangup {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signal = signal(SignalKind::hangup())
+                .map_err(|err| log::warn!("failed to listen for SIGHUP: {}", err))
+                .ok();
+            Hangup { signal }
+        }
+        #[cfg(not(unix))]
+        Hangup {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limiter::RateLimitConfig;
+    use std::time::Instant;
+
+    fn write(file: &tempfile::NamedTempFile, contents: &str) {
+        fs::write(file.path(), contents).unwrap();
+    }
+
+    #[test]
+    fn reloads_apply_rate_limits_and_keep_state_elsewhere() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        write(
+            &file,
+            r#"
+            [rate_limits.routes."/a"]
+            algorithm = "token_bucket"
+            capacity = 1
+            refill_per_second = 0.001
+            "#,
+        );
+        let source = ConfigSource::file(file.path()).unwrap();
+        let limiters = Arc::new(KeyedLimiters::new(&source.initial.rate_limits));
+        let reloader = Reloader::new(source, limiters.clone());
+        let now = Instant::now();
+        assert!(limiters.check("/a", "alice", now).unwrap().1.is_allowed());
+
+        write(
+            &file,
+            r#"
+            [server]
+            port = 9090
+
+            [rate_limits.routes."/a"]
+            algorithm = "token_bucket"
+            capacity = 1
+            refill_per_second = 0.001
+
+            [rate_limits.routes."/b"]
+            algorithm = "token_bucket"
+            capacity = 1
+            refill_per_second = 0.001
+            "#,
+        );
+        let reload = reloader.reload(ReloadTrigger::Signal);
+        assert_eq!(reload.error, None);
+        assert_eq!(reload.applied, ["rate_limits.routes./b"]);
+        assert_eq!(reload.ignored, ["server"]);
+        assert!(!limiters.check("/a", "alice", now).unwrap().1.is_allowed());
+        assert!(limiters.check("/b", "alice", now).is_some());
+        assert_eq!(reloader.status().generation, 1);
+    }
+
+    #[test]
+    fn invalid_configurations_are_refused() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        write(&file, "");
+        let source = ConfigSource::file(file.path()).unwrap();
+        let limiters = Arc::new(KeyedLimiters::new(&RateLimitConfig::counter_defaults()));
+        let reloader = Reloader::new(source, limiters.clone());
+
+        write(&file, "[log]\nlevel = \"loud\"\n");
+        let reload = reloader.reload(ReloadTrigger::File);
+        assert!(reload.error.unwrap().contains("log.level"));
+        assert!(reload.applied.is_empty());
+
+        let status = reloader.status();
+        assert_eq!(status.generation, 0);
+        assert_eq!(status.reloads.len(), 1);
+        assert_eq!(
+            limiters.config().routes,
+            RateLimitConfig::counter_defaults().routes
+        );
+    }
+}
@@ -0,0 +1,554 @@
+use crate::counter::DEFAULT_COUNTER;
+use crate::history::Origin;
+use crate::limiter::{
+    key::{ClientId, KeyError},
+    layer::whole_seconds,
+    KeyExtractor, RateLimitConfig, Refusal,
+};
+use crate::routes::{
+    count::{self, try_alter_count, ServerError},
+    counters,
+};
+use crate::state::AppState;
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderName, HeaderValue, Method, Request},
+};
+use client::CountRequest;
+use std::{
+    fmt, io,
+    net::{SocketAddr, TcpListener},
+};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+const MAX_ARGUMENTS: usize = 16;
+const MAX_ARGUMENT_LENGTH: usize = 1024;
+
+/// Serve the counters over the Redis protocol on `listener`, so that
+/// `redis-cli` and Redis client libraries can drive them. Keys are counter
+/// names; unlike Redis, counters must already exist, having been created
+/// over HTTP.
+///
+/// Only `GET`, `SET`, `INCR`, `DECR`, `INCRBY`, `DECRBY`, `AUTH` and `PING`
+/// are understood. As with gRPC, commands changing a counter are held to the
+/// access lists and the policy of the matching HTTP route, keyed the same way
+/// as HTTP requests. When clients need an API key, they send it with `AUTH`.
+/// Connections are closed once the server shuts down, after replying to any
+/// command already read.
+pub fn serve(listener: TcpListener, state: AppState) {
+    listener
+        .set_nonblocking(true)
+        .expect("failed to make RESP listener non-blocking");
+    let listener =
+        tokio::net::TcpListener::from_std(listener).expect("failed to register RESP listener");
+    let shutdown = state.shutdown.clone();
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.triggered() => return,
+            };
+            match accepted {
+                Ok((stream, peer)) => {
+                    let draining = shutdown.guard();
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        handle_connection(stream, peer, state).await;
+                        drop(draining);
+                    });
+                }
+                Err(err) => log::error!("failed to accept RESP connection: {}", err),
+            }
+        }
+    });
+}
+
+async fn handle_connection(stream: tokio::net::TcpStream, peer: SocketAddr, state: AppState) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut session = Session {
+        peer: Some(peer),
+        api_key: None,
+    };
+    loop {
+        let command = tokio::select! {
+            command = read_command(&mut reader) => command,
+            _ = state.shutdown.triggered() => return,
+        };
+        let (reply, keep_going) = match command {
+            Ok(Some(args)) => (execute(&args, &state, &mut session), true),
+            Ok(None) => return,
+            // the stream can't be trusted to line up with commands any more
+            Err(RespError::Protocol(message)) => (Reply::Error(message), false),
+            Err(RespError::Io(err)) => {
+                log::debug!("RESP connection from {} failed: {}", peer, err);
+                return;
+            }
+        };
+        if writer.write_all(&reply.encode()).await.is_err() || !keep_going {
+            return;
+        }
+    }
+}
+
+/// What a connection remembers between commands.
+#[derive(Default)]
+struct Session {
+    peer: Option<SocketAddr>,
+    /// The API key last accepted by `AUTH`.
+    api_key: Option<String>,
+}
+
+impl Session {
+    /// The HTTP request this connection's commands amount to, as far as
+    /// keying them is concerned: one from the peer, carrying its API key.
+    fn http_request(&self, config: &RateLimitConfig) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(peer) = self.peer {
+            request.extensions_mut().insert(ConnectInfo(peer));
+        }
+        if let (KeyExtractor::ApiKey { header, .. }, Some(api_key)) = (&config.key, &self.api_key) {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(header.as_bytes()),
+                HeaderValue::from_str(api_key),
+            ) {
+                request.headers_mut().insert(name, value);
+            }
+        }
+        request
+    }
+}
+
+#[derive(Debug)]
+enum RespError {
+    Io(io::Error),
+    Protocol(String),
+}
+
+impl From<io::Error> for RespError {
+    fn from(err: io::Error) -> Self {
+        RespError::Io(err)
+    }
+}
+
+fn protocol_error(message: &str) -> RespError {
+    RespError::Protocol(format!("ERR Protocol error: {}", message))
+}
+
+/// Read one command, either as an array of bulk strings or inline as
+/// words on a line, returning `None` once the client hangs up.
+async fn read_command<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<Vec<String>>, RespError> {
+    let line = match read_line(reader).await? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let count = match line.strip_prefix('*') {
+        Some(count) => parse_length(count, MAX_ARGUMENTS)?,
+        None => return Ok(Some(line.split_whitespace().map(str::to_owned).collect())),
+    };
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let line = read_line(reader)
+            .await?
+            .ok_or_else(|| protocol_error("unexpected end of stream"))?;
+        let length = match line.strip_prefix('$') {
+            Some(length) => parse_length(length, MAX_ARGUMENT_LENGTH)?,
+            None => return Err(protocol_error("expected '$'")),
+        };
+        let mut arg = vec![0; length + 2];
+        reader.read_exact(&mut arg).await?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not terminated by CRLF"));
+        }
+        arg.truncate(length);
+        let arg = String::from_utf8(arg).map_err(|_| protocol_error("invalid UTF-8"))?;
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<String>, RespError> {
+    let mut line = String::new();
+    let read = (&mut *reader)
+        .take(MAX_ARGUMENT_LENGTH as u64)
+        .read_line(&mut line)
+        .await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(protocol_error("line too long"));
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
+fn parse_length(length: &str, max: usize) -> Result<usize, RespError> {
+    match length.parse() {
+        Ok(length) if length <= max => Ok(length),
+        _ => Err(protocol_error("invalid length")),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+}
+
+impl Reply {
+    fn encode(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Simple(message) => write!(f, "+{}\r\n", message),
+            Reply::Error(message) => write!(f, "-{}\r\n", message),
+            Reply::Integer(value) => write!(f, ":{}\r\n", value),
+            Reply::Bulk(Some(value)) => write!(f, "${}\r\n{}\r\n", value.len(), value),
+            Reply::Bulk(None) => write!(f, "$-1\r\n"),
+        }
+    }
+}
+
+fn execute(args: &[String], state: &AppState, session: &mut Session) -> Reply {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.to_ascii_uppercase(), args),
+        None => return Reply::Error("ERR empty command".into()),
+    };
+    let result = match (command.as_str(), args) {
+        ("PING", []) => Ok(Reply::Simple("PONG")),
+        ("PING", [message]) => Ok(Reply::Bulk(Some(message.clone()))),
+        // Redis clients send a username too, which means nothing here
+        ("AUTH", [api_key] | [_, api_key]) => auth(state, session, api_key),
+        ("GET", [key]) => get(state, session, key),
+        ("SET", [key, value]) => parse_integer(value)
+            .and_then(|value| i32::try_from(value).map_err(|_| not_an_integer()))
+            .and_then(|value| alter(state, session, key, CountRequest::Set { value }))
+            .map(|_| Reply::Simple("OK")),
+        ("INCR", [key]) => alter(state, session, key, CountRequest::Increment).map(Reply::Integer),
+        ("DECR", [key]) => alter(state, session, key, CountRequest::Decrement).map(Reply::Integer),
+        ("INCRBY", [key, delta]) => parse_integer(delta)
+            .and_then(|delta| alter(state, session, key, add(delta)?))
+            .map(Reply::Integer),
+        ("DECRBY", [key, delta]) => parse_integer(delta)
+            .and_then(|delta| delta.checked_neg().ok_or_else(not_an_integer))
+            .and_then(|delta| alter(state, session, key, add(delta)?))
+            .map(Reply::Integer),
+        ("PING" | "AUTH" | "GET" | "SET" | "INCR" | "DECR" | "INCRBY" | "DECRBY", _) => {
+            Err(format!(
+                "ERR wrong number of arguments for '{}' command",
+                command.to_ascii_lowercase()
+            ))
+        }
+        _ => Err(format!(
+            "ERR unknown command '{}'",
+            command
+                .chars()
+                .take(32)
+                .collect::<String>()
+                .to_ascii_lowercase()
+        )),
+    };
+    result.unwrap_or_else(Reply::Error)
+}
+
+/// Remember `api_key` for the connection's later commands, if it is one of
+/// the keys clients are limited under.
+fn auth(state: &AppState, session: &mut Session, api_key: &str) -> Result<Reply, String> {
+    let config = state.limiters.config();
+    if !matches!(config.key, KeyExtractor::ApiKey { .. }) {
+        return Err("ERR AUTH called without any API keys configured".into());
+    }
+    let previous = session.api_key.replace(api_key.to_owned());
+    match config.key.extract(&session.http_request(&config)) {
+        Ok(_) => Ok(Reply::Simple("OK")),
+        Err(_) => {
+            session.api_key = previous;
+            Err(key_error(KeyError::UnknownApiKey))
+        }
+    }
+}
+
+/// Apply `request` to the counter `key`.
+/// Read a counter, unless the peer is on the deny list.
+fn get(state: &AppState, session: &Session, key: &str) -> Result<Reply, String> {
+    let config = state.limiters.config();
+    let ip = config.key.client_ip(&session.http_request(&config));
+    if state.forbids(ip) {
+        return Err("ERR forbidden".into());
+    }
+    Ok(Reply::Bulk(
+        state
+            .counters
+            .get(key)
+            .map(|counter| counter.value().to_string()),
+    ))
+}
+
+fn alter(
+    state: &AppState,
+    session: &Session,
+    key: &str,
+    request: CountRequest,
+) -> Result<i64, String> {
+    let config = state.limiters.config();
+    let http = session.http_request(&config);
+    let client = config.key.extract(&http).map_err(key_error)?;
+    let ip = config.key.client_ip(&http);
+    let ClientId(id) = config.key.client_id(&client);
+    let origin = Origin {
+        // everyone shares the empty key when connections are not told apart
+        client: Some(id).filter(|id| !id.is_empty()),
+        request_id: None,
+    };
+
+    let counter = state
+        .counters
+        .get(key)
+        .ok_or_else(|| format!("ERR no counter named {}", key))?;
+    // charge the route the same change would take over HTTP
+    let route = if key == DEFAULT_COUNTER {
+        count::MUTATION_ROUTE
+    } else {
+        counters::MUTATION_ROUTE
+    };
+    match state.admit(&Method::POST, route, &client, ip) {
+        Ok(()) => {}
+        Err(Refusal::Forbidden) => return Err("ERR forbidden".into()),
+        Err(Refusal::RateLimited { retry_after }) => {
+            return Err(format!(
+                "ERR rate limited, retry after {} seconds",
+                whole_seconds(retry_after)
+            ))
+        }
+    }
+    try_alter_count(&counter, request, &origin)
+        .map(i64::from)
+        .map_err(|err: ServerError| {
+            let (_, _, message) = err.describe();
+            format!("ERR {}", message)
+        })
+}
+
+/// The request moving a counter by `delta`.
+fn add(delta: i64) -> Result<CountRequest, String> {
+    let amount = u32::try_from(delta.unsigned_abs()).map_err(|_| not_an_integer())?;
+    Ok(if delta < 0 {
+        CountRequest::Subtract { amount }
+    } else {
+        CountRequest::Add { amount }
+    })
+}
+
+fn key_error(err: KeyError) -> String {
+    match err {
+        KeyError::MissingApiKey => "NOAUTH Authentication required.".into(),
+        KeyError::UnknownApiKey => "WRONGPASS invalid API key".into(),
+    }
+}
+
+fn parse_integer(value: &str) -> Result<i64, String> {
+    value.parse().map_err(|_| not_an_integer())
+}
+
+fn not_an_integer() -> String {
+    "ERR value is not an integer or out of range".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::{Bounds, Counters};
+    use crate::limiter::{KeyedLimiters, Policy};
+    use crate::routes::counters::MUTATION_ROUTE;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn run(state: &AppState, command: &str) -> Reply {
+        run_as(state, &mut Session::default(), command)
+    }
+
+    fn run_as(state: &AppState, session: &mut Session, command: &str) -> Reply {
+        let args: Vec<String> = command.split_whitespace().map(str::to_owned).collect();
+        execute(&args, state, session)
+    }
+
+    fn from(ip: [u8; 4]) -> Session {
+        Session {
+            peer: Some(SocketAddr::from((IpAddr::from(ip), 6380))),
+            api_key: None,
+        }
+    }
+
+    fn with_apples(limits: &RateLimitConfig) -> AppState {
+        let counters = Counters::default();
+        counters.put("apples", 0, Bounds::default()).unwrap();
+        AppState::with_parts(counters, KeyedLimiters::new(limits))
+    }
+
+    #[tokio::test]
+    async fn commands_are_read_as_arrays_or_inline() {
+        let mut input: &[u8] = b"*2\r\n$4\r\nINCR\r\n$6\r\napples\r\nPING\r\n";
+        let command = read_command(&mut input).await.unwrap();
+        assert_eq!(command, Some(vec!["INCR".into(), "apples".into()]));
+        let command = read_command(&mut input).await.unwrap();
+        assert_eq!(command, Some(vec!["PING".into()]));
+        assert!(read_command(&mut input).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_arrays_are_refused() {
+        let mut input: &[u8] = b"*1000\r\n";
+        assert!(matches!(
+            read_command(&mut input).await,
+            Err(RespError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn replies_are_encoded() {
+        assert_eq!(Reply::Simple("OK").to_string(), "+OK\r\n");
+        assert_eq!(Reply::Integer(-3).to_string(), ":-3\r\n");
+        assert_eq!(Reply::Bulk(Some("12".into())).to_string(), "$2\r\n12\r\n");
+        assert_eq!(Reply::Bulk(None).to_string(), "$-1\r\n");
+    }
+
+    #[test]
+    fn commands_drive_counters() {
+        let state = with_apples(&RateLimitConfig::default());
+        assert_eq!(run(&state, "GET pears"), Reply::Bulk(None));
+        assert_eq!(run(&state, "INCR apples"), Reply::Integer(1));
+        assert_eq!(run(&state, "incrby apples 10"), Reply::Integer(11));
+        assert_eq!(run(&state, "DECRBY apples -4"), Reply::Integer(15));
+        assert_eq!(run(&state, "DECR apples"), Reply::Integer(14));
+        assert_eq!(run(&state, "GET apples"), Reply::Bulk(Some("14".into())));
+        assert_eq!(run(&state, "SET default 7"), Reply::Simple("OK"));
+        assert_eq!(state.counters.default_counter().value(), 7);
+        assert_eq!(run(&state, "PING"), Reply::Simple("PONG"));
+    }
+
+    #[test]
+    fn bad_commands_are_errors() {
+        let state = with_apples(&RateLimitConfig::default());
+        assert!(matches!(run(&state, "SET apples pears"), Reply::Error(_)));
+        assert!(matches!(
+            run(&state, "INCRBY apples 99999999999"),
+            Reply::Error(_)
+        ));
+        assert!(matches!(run(&state, "INCR"), Reply::Error(_)));
+        assert!(matches!(run(&state, "FLUSHALL"), Reply::Error(_)));
+        assert!(matches!(run(&state, "INCR two/words"), Reply::Error(_)));
+        // counters are only created over HTTP
+        assert_eq!(
+            run(&state, "INCR pears"),
+            Reply::Error("ERR no counter named pears".into())
+        );
+        assert!(state.counters.get("pears").is_none());
+        run(&state, "SET default 2147483647");
+        assert_eq!(
+            run(&state, "INCR default"),
+            Reply::Error("ERR Count is at its maximum value".into())
+        );
+    }
+
+    #[test]
+    fn changes_are_rate_limited_by_peer() {
+        let limits = RateLimitConfig::default()
+            .route(MUTATION_ROUTE, Policy::gcra(1, Duration::from_secs(60)));
+        let limits = limits.key(KeyExtractor::PeerIp);
+        let state = with_apples(&limits);
+        let mut peer = from([10, 0, 0, 1]);
+        assert_eq!(run_as(&state, &mut peer, "INCR apples"), Reply::Integer(1));
+        assert_eq!(
+            run_as(&state, &mut peer, "INCR apples"),
+            Reply::Error("ERR rate limited, retry after 60 seconds".into())
+        );
+        let mut other = from([10, 0, 0, 2]);
+        assert_eq!(run_as(&state, &mut other, "INCR apples"), Reply::Integer(2));
+        // reads are not limited
+        assert_eq!(
+            run_as(&state, &mut peer, "GET apples"),
+            Reply::Bulk(Some("2".into()))
+        );
+    }
+
+    #[test]
+    fn the_default_counter_is_limited_like_its_own_route() {
+        let limits = RateLimitConfig::default().route(
+            count::MUTATION_ROUTE,
+            Policy::gcra(1, Duration::from_secs(60)),
+        );
+        let state = with_apples(&limits);
+        assert_eq!(run(&state, "INCR default"), Reply::Integer(1));
+        assert_eq!(
+            run(&state, "INCR default"),
+            Reply::Error("ERR rate limited, retry after 60 seconds".into())
+        );
+        assert_eq!(run(&state, "INCR apples"), Reply::Integer(1));
+        assert_eq!(run(&state, "INCR apples"), Reply::Integer(2));
+    }
+
+    #[test]
+    fn denied_peers_cannot_read_or_change_counters() {
+        let limits = RateLimitConfig::default().deny("10.0.0.0/8".parse().unwrap());
+        let state = with_apples(&limits);
+        assert_eq!(
+            run_as(&state, &mut from([10, 0, 0, 1]), "INCR apples"),
+            Reply::Error("ERR forbidden".into())
+        );
+        assert_eq!(state.counters.get("apples").unwrap().value(), 0);
+        assert_eq!(
+            run_as(&state, &mut from([10, 0, 0, 1]), "GET apples"),
+            Reply::Error("ERR forbidden".into())
+        );
+        assert_eq!(
+            run_as(&state, &mut from([192, 168, 0, 1]), "GET apples"),
+            Reply::Bulk(Some("0".into()))
+        );
+    }
+
+    #[test]
+    fn changes_need_an_api_key_when_clients_are_keyed_on_them() {
+        let limits = RateLimitConfig::default()
+            .key(KeyExtractor::api_key(["secret"]))
+            .route(MUTATION_ROUTE, Policy::gcra(1, Duration::from_secs(60)));
+        let state = with_apples(&limits);
+        let mut session = Session::default();
+        assert_eq!(
+            run_as(&state, &mut session, "INCR apples"),
+            Reply::Error("NOAUTH Authentication required.".into())
+        );
+        assert_eq!(
+            run_as(&state, &mut session, "AUTH guess"),
+            Reply::Error("WRONGPASS invalid API key".into())
+        );
+        assert_eq!(
+            run_as(&state, &mut session, "AUTH default secret"),
+            Reply::Simple("OK")
+        );
+        assert_eq!(
+            run_as(&state, &mut session, "INCR apples"),
+            Reply::Integer(1)
+        );
+        // a wrong key later on leaves the connection authenticated as before
+        run_as(&state, &mut session, "AUTH guess");
+        assert_eq!(
+            run_as(&state, &mut session, "INCR apples"),
+            Reply::Error("ERR rate limited, retry after 60 seconds".into())
+        );
+    }
+
+    #[test]
+    fn auth_is_refused_without_api_keys() {
+        let state = AppState::new();
+        assert!(matches!(run(&state, "AUTH secret"), Reply::Error(_)));
+    }
+}
@@ -1,2 +1,23 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use client::{ErrorKind, ErrorResponse};
+
+pub mod admin;
 pub mod count;
+pub mod counters;
 pub mod health_check;
+pub mod metrics;
+pub mod sse;
+
+/// A JSON error body, for errors which cannot clear up on their own.
+pub(crate) fn error_response(status: StatusCode, error: ErrorKind, message: String) -> Response {
+    let body = ErrorResponse {
+        error,
+        message,
+        retry_after: None,
+    };
+    (status, Json(body)).into_response()
+}
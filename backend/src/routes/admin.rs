@@ -0,0 +1,326 @@
+use crate::{
+    limiter::{is_route, key::ClientId},
+    routes::error_response,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use client::{
+    AccessListsResponse, AccessRule, ErrorKind, KeyOverride, KeyState, PoliciesResponse,
+    RoutePolicy, RouteQuery, TopKeysQuery, TopKeysResponse, TopUpRequest,
+};
+use ipnet::IpNet;
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_TOP_KEYS: usize = 10;
+const MAX_TOP_KEYS: usize = 1000;
+
+/// Let requests through only if they carry one of `tokens` as a bearer token.
+pub async fn authorize<B>(
+    tokens: Arc<Vec<String>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if tokens.iter().any(|token| same(token, presented)) => {
+            next.run(request).await
+        }
+        _ => {
+            let mut response = error_response(
+                StatusCode::UNAUTHORIZED,
+                ErrorKind::Unauthorized,
+                "Missing or unknown admin token".into(),
+            );
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            response
+        }
+    }
+}
+
+// compare in constant time, so the time taken gives nothing away about the token
+fn same(token: &str, presented: &str) -> bool {
+    token.len() == presented.len()
+        && token
+            .bytes()
+            .zip(presented.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The configuration file being watched and the outcome of recent reloads.
+pub async fn config_status(Extension(state): Extension<AppState>) -> Response {
+    match &state.reloader {
+        Some(reloader) => Json(reloader.status()).into_response(),
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            ErrorKind::NotFound,
+            "the configuration is not reloadable".into(),
+        ),
+    }
+}
+
+pub async fn top_keys(
+    Extension(state): Extension<AppState>,
+    Query(query): Query<TopKeysQuery>,
+) -> Json<TopKeysResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_KEYS).min(MAX_TOP_KEYS);
+    let config = state.limiters.config();
+    let mut keys = state.limiters.top_keys(query.route.as_deref(), limit);
+    for usage in &mut keys {
+        let ClientId(id) = config.key.client_id(&usage.key);
+        usage.key = id;
+    }
+    Json(TopKeysResponse { keys })
+}
+
+pub async fn get_key(Extension(state): Extension<AppState>, Path(name): Path<String>) -> Response {
+    let (key, id) = named_key(&state, &name);
+    key_state(&state, &key, &id)
+}
+
+/// Forget a key's limiters, so its next request starts with a full quota.
+pub async fn reset_key(
+    Extension(state): Extension<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<RouteQuery>,
+) -> Response {
+    let (key, id) = named_key(&state, &name);
+    match state.limiters.reset(&key, query.route.as_deref()) {
+        0 => key_not_found(&id),
+        reset => {
+            log::info!("admin reset {} limiters for {:?}", reset, id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+pub async fn top_up_key(
+    Extension(state): Extension<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<TopUpRequest>,
+) -> Response {
+    let (key, id) = named_key(&state, &name);
+    if request.requests == 0 {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorKind::InvalidRequest,
+            "Top up by at least one request".into(),
+        );
+    }
+    let route = request.route.as_deref();
+    if state.limiters.top_up(&key, route, request.requests) == 0 {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            ErrorKind::NotFound,
+            format!("Route {:?} is not limited", route.unwrap_or_default()),
+        );
+    }
+    log::info!("admin topped up {:?} by {} requests", id, request.requests);
+    key_state(&state, &key, &id)
+}
+
+/// Ban or exempt a key for a while.
+pub async fn put_override(
+    Extension(state): Extension<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<KeyOverride>,
+) -> Response {
+    let (key, id) = named_key(&state, &name);
+    if request.seconds == 0 {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorKind::InvalidRequest,
+            "Overrides last at least one second".into(),
+        );
+    }
+    let duration = Duration::from_secs(request.seconds);
+    if !state
+        .limiters
+        .set_override(&key, request.kind, duration, Instant::now())
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorKind::InvalidRequest,
+            format!("Overrides cannot last {} seconds", request.seconds),
+        );
+    }
+    log::info!(
+        "admin set {:?} on {:?} for {:?}",
+        request.kind,
+        id,
+        duration
+    );
+    key_state(&state, &key, &id)
+}
+
+pub async fn delete_override(
+    Extension(state): Extension<AppState>,
+    Path(name): Path<String>,
+) -> Response {
+    let (key, id) = named_key(&state, &name);
+    if state.limiters.clear_override(&key, Instant::now()) {
+        log::info!("admin lifted the override on {:?}", id);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        key_not_found(&id)
+    }
+}
+
+pub async fn list_policies(Extension(state): Extension<AppState>) -> Json<PoliciesResponse> {
+    let config = state.limiters.config();
+    let mut policies: Vec<_> = config
+        .routes
+        .iter()
+        .map(|(route, policy)| RoutePolicy {
+            route: route.clone(),
+            policy: *policy,
+        })
+        .collect();
+    policies.sort_by(|a, b| a.route.cmp(&b.route));
+    Json(PoliciesResponse { policies })
+}
+
+/// Limit a route, or change how it is limited, until the configuration is
+/// next reloaded.
+pub async fn post_policy(
+    Extension(state): Extension<AppState>,
+    Json(request): Json<RoutePolicy>,
+) -> Response {
+    let policy = request.policy;
+    let invalid = if !is_route(&request.route) {
+        Some(format!("{:?} is not a route", request.route))
+    } else {
+        policy.validate().err()
+    };
+    if let Some(message) = invalid {
+        return error_response(StatusCode::BAD_REQUEST, ErrorKind::InvalidRequest, message);
+    }
+
+    let status = if state.limiters.set_policy(&request.route, policy) {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    log::info!("admin limited {} with {:?}", request.route, request.policy);
+    (status, Json(request)).into_response()
+}
+
+pub async fn list_access(Extension(state): Extension<AppState>) -> Json<AccessListsResponse> {
+    let config = state.limiters.config();
+    let strings = |ranges: &[IpNet]| ranges.iter().map(ToString::to_string).collect();
+    Json(AccessListsResponse {
+        allow: strings(&config.allow),
+        deny: strings(&config.deny),
+    })
+}
+
+/// Allow or deny a range until the configuration is next reloaded.
+pub async fn post_access(
+    Extension(state): Extension<AppState>,
+    Json(rule): Json<AccessRule>,
+) -> Response {
+    let range = match parse_range(&rule.range) {
+        Some(range) => range,
+        None => return invalid_range(&rule.range),
+    };
+    let status = if state.limiters.add_access(rule.list, range) {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    log::info!("admin added {} to the {:?} list", range, rule.list);
+    (status, Json(rule)).into_response()
+}
+
+pub async fn delete_access(
+    Extension(state): Extension<AppState>,
+    Query(rule): Query<AccessRule>,
+) -> Response {
+    let range = match parse_range(&rule.range) {
+        Some(range) => range,
+        None => return invalid_range(&rule.range),
+    };
+    if state.limiters.remove_access(rule.list, range) {
+        log::info!("admin removed {} from the {:?} list", range, rule.list);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        error_response(
+            StatusCode::NOT_FOUND,
+            ErrorKind::NotFound,
+            format!("{} is not on the {:?} list", range, rule.list),
+        )
+    }
+}
+
+// a lone address is taken as a range of one
+fn parse_range(range: &str) -> Option<IpNet> {
+    range
+        .parse()
+        .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+        .ok()
+}
+
+fn invalid_range(range: &str) -> Response {
+    error_response(
+        StatusCode::BAD_REQUEST,
+        ErrorKind::InvalidRequest,
+        format!("{:?} is not a CIDR range, e.g. 10.0.0.0/8", range),
+    )
+}
+
+// keys are shown, logged and looked up by `ClientId`, so API keys never leave
+// the configuration; the key itself is still accepted in paths
+fn named_key(state: &AppState, name: &str) -> (String, String) {
+    let config = state.limiters.config();
+    let key = config.key.named(name);
+    let ClientId(id) = config.key.client_id(&key);
+    (key, id)
+}
+
+fn key_state(state: &AppState, key: &str, id: &str) -> Response {
+    match state.limiters.key_state(key, Instant::now()) {
+        Some(key_state) => Json(KeyState {
+            key: id.to_owned(),
+            ..key_state
+        })
+        .into_response(),
+        None => key_not_found(id),
+    }
+}
+
+fn key_not_found(id: &str) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        ErrorKind::NotFound,
+        format!("Key {:?} has no rate-limit state", id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(same("secret", "secret"));
+        assert!(!same("secret", "secreT"));
+        assert!(!same("secret", "secret2"));
+        assert!(!same("secret", ""));
+    }
+}
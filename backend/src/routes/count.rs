@@ -1,25 +1,91 @@
-use std::{sync::atomic::Ordering, str::FromStr};
+use std::str::FromStr;
 use std::borrow::Cow;
 use std::ops::ControlFlow;
+use std::time::Instant;
+use crate::counter::{BoundsError, Counter};
+use crate::history::Origin;
+use crate::limiter::{key::{ClientIp, ClientKey}, layer::whole_seconds, Refusal};
+use crate::routes::error_response;
+use crate::shutdown::Shutdown;
 use crate::state::AppState;
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade, CloseFrame},
-        Path,
+        ws::{close_code, Message, WebSocket, WebSocketUpgrade, CloseFrame},
+        Path, Query,
     },
-    http::StatusCode,
+    http::{Method, StatusCode},
     response::{IntoResponse, Response},
-    Extension,
+    Extension, Json,
 };
-use client::{CountRequest, CountResponse, Direction};
-use futures::{sink::SinkExt, stream::StreamExt};
+use client::{
+    Command, CountRequest, CountResponse, CountStats, Direction, ErrorKind, HistoryQuery, HistoryResponse, Reply,
+    SocketCommand, SocketReply,
+};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The route whose policy changes to the default counter made outside HTTP,
+/// as over `/ws/count`, are charged to.
+pub(crate) const MUTATION_ROUTE: &str = "/api/count/:direction";
 
 #[derive(Debug, PartialEq)]
-enum ServerError {
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum ServerError {
     MaximumValueError,
+    MinimumValueError,
+    ValueMismatchError { current: i32 },
     SerialisationError,
 }
 
+impl ServerError {
+    /// The status, kind and message this error is reported with.
+    pub(crate) fn describe(&self) -> (StatusCode, ErrorKind, String) {
+        match self {
+            ServerError::MaximumValueError => (
+                StatusCode::CONFLICT,
+                ErrorKind::OutOfBounds,
+                "Count is at its maximum value".into(),
+            ),
+            ServerError::MinimumValueError => (
+                StatusCode::CONFLICT,
+                ErrorKind::OutOfBounds,
+                "Count is at its minimum value".into(),
+            ),
+            ServerError::ValueMismatchError { current } => (
+                StatusCode::PRECONDITION_FAILED,
+                ErrorKind::PreconditionFailed,
+                format!("Count is {}, not the expected value", current),
+            ),
+            ServerError::SerialisationError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorKind::Internal,
+                "Failed to build the response".into(),
+            ),
+        }
+    }
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        match self {
+            ServerError::SerialisationError => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            _ => {
+                let (status, kind, message) = self.describe();
+                error_response(status, kind, message)
+            }
+        }
+    }
+}
+
+impl From<ServerError> for Reply {
+    fn from(err: ServerError) -> Self {
+        let (_, error, message) = err.describe();
+        Reply::Error { error, message }
+    }
+}
+
 pub async fn get_count(Extension(state): Extension<AppState>) -> impl IntoResponse {
     match try_get_count(&state) {
         Ok(json) => Ok(json),
@@ -28,8 +94,8 @@ pub async fn get_count(Extension(state): Extension<AppState>) -> impl IntoRespon
 }
 
 fn try_get_count(state: &AppState) -> Result<String, ServerError> {
-    let count = state.count.load(Ordering::Relaxed);
-    match serde_json::to_string(&CountResponse { count }) {
+    let count = state.counters.default_counter().value();
+    match serde_json::to_string(&CountResponse { count, stats: None }) {
         Ok(j) => Ok(j),
         Err(_) => Err(ServerError::SerialisationError),
     }
@@ -37,37 +103,120 @@ fn try_get_count(state: &AppState) -> Result<String, ServerError> {
 
 pub async fn post_count(
     Extension(state): Extension<AppState>,
+    origin: Origin,
     Path(direction): Path<String>,
 ) -> impl IntoResponse {
-    if let Ok(request) = CountRequest::from_str(&direction) {
-        match try_alter_count(&state, request) {
-            Ok(_) => StatusCode::OK,
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    if let Ok(direction) = Direction::from_str(&direction) {
+        match try_alter_count(&state.counters.default_counter(), direction.into(), &origin) {
+            Ok(_) => StatusCode::OK.into_response(),
+            Err(err) => err.into_response(),
         }
     } else {
-        StatusCode::BAD_REQUEST
+        StatusCode::BAD_REQUEST.into_response()
     }
 }
 
-fn try_alter_count(state: &AppState, request: CountRequest) -> Result<(), ServerError> {
-    match request.direction {
-        Direction::Increment => {
-            if state.count.load(Ordering::Relaxed) == i32::MAX {
-                return Err(ServerError::MaximumValueError);
+pub async fn post_count_request(
+    Extension(state): Extension<AppState>,
+    origin: Origin,
+    Json(request): Json<CountRequest>,
+) -> Response {
+    match try_alter_count(&state.counters.default_counter(), request, &origin) {
+        Ok(count) => Json(CountResponse { count, stats: None }).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+pub async fn get_count_history(
+    Extension(state): Extension<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<HistoryResponse> {
+    Json(state.counters.default_counter().history().page(&query))
+}
+
+pub async fn get_count_stats(Extension(state): Extension<AppState>) -> Json<CountStats> {
+    Json(state.counters.default_counter().stats().at(Instant::now()))
+}
+
+/// Apply `request` to `counter`, recording the change in its history on behalf of `origin`.
+pub(crate) fn try_alter_count(counter: &Counter, request: CountRequest, origin: &Origin) -> Result<i32, ServerError> {
+    counter.update(|count, bounds| {
+        // only moves are subject to the overflow policy; a value set outright must be in bounds
+        let next = match request {
+            CountRequest::Increment => bounds.apply(count, 1),
+            CountRequest::Decrement => bounds.apply(count, -1),
+            CountRequest::Add { amount } => bounds.apply(count, amount as i64),
+            CountRequest::Subtract { amount } => bounds.apply(count, -(amount as i64)),
+            CountRequest::Set { value } => bounds.check(value as i64),
+            CountRequest::Reset => bounds.check(0),
+            CountRequest::CompareAndSwap { expected, new } => {
+                if count != expected as i64 {
+                    let current = count.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+                    return Err(ServerError::ValueMismatchError { current });
+                }
+                bounds.check(new as i64)
             }
-            state.count.fetch_add(1, Ordering::Relaxed)
         }
-        Direction::Decrement => state.count.fetch_sub(1, Ordering::Relaxed),
-    };
-    Ok(())
+        .map_err(|err| match err {
+            BoundsError::AboveMaximum => ServerError::MaximumValueError,
+            BoundsError::BelowMinimum => ServerError::MinimumValueError,
+        })?;
+        // record while the counter is still locked, so events are in the order they happened
+        let resulting = next.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        counter.history().record(next - count, resulting, origin);
+        Ok(next)
+    })
+}
+
+#[derive(Deserialize)]
+pub struct WsOptions {
+    /// Send the counter's stats along with every count.
+    #[serde(default)]
+    stats: bool,
 }
 
-pub async fn ws_handler(ws: WebSocketUpgrade, Extension(state): Extension<AppState>) -> Response {
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Extension(state): Extension<AppState>,
+    origin: Origin,
+    client: Option<Extension<ClientKey>>,
+    ip: Option<Extension<ClientIp>>,
+    Query(options): Query<WsOptions>,
+) -> Response {
     log::info!("client connected");
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+    let metrics = state.metrics.clone();
+    let shutdown = state.shutdown.clone();
+    let session = Session {
+        counter: state.counters.default_counter(),
+        // changes made over the socket are recorded under the id of the upgrade request
+        origin,
+        client: client.map(|Extension(ClientKey(key))| key),
+        ip: ip.map(|Extension(ClientIp(ip))| ip),
+        state,
+        stats: options.stats,
+        subscribed: true,
+    };
+    ws.on_upgrade(move |socket| async move {
+        let _open = metrics.track_websocket();
+        let _draining = shutdown.guard();
+        handle_socket(socket, session, shutdown).await
+    })
+}
+
+/// What a socket remembers between the commands it is sent.
+struct Session {
+    counter: Arc<Counter>,
+    origin: Origin,
+    // who to charge the changes made over the socket to; without a key, as when
+    // an API key is needed but none was given, the socket can only watch
+    client: Option<String>,
+    ip: Option<IpAddr>,
+    state: AppState,
+    stats: bool,
+    subscribed: bool,
 }
 
-async fn handle_socket<>(mut socket: WebSocket, state: AppState) {
+async fn handle_socket<>(mut socket: WebSocket, mut session: Session, shutdown: Shutdown) {
     // send a ping to ensure the connection upgrade succeeded
     if socket.send(Message::Ping(vec![1, 2, 3])).await.is_ok() {
         log::trace!("sent startup ping");
@@ -76,77 +225,87 @@ async fn handle_socket<>(mut socket: WebSocket, state: AppState) {
         return;
     }
 
-    // we need to both send and receive messages
-    let (mut sender, mut receiver) = socket.split();
-
-    let _send_task = tokio::spawn(async move {
-        let mut latest_count = state.count.load(Ordering::Relaxed);
-        
-        // on connection, send initial state
-        let response_json = serde_json::to_string(&CountResponse {
-            count: latest_count,
-        });
-        match response_json {
-            Ok(j) => {
-                // send message
-                if sender
-                    .send(Message::Text(j))
-                    .await
-                    .is_err() {
+    // subscribe before reading the initial state, so no change can slip between them
+    let mut changes = session.counter.subscribe();
+
+    // on connection send the initial state, then every change as it is published
+    if send_count(&mut socket, &session, &mut changes).await.is_err() {
+        log::error!("client disconnected during transfer");
+        return;
+    }
+
+    let (code, reason) = loop {
+        tokio::select! {
+            _ = shutdown.triggered() => {
+                break (close_code::AWAY, "server shutting down");
+            }
+            // park until the count changes or a command arrives; an idle socket costs nothing
+            changed = changes.changed(), if session.subscribed => {
+                if changed.is_err() {
+                    break (close_code::NORMAL, "hanging up");
+                }
+                if send_count(&mut socket, &session, &mut changes).await.is_err() {
                     log::error!("client disconnected during transfer");
+                    return;
                 }
             }
-            Err(_) => log::error!("abject failure to build JSON"),
-        }
-
-        // infinite loop to dispatch state changes to socket
-        loop {
-            let count = state.count.load(Ordering::Relaxed);
-            if count != latest_count {
-                latest_count = count;
-
-                let response_json = serde_json::to_string(&CountResponse {
-                    count,
-                });
-                match response_json {
-                    Ok(j) => {
-                        // send message
-                        if sender
-                            .send(Message::Text(j))
-                            .await
-                            .is_err() {
-                            log::error!("client disconnected during transfer");
-                            break;
-                        }
-                    }
-                    Err(_) => log::error!("abject failure to build JSON"),
+            msg = socket.recv() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => return,
+                };
+                let was_subscribed = session.subscribed;
+                let reply = match process_message(msg, &mut session) {
+                    ControlFlow::Break(()) => return,
+                    ControlFlow::Continue(Some(reply)) => reply,
+                    ControlFlow::Continue(None) => continue,
+                };
+                if send_json(&mut socket, &reply).await.is_err() {
+                    log::error!("client disconnected during transfer");
+                    return;
+                }
+                // catch a resubscribed client up with whatever it missed
+                if session.subscribed && !was_subscribed
+                    && send_count(&mut socket, &session, &mut changes).await.is_err() {
+                    log::error!("client disconnected during transfer");
+                    return;
                 }
             }
-            
-            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         }
+    };
 
-        sender.send(Message::Close(Some(CloseFrame {
-            code: axum::extract::ws::close_code::NORMAL,
-            reason: Cow::from("hanging up"),
-        }))).await
-    });
+    let _ = socket.send(Message::Close(Some(CloseFrame {
+        code,
+        reason: Cow::from(reason),
+    }))).await;
+}
 
-    // spawn a task which receives messages from the socket
-    let _recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
-            if process_message(msg).is_break() {
-                break;
-            }
-        }
-    });
+async fn send_count(
+    socket: &mut WebSocket,
+    session: &Session,
+    changes: &mut watch::Receiver<i32>,
+) -> Result<(), axum::Error> {
+    let count = *changes.borrow_and_update();
+    let stats = session.stats.then(|| session.counter.stats().at(Instant::now()));
+    send_json(socket, &CountResponse { count, stats }).await
+}
 
+async fn send_json(socket: &mut WebSocket, message: &impl Serialize) -> Result<(), axum::Error> {
+    match serde_json::to_string(message) {
+        Ok(j) => socket.send(Message::Text(j)).await,
+        Err(_) => {
+            log::error!("abject failure to build JSON");
+            Ok(())
+        }
+    }
 }
 
-fn process_message(msg: Message) -> ControlFlow<(), ()> {
+/// Handle one message from the client, returning the reply to send if it was a command.
+fn process_message(msg: Message, session: &mut Session) -> ControlFlow<(), Option<SocketReply>> {
     match msg {
-        Message::Text(_) => {
-            log::info!("client sent text data");
+        Message::Text(text) => {
+            log::trace!("client sent {}", text);
+            return ControlFlow::Continue(Some(execute(&text, session)));
         }
         Message::Binary(_) => {
             log::error!("client sent binary data");
@@ -162,12 +321,80 @@ fn process_message(msg: Message) -> ControlFlow<(), ()> {
             return ControlFlow::Break(());
         }
     }
-    ControlFlow::Continue(())
+    ControlFlow::Continue(None)
+}
+
+fn execute(text: &str, session: &mut Session) -> SocketReply {
+    let SocketCommand { id, command } = match serde_json::from_str(text) {
+        Ok(command) => command,
+        Err(err) => {
+            // answer with the id if there is one, so the client knows which command failed
+            let id = serde_json::from_str::<serde_json::Value>(text)
+                .ok()
+                .and_then(|value| value.get("id")?.as_u64());
+            return SocketReply {
+                id,
+                reply: Reply::Error {
+                    error: ErrorKind::InvalidRequest,
+                    message: err.to_string(),
+                },
+            };
+        }
+    };
+
+    let reply = match command {
+        Command::Increment => alter_count(session, CountRequest::Increment),
+        Command::Decrement => alter_count(session, CountRequest::Decrement),
+        Command::Get => Reply::Count {
+            count: session.counter.value(),
+        },
+        Command::Subscribe => {
+            session.subscribed = true;
+            Reply::Subscribed
+        }
+        Command::Unsubscribe => {
+            session.subscribed = false;
+            Reply::Unsubscribed
+        }
+        Command::Ping => Reply::Pong,
+    };
+    SocketReply { id: Some(id), reply }
+}
+
+fn alter_count(session: &Session, request: CountRequest) -> Reply {
+    let client = match &session.client {
+        Some(client) => client,
+        None => {
+            return Reply::Error { error: ErrorKind::Unauthorized, message: "Missing API Key".into() };
+        }
+    };
+    // the upgrade was only checked once, so every change is checked as it comes
+    match session.state.admit(&Method::POST, MUTATION_ROUTE, client, session.ip) {
+        Ok(()) => {}
+        Err(Refusal::RateLimited { retry_after }) => {
+            return Reply::RateLimited { retry_after: whole_seconds(retry_after) };
+        }
+        Err(Refusal::Forbidden) => {
+            return Reply::Error { error: ErrorKind::Forbidden, message: "Forbidden".into() };
+        }
+    }
+    match try_alter_count(&session.counter, request, &session.origin) {
+        Ok(count) => Reply::Count { count },
+        Err(err) => err.into(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::counter::{Bounds, Counters};
+    use client::OverflowPolicy;
+    use crate::limiter::{KeyedLimiters, Policy, RateLimitConfig};
+    use std::time::Duration;
+
+    fn set_count(counter: &Counter, count: i32) {
+        counter.update::<()>(|_, _| Ok(count as i64)).unwrap();
+    }
     
     #[test]
     fn try_get_count_returns_json_count_response() {
@@ -178,27 +405,158 @@ mod tests {
     
     #[test]
     fn try_alter_count_increments_then_decrements_state() {
-        let state = AppState::new();
-        try_alter_count(&state, CountRequest { direction: Direction::Increment}).expect("failed to incremend state");
-        assert!(state.count.load(Ordering::Acquire) == 1);        
-        try_alter_count(&state, CountRequest { direction: Direction::Decrement }).expect("failed to decrement state");
-        assert!(state.count.load(Ordering::Acquire) == 0);
+        let counter = Counter::default();
+        try_alter_count(&counter, CountRequest::Increment, &Origin::default()).expect("failed to incremend state");
+        assert!(counter.value() == 1);        
+        try_alter_count(&counter, CountRequest::Decrement, &Origin::default()).expect("failed to decrement state");
+        assert!(counter.value() == 0);
     }
 
     #[test]
     fn try_alter_count_fails_to_increment_at_maxiumum_value() {
-        let state = AppState::new();
-        state.count.swap(i32::MAX, Ordering::Acquire);
-        let result = try_alter_count(&state, CountRequest { direction: Direction::Increment});
+        let counter = Counter::default();
+        set_count(&counter, i32::MAX);
+        let result = try_alter_count(&counter, CountRequest::Increment, &Origin::default());
         let expected = Err(ServerError::MaximumValueError);
         assert_eq!(result, expected);
     }
 
     #[test]
     fn try_alter_count_decrements_maximum_value() {
-        let state = AppState::new();
-        state.count.swap(i32::MAX, Ordering::Acquire);
-        try_alter_count(&state, CountRequest { direction: Direction::Decrement }).expect("failed to decrement state");
-        assert_eq!(state.count.load(Ordering::Acquire), i32::MAX - 1);
+        let counter = Counter::default();
+        set_count(&counter, i32::MAX);
+        try_alter_count(&counter, CountRequest::Decrement, &Origin::default()).expect("failed to decrement state");
+        assert_eq!(counter.value(), i32::MAX - 1);
+    }
+
+    #[test]
+    fn try_alter_count_fails_to_decrement_at_minimum_value() {
+        let counter = Counter::default();
+        set_count(&counter, i32::MIN);
+        let result = try_alter_count(&counter, CountRequest::Decrement, &Origin::default());
+        assert_eq!(result, Err(ServerError::MinimumValueError));
+    }
+
+    #[test]
+    fn try_alter_count_applies_every_operation() {
+        let counter = Counter::default();
+        assert_eq!(try_alter_count(&counter, CountRequest::Add { amount: 5 }, &Origin::default()), Ok(5));
+        assert_eq!(try_alter_count(&counter, CountRequest::Subtract { amount: 7 }, &Origin::default()), Ok(-2));
+        assert_eq!(try_alter_count(&counter, CountRequest::Set { value: 40 }, &Origin::default()), Ok(40));
+        assert_eq!(try_alter_count(&counter, CountRequest::CompareAndSwap { expected: 40, new: 42 }, &Origin::default()), Ok(42));
+        assert_eq!(try_alter_count(&counter, CountRequest::Reset, &Origin::default()), Ok(0));
+    }
+
+    #[test]
+    fn try_alter_count_fails_compare_and_swap_on_mismatch() {
+        let counter = Counter::default();
+        set_count(&counter, 3);
+        let result = try_alter_count(&counter, CountRequest::CompareAndSwap { expected: 2, new: 5 }, &Origin::default());
+        assert_eq!(result, Err(ServerError::ValueMismatchError { current: 3 }));
+        assert_eq!(counter.value(), 3);
+    }
+
+    #[test]
+    fn try_alter_count_fails_to_add_past_maximum_value() {
+        let counter = Counter::default();
+        set_count(&counter, i32::MAX - 1);
+        let result = try_alter_count(&counter, CountRequest::Add { amount: 2 }, &Origin::default());
+        assert_eq!(result, Err(ServerError::MaximumValueError));
+    }
+
+    #[test]
+    fn try_alter_count_only_saturates_moves() {
+        let counters = Counters::default();
+        counters.put("seats", 5, Bounds::new(0, 10).overflow(OverflowPolicy::Saturate)).unwrap();
+        let counter = counters.get("seats").unwrap();
+        let origin = Origin::default();
+        assert_eq!(try_alter_count(&counter, CountRequest::Add { amount: 20 }, &origin), Ok(10));
+        assert_eq!(try_alter_count(&counter, CountRequest::Set { value: 11 }, &origin), Err(ServerError::MaximumValueError));
+        let swap = CountRequest::CompareAndSwap { expected: 10, new: -1 };
+        assert_eq!(try_alter_count(&counter, swap, &origin), Err(ServerError::MinimumValueError));
+        assert_eq!(counter.value(), 10);
+    }
+
+    #[test]
+    fn try_alter_count_records_applied_changes() {
+        let counter = Counter::default();
+        let origin = Origin {
+            client: Some("10.0.0.1".into()),
+            request_id: Some("abc".into()),
+        };
+        try_alter_count(&counter, CountRequest::Add { amount: 5 }, &origin).unwrap();
+        try_alter_count(&counter, CountRequest::CompareAndSwap { expected: 0, new: 1 }, &origin).unwrap_err();
+        try_alter_count(&counter, CountRequest::Set { value: 2 }, &Origin::default()).unwrap();
+
+        let events = counter.history().page(&HistoryQuery::default()).events;
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].delta, events[0].count), (5, 5));
+        assert_eq!(events[0].client.as_deref(), Some("10.0.0.1"));
+        assert_eq!(events[0].request_id.as_deref(), Some("abc"));
+        assert_eq!((events[1].delta, events[1].count), (-3, 2));
+        assert_eq!(events[1].client, None);
+    }
+
+    fn session() -> Session {
+        session_with(AppState::new())
+    }
+
+    fn session_with(state: AppState) -> Session {
+        Session {
+            counter: state.counters.default_counter(),
+            origin: Origin::default(),
+            client: Some("10.0.0.1".into()),
+            ip: None,
+            state,
+            stats: false,
+            subscribed: true,
+        }
+    }
+
+    #[test]
+    fn execute_runs_socket_commands() {
+        let mut session = session();
+        let reply = execute(r#"{"id":1,"command":"Increment"}"#, &mut session);
+        assert_eq!(reply, SocketReply { id: Some(1), reply: Reply::Count { count: 1 } });
+        assert_eq!(session.counter.value(), 1);
+
+        let reply = execute(r#"{"id":2,"command":"Unsubscribe"}"#, &mut session);
+        assert_eq!(reply.reply, Reply::Unsubscribed);
+        assert!(!session.subscribed);
+
+        let reply = execute(r#"{"id":3,"command":"Ping"}"#, &mut session);
+        assert_eq!(reply, SocketReply { id: Some(3), reply: Reply::Pong });
+    }
+
+    #[test]
+    fn execute_replies_with_typed_errors() {
+        let mut session = session();
+        set_count(&session.counter, i32::MIN);
+        let reply = execute(r#"{"id":1,"command":"Decrement"}"#, &mut session);
+        assert!(matches!(reply.reply, Reply::Error { error: ErrorKind::OutOfBounds, .. }));
+
+        let reply = execute(r#"{"id":2,"command":"Explode"}"#, &mut session);
+        assert_eq!(reply.id, Some(2));
+        assert!(matches!(reply.reply, Reply::Error { error: ErrorKind::InvalidRequest, .. }));
+
+        let reply = execute("not json", &mut session);
+        assert_eq!(reply.id, None);
+    }
+
+    #[test]
+    fn socket_changes_are_rate_limited() {
+        let limits = RateLimitConfig::default()
+            .route(MUTATION_ROUTE, Policy::gcra(1, Duration::from_secs(60)));
+        let mut session = session_with(AppState::with_parts(Counters::default(), KeyedLimiters::new(&limits)));
+        let reply = execute(r#"{"id":1,"command":"Increment"}"#, &mut session);
+        assert_eq!(reply.reply, Reply::Count { count: 1 });
+
+        let reply = execute(r#"{"id":2,"command":"Decrement"}"#, &mut session);
+        assert_eq!(reply.reply, Reply::RateLimited { retry_after: 60 });
+        assert_eq!(session.counter.value(), 1);
+
+        // reading the count costs nothing
+        let reply = execute(r#"{"id":3,"command":"Get"}"#, &mut session);
+        assert_eq!(reply.reply, Reply::Count { count: 1 });
     }
 }
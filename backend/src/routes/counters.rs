@@ -0,0 +1,183 @@
+use crate::counter::{Bounds, TooManyCounters, DEFAULT_COUNTER};
+use crate::history::Origin;
+use crate::routes::{count::try_alter_count, error_response};
+use crate::state::AppState;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use client::{
+    CountRequest, CounterResponse, CountersResponse, Direction, ErrorKind, PutCounterRequest,
+};
+use std::str::FromStr;
+
+const MAX_NAME_LENGTH: usize = 64;
+/// The route whose policy changes to named counters made outside HTTP, as
+/// over gRPC or the Redis protocol, are charged to.
+pub(crate) const MUTATION_ROUTE: &str = "/api/counters/:name/:direction";
+
+pub async fn list_counters(Extension(state): Extension<AppState>) -> Json<CountersResponse> {
+    let counters = state
+        .counters
+        .list()
+        .into_iter()
+        .map(|(name, counter)| counter_response(name, counter.value(), counter.bounds()))
+        .collect();
+    Json(CountersResponse { counters })
+}
+
+pub async fn get_counter(
+    Extension(state): Extension<AppState>,
+    Path(name): Path<String>,
+) -> Response {
+    match state.counters.get(&name) {
+        Some(counter) => {
+            Json(counter_response(name, counter.value(), counter.bounds())).into_response()
+        }
+        None => not_found(&name),
+    }
+}
+
+pub async fn put_counter(
+    Extension(state): Extension<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<PutCounterRequest>,
+) -> Response {
+    if !is_valid_name(&name) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorKind::InvalidRequest,
+            format!(
+                "Counter names are 1 to {} letters, digits, '-', '_' or '.'",
+                MAX_NAME_LENGTH
+            ),
+        );
+    }
+    let bounds = Bounds::new(
+        request.min.unwrap_or(i32::MIN),
+        request.max.unwrap_or(i32::MAX),
+    )
+    .overflow(request.overflow);
+    if !bounds.contains(request.count as i64) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            ErrorKind::InvalidRequest,
+            format!(
+                "Count {} is outside the bounds {}..={}",
+                request.count, bounds.min, bounds.max
+            ),
+        );
+    }
+
+    let status = match state.counters.put(&name, request.count, bounds) {
+        Ok(true) => StatusCode::CREATED,
+        Ok(false) => StatusCode::OK,
+        Err(TooManyCounters { max }) => {
+            return error_response(
+                StatusCode::CONFLICT,
+                ErrorKind::Conflict,
+                format!(
+                    "There are already {} counters, delete one to make room",
+                    max
+                ),
+            );
+        }
+    };
+    let response = counter_response(name, request.count, bounds);
+    (status, Json(response)).into_response()
+}
+
+pub async fn delete_counter(
+    Extension(state): Extension<AppState>,
+    Path(name): Path<String>,
+) -> Response {
+    if name == DEFAULT_COUNTER {
+        return error_response(
+            StatusCode::CONFLICT,
+            ErrorKind::Conflict,
+            "The default counter cannot be deleted".into(),
+        );
+    }
+    match state.counters.remove(&name) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => not_found(&name),
+    }
+}
+
+pub async fn post_counter(
+    Extension(state): Extension<AppState>,
+    origin: Origin,
+    Path((name, direction)): Path<(String, String)>,
+) -> Response {
+    match Direction::from_str(&direction) {
+        Ok(direction) => alter_counter(&state, name, direction.into(), &origin),
+        Err(_) => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+pub async fn post_counter_request(
+    Extension(state): Extension<AppState>,
+    origin: Origin,
+    Path(name): Path<String>,
+    Json(request): Json<CountRequest>,
+) -> Response {
+    alter_counter(&state, name, request, &origin)
+}
+
+fn alter_counter(
+    state: &AppState,
+    name: String,
+    request: CountRequest,
+    origin: &Origin,
+) -> Response {
+    let counter = match state.counters.get(&name) {
+        Some(counter) => counter,
+        None => return not_found(&name),
+    };
+    match try_alter_count(&counter, request, origin) {
+        Ok(count) => Json(counter_response(name, count, counter.bounds())).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+fn counter_response(name: String, count: i32, bounds: Bounds) -> CounterResponse {
+    CounterResponse {
+        name,
+        count,
+        min: bounds.min,
+        max: bounds.max,
+        overflow: bounds.overflow,
+    }
+}
+
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_LENGTH).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn not_found(name: &str) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        ErrorKind::NotFound,
+        format!("No counter named {}", name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_restricted_to_url_safe_characters() {
+        assert!(is_valid_name("apples"));
+        assert!(is_valid_name("page-views_2023.v1"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("two words"));
+        assert!(!is_valid_name("naïve"));
+        assert!(!is_valid_name(&"a".repeat(MAX_NAME_LENGTH + 1)));
+    }
+}
@@ -0,0 +1,9 @@
+use crate::{metrics::CONTENT_TYPE, state::AppState};
+use axum::{http::header, response::IntoResponse, Extension};
+
+pub async fn metrics(Extension(state): Extension<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, CONTENT_TYPE)],
+        state.metrics.encode(&state.counters),
+    )
+}
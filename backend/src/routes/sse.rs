@@ -0,0 +1,106 @@
+use crate::counter::Counter;
+use crate::shutdown::{DrainGuard, Shutdown};
+use crate::state::AppState;
+use axum::{
+    http::{HeaderMap, HeaderName},
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use client::CountResponse;
+use futures::stream::{self, Stream};
+use std::{collections::VecDeque, sync::Arc};
+use tokio::sync::watch;
+
+static LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
+
+/// Streams the count as Server-Sent Events, for clients which cannot hold a
+/// WebSocket open.
+///
+/// Event ids are those of `/api/count/history`, so a client reconnecting with
+/// `Last-Event-ID` is first sent every change it missed that is still
+/// remembered. Changes merged from peers are not in the history, so they
+/// reuse the id of the latest change before them.
+///
+/// Streams end when the server shuts down, rather than holding it open.
+pub async fn sse_handler(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, serde_json::Error>>> {
+    let counter = state.counters.default_counter();
+    // subscribe before reading the history, so no change can slip between them
+    let changes = counter.subscribe();
+    let latest = counter.history().last_id();
+    let last_id = headers
+        .get(&LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok())
+        // an id from before a restart would hold back every new event
+        .filter(|&id| id <= latest)
+        .unwrap_or(latest);
+
+    let feed = Feed {
+        counter,
+        changes,
+        last_id,
+        last_count: None,
+        pending: VecDeque::new(),
+        started: false,
+        _draining: state.shutdown.guard(),
+        shutdown: state.shutdown,
+    };
+    let events = stream::unfold(feed, |mut feed| async move {
+        let event = feed.next().await?;
+        Some((event, feed))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// The events one client has yet to be sent.
+struct Feed {
+    counter: Arc<Counter>,
+    changes: watch::Receiver<i32>,
+    last_id: u64,
+    last_count: Option<i32>,
+    pending: VecDeque<(u64, i32)>,
+    started: bool,
+    shutdown: Shutdown,
+    _draining: DrainGuard,
+}
+
+impl Feed {
+    async fn next(&mut self) -> Option<Result<Event, serde_json::Error>> {
+        loop {
+            if let Some((id, count)) = self.pending.pop_front() {
+                return Some(
+                    Event::default()
+                        .id(id.to_string())
+                        .json_data(CountResponse { count, stats: None }),
+                );
+            }
+            // the first events are whatever was missed, then the current count
+            if self.started {
+                tokio::select! {
+                    changed = self.changes.changed() => changed.ok()?,
+                    _ = self.shutdown.triggered() => return None,
+                }
+            }
+            self.started = true;
+            self.catch_up();
+        }
+    }
+
+    fn catch_up(&mut self) {
+        for event in self.counter.history().after(self.last_id) {
+            self.pending.push_back((event.id, event.count));
+            self.last_id = event.id;
+            self.last_count = Some(event.count);
+        }
+        // a change made since reading the history is sent again with its own id
+        // on the next notification, which is harmless as every event is the whole count
+        let count = *self.changes.borrow_and_update();
+        if self.last_count != Some(count) {
+            self.pending.push_back((self.last_id, count));
+            self.last_count = Some(count);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Tells long-lived connections that the server is stopping, and keeps
+/// track of those still winding down.
+///
+/// Connections which outlive the request that opened them, like WebSockets
+/// and streams, hold a `DrainGuard` while they run and stop once
+/// `triggered` resolves, so that `run` can wait for them to say goodbye.
+#[derive(Clone)]
+pub struct Shutdown {
+    triggered: Arc<watch::Sender<bool>>,
+    open: Arc<watch::Sender<usize>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            triggered: Arc::new(watch::channel(false).0),
+            open: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Ask everything listening to stop.
+    pub fn trigger(&self) {
+        self.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.triggered.borrow()
+    }
+
+    /// Resolves once the server starts shutting down, straight away if it already has.
+    pub async fn triggered(&self) {
+        let mut triggered = self.triggered.subscribe();
+        // the sender lives as long as `self`, so this cannot fail
+        let _ = triggered.wait_for(|&triggered| triggered).await;
+    }
+
+    /// Count a connection as open until the returned guard is dropped.
+    pub fn guard(&self) -> DrainGuard {
+        self.open.send_modify(|open| *open += 1);
+        DrainGuard(self.open.clone())
+    }
+
+    /// Resolves once every guard has been dropped.
+    pub async fn drained(&self) {
+        let mut open = self.open.subscribe();
+        let _ = open.wait_for(|&open| open == 0).await;
+    }
+}
+
+/// Holds a connection open as far as `Shutdown::drained` is concerned.
+pub struct DrainGuard(Arc<watch::Sender<usize>>);
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        self.0.send_modify(|open| *open -= 1);
+    }
+}
+
+/// Resolves on SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn drained_waits_for_every_guard() {
+        let shutdown = Shutdown::new();
+        let first = shutdown.guard();
+        let second = shutdown.guard();
+        drop(first);
+        assert!(timeout(Duration::from_millis(10), shutdown.drained())
+            .await
+            .is_err());
+        drop(second);
+        timeout(Duration::from_millis(10), shutdown.drained())
+            .await
+            .expect("still waiting after the last guard was dropped");
+    }
+
+    #[tokio::test]
+    async fn triggered_resolves_for_late_listeners() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.is_triggered());
+        shutdown.trigger();
+        timeout(Duration::from_millis(10), shutdown.clone().triggered())
+            .await
+            .expect("missed a shutdown triggered before listening");
+        assert!(shutdown.is_triggered());
+    }
+}
@@ -1,37 +1,355 @@
 use crate::{
-    routes::count::{get_count, post_count, ws_handler},
+    cluster::{self, Cluster},
+    config::{CounterConfig, TlsConfig},
+    counter::{Counters, DEFAULT_MAX_COUNTERS},
+    grpc,
+    idempotency::{self, IdempotencyCache, IdempotencyLayer},
+    limiter::{KeyedLimiters, RateLimitConfig, RateLimitLayer},
+    metrics::MetricsLayer,
+    reload::{self, ConfigSource, Reloader},
+    resp,
+    routes::admin::{
+        self, config_status, delete_access, delete_override, get_key, list_access, list_policies,
+        post_access, post_policy, put_override, reset_key, top_keys, top_up_key,
+    },
+    routes::count::{
+        get_count, get_count_history, get_count_stats, post_count, post_count_request, ws_handler,
+    },
+    routes::counters::{
+        delete_counter, get_counter, list_counters, post_counter, post_counter_request, put_counter,
+    },
     routes::health_check::health_check,
+    routes::metrics::metrics,
+    routes::sse::sse_handler,
+    shutdown::Shutdown,
     state::AppState,
+    store::{self, CounterStore},
 };
-use axum::{http::StatusCode, routing::get, routing::post, Extension, Router};
-use std::net::TcpListener;
+use axum::{
+    http::StatusCode, middleware, routing::get, routing::post, routing::put, Extension, Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
 use tower::ServiceBuilder;
-use tower_http::trace::TraceLayer;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+
+/// How long open connections get to finish once shutdown begins, by default.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything `run` needs beyond the listener.
+pub struct Settings {
+    pub static_dir: String,
+    pub rate_limits: RateLimitConfig,
+    pub cluster: Option<Cluster>,
+    pub store: Option<Arc<dyn CounterStore>>,
+    pub idempotency_ttl: Duration,
+    pub grpc: Option<TcpListener>,
+    pub resp: Option<TcpListener>,
+    pub drain_timeout: Duration,
+    pub counters: Vec<CounterConfig>,
+    pub max_counters: usize,
+    pub tls: Option<TlsConfig>,
+    pub reload: Option<ConfigSource>,
+    pub admin_tokens: Vec<String>,
+}
+
+impl Settings {
+    pub fn new(static_dir: impl Into<String>) -> Settings {
+        Settings {
+            static_dir: static_dir.into(),
+            rate_limits: RateLimitConfig::default(),
+            cluster: None,
+            store: None,
+            idempotency_ttl: idempotency::DEFAULT_TTL,
+            grpc: None,
+            resp: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            counters: Vec::new(),
+            max_counters: DEFAULT_MAX_COUNTERS,
+            tls: None,
+            reload: None,
+            admin_tokens: Vec::new(),
+        }
+    }
+
+    pub fn rate_limits(mut self, rate_limits: RateLimitConfig) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    /// Join `cluster`, sharing rate-limit consumption and the counters with its peers.
+    pub fn cluster(mut self, cluster: Cluster) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Recover the counters from `store` at startup, and save every change to it.
+    pub fn store(mut self, store: Arc<dyn CounterStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Remember responses to requests sent with an `Idempotency-Key` for `ttl`.
+    pub fn idempotency_ttl(mut self, ttl: Duration) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    /// Also serve the counters over gRPC, on `listener`.
+    pub fn grpc(mut self, listener: TcpListener) -> Self {
+        self.grpc = Some(listener);
+        self
+    }
+
+    /// Also serve the counters over the Redis protocol, on `listener`.
+    pub fn resp(mut self, listener: TcpListener) -> Self {
+        self.resp = Some(listener);
+        self
+    }
+
+    /// Give open connections `timeout` to finish once shutdown begins, before
+    /// cutting them off.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Create `counters` at startup, unless they were recovered from the store
+    /// or deleted. Every node seeds them alike, so their values merge.
+    pub fn counters(mut self, counters: Vec<CounterConfig>) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Let clients create at most `max` counters, the default counter included.
+    pub fn max_counters(mut self, max: usize) -> Self {
+        self.max_counters = max;
+        self
+    }
+
+    /// Serve HTTPS with the certificate and key in `tls`.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Reload rate limits and the log level from `source` when its file
+    /// changes or the server is sent SIGHUP.
+    pub fn reload(mut self, source: ConfigSource) -> Self {
+        self.reload = Some(source);
+        self
+    }
+
+    /// Serve the `/admin` API to requests bearing one of `tokens`.
+    pub fn admin_tokens(mut self, tokens: Vec<String>) -> Self {
+        self.admin_tokens = tokens;
+        self
+    }
+}
 
 async fn fallback() -> (StatusCode, &'static str) {
     (StatusCode::NOT_FOUND, "Not Found")
 }
 
-pub async fn run(listener: TcpListener, static_dir: String) {
-    let app = build_router(static_dir);
-    
-    axum::Server::from_tcp(listener).expect("failed to bind to socket address")
-        .serve(app.into_make_service())
+/// Serve until `shutdown` resolves, then stop accepting connections, close
+/// WebSockets and streams, and wait up to the drain timeout for requests in
+/// flight before saving the counters one last time.
+pub async fn run(
+    listener: TcpListener,
+    mut settings: Settings,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let grpc = settings.grpc.take();
+    let resp = settings.resp.take();
+    let drain_timeout = settings.drain_timeout;
+    let tls = settings.tls.take();
+    let (app, state, store) = build_router(settings);
+    let persisting = store.map(|store| {
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = store::persist(state.counters.clone(), store, async {
+            let _ = stopped.await;
+        });
+        (stop, task)
+    });
+    if let Some(grpc) = grpc {
+        grpc::serve(grpc, state.clone());
+    }
+    if let Some(resp) = resp {
+        resp::serve(resp, state.clone());
+    }
+    if let Some(reloader) = &state.reloader {
+        reload::watch(reloader.clone(), state.shutdown.clone());
+    }
+
+    let trigger = async {
+        shutdown.await;
+        log::info!("shutting down");
+        state.shutdown.trigger();
+    };
+    let drained = async {
+        tokio::join!(serve(listener, app, tls, state.shutdown.clone()), trigger);
+        state.shutdown.drained().await;
+    };
+    let deadline = async {
+        state.shutdown.triggered().await;
+        tokio::time::sleep(drain_timeout).await;
+    };
+    tokio::select! {
+        _ = drained => {}
+        _ = deadline => log::warn!("connections still open after {:?}, closing them", drain_timeout),
+    }
+
+    if let Some((stop, task)) = persisting {
+        let _ = stop.send(());
+        if let Err(err) = task.await {
+            log::error!("failed to save counters: {}", err);
+        }
+    }
+}
+
+/// Serve `app` on `listener` until `shutdown` is triggered and the requests
+/// in flight have been answered.
+async fn serve(listener: TcpListener, app: Router, tls: Option<TlsConfig>, shutdown: Shutdown) {
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let tls = match tls {
+        Some(tls) => tls,
+        None => {
+            return axum::Server::from_tcp(listener)
+                .expect("failed to bind to socket address")
+                .serve(app)
+                .with_graceful_shutdown(async move { shutdown.triggered().await })
+                .await
+                .expect("Unable to start server");
+        }
+    };
+
+    let config = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
         .await
-        .expect("Unable to start server");
+        .expect("failed to load TLS certificate and key");
+    let handle = Handle::new();
+    let stop = async {
+        shutdown.triggered().await;
+        handle.graceful_shutdown(None);
+    };
+    let server = axum_server::from_tcp_rustls(listener, config)
+        .handle(handle.clone())
+        .serve(app);
+    let (served, ()) = tokio::join!(server, stop);
+    served.expect("Unable to start server");
 }
 
-fn build_router(static_dir: String) -> Router {
-    let state = AppState::new();
+fn build_router(settings: Settings) -> (Router, AppState, Option<Arc<dyn CounterStore>>) {
+    let Settings {
+        static_dir,
+        rate_limits,
+        cluster,
+        store,
+        idempotency_ttl,
+        grpc: _,
+        resp: _,
+        drain_timeout: _,
+        counters: configured,
+        max_counters,
+        tls: _,
+        reload,
+        admin_tokens,
+    } = settings;
+
+    let counters = match &cluster {
+        Some(cluster) => Counters::new(cluster.local_peer_id().to_string()),
+        None => Counters::default(),
+    }
+    .max_counters(max_counters);
+    // recover before sharing, so peers never see this node without its saved counters
+    let recovered = store.as_ref().map(|store| {
+        let recovered = store.load().expect("failed to recover counters");
+        counters.merge(&recovered);
+        recovered
+    });
+    for counter in &configured {
+        counters.seed(&counter.name, counter.value, counter.bounds());
+    }
+    // saving only ever writes what changed since startup, so save seeded counters now
+    if let (Some(store), Some(recovered)) = (&store, &recovered) {
+        let seeded = store::changes_since(recovered, &counters.snapshot());
+        store
+            .save(&seeded)
+            .expect("failed to save configured counters");
+    }
+
+    let mut state = match &cluster {
+        Some(cluster) => {
+            let state = AppState::with_parts(counters, KeyedLimiters::shared(&rate_limits));
+            cluster::share_rate_limits(cluster, state.limiters.clone());
+            cluster::share_counters(cluster, state.counters.clone());
+            state
+        }
+        None => AppState::with_parts(counters, KeyedLimiters::new(&rate_limits)),
+    };
+    state.reloader = reload.map(|source| Arc::new(Reloader::new(source, state.limiters.clone())));
 
-    Router::new()
+    let mut router = Router::new()
         .route("/health_check", get(health_check))
-        .route("/api/count", get(get_count))
+        .route("/metrics", get(metrics))
+        .route("/api/count", get(get_count).post(post_count_request))
+        .route("/api/count/history", get(get_count_history))
+        .route("/api/count/stats", get(get_count_stats))
         .route("/api/count/:direction", post(post_count))
+        .route("/api/counters", get(list_counters))
+        .route(
+            "/api/counters/:name",
+            get(get_counter)
+                .post(post_counter_request)
+                .put(put_counter)
+                .delete(delete_counter),
+        )
+        .route("/api/counters/:name/:direction", post(post_counter))
         .route("/ws/count", get(ws_handler))
+        .route("/sse/count", get(sse_handler))
         // frontend serving: static assets for the Single-Page Application
-        .merge(axum_extra::routing::SpaRouter::new("/assets", static_dir))
+        .merge(axum_extra::routing::SpaRouter::new("/assets", static_dir));
+    if !admin_tokens.is_empty() {
+        let tokens = Arc::new(admin_tokens);
+        let admin = Router::new()
+            .route("/config", get(config_status))
+            .route("/keys", get(top_keys))
+            .route("/keys/:key", get(get_key).delete(reset_key))
+            .route("/keys/:key/top_up", post(top_up_key))
+            .route(
+                "/keys/:key/override",
+                put(put_override).delete(delete_override),
+            )
+            .route("/policies", get(list_policies).post(post_policy))
+            .route(
+                "/access",
+                get(list_access).post(post_access).delete(delete_access),
+            )
+            .route_layer(middleware::from_fn(move |request, next| {
+                admin::authorize(tokens.clone(), request, next)
+            }));
+        router = router.nest("/admin", admin);
+    }
+    let router = router
         .fallback(fallback)
-        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()))
-        .layer(Extension(state))
+        .layer(
+            ServiceBuilder::new()
+                // keep the id a client sent, so it can match our history to its logs
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(TraceLayer::new_for_http())
+                .layer(MetricsLayer::new(state.metrics.clone()))
+                .layer(RateLimitLayer::new(state.limiters.clone()).metrics(state.metrics.clone()))
+                .layer(IdempotencyLayer::new(Arc::new(IdempotencyCache::new(
+                    idempotency_ttl,
+                )))),
+        )
+        .layer(Extension(state.clone()));
+    (router, state, store)
 }
@@ -1,14 +1,83 @@
-use std::sync::{Arc, atomic::AtomicI32};
+use crate::counter::Counters;
+use crate::limiter::{Access, KeyedLimiters, Refusal};
+use crate::metrics::Metrics;
+use crate::reload::Reloader;
+use crate::shutdown::Shutdown;
+use axum::http::Method;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub count: Arc<AtomicI32>,
+    pub counters: Arc<Counters>,
+    pub limiters: Arc<KeyedLimiters>,
+    pub metrics: Arc<Metrics>,
+    pub shutdown: Shutdown,
+    /// Set when the configuration can be reloaded while running.
+    pub reloader: Option<Arc<Reloader>>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
     pub fn new() -> AppState {
+        AppState::with_parts(Counters::default(), KeyedLimiters::default())
+    }
+
+    pub fn with_parts(counters: Counters, limiters: KeyedLimiters) -> AppState {
         AppState {
-            count: Arc::new(AtomicI32::new(0)),
+            counters: Arc::new(counters),
+            limiters: Arc::new(limiters),
+            metrics: Arc::new(Metrics::new()),
+            shutdown: Shutdown::new(),
+            reloader: None,
+        }
+    }
+
+    /// Whether `ip` is on the deny list, and so refused everything, reads
+    /// included, as `RateLimitLayer` refuses it every request.
+    pub fn forbids(&self, ip: Option<IpAddr>) -> bool {
+        let config = self.limiters.config();
+        ip.and_then(|ip| config.access(ip)) == Some(Access::Deny)
+    }
+
+    /// Check a command from `ip` under `key` the way `RateLimitLayer` checks
+    /// `method` requests to the route `path`, for commands that arrive some
+    /// other way: clients on the deny list are refused, those on the allow
+    /// list admitted, and everyone else held to the route's policy.
+    pub fn admit(
+        &self,
+        method: &Method,
+        path: &str,
+        key: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<(), Refusal> {
+        let config = self.limiters.config();
+        match ip.and_then(|ip| config.access(ip)) {
+            Some(Access::Deny) => return Err(Refusal::Forbidden),
+            Some(Access::Allow) => return Ok(()),
+            None => {}
+        }
+        let route = match config.route_for(method, path) {
+            Some(route) => route,
+            None => return Ok(()),
+        };
+        let decision = match self.limiters.check(&route, key, Instant::now()) {
+            Some((_, decision)) => decision,
+            None => return Ok(()),
+        };
+        self.metrics.record_decision(&route, decision.is_allowed());
+        if decision.is_allowed() {
+            Ok(())
+        } else {
+            Err(Refusal::RateLimited {
+                retry_after: decision.retry_after,
+            })
         }
     }
 }
@@ -0,0 +1,136 @@
+use client::{CountStats, Movement};
+use std::{collections::VecDeque, sync::Mutex, time::Instant};
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const SECONDS_PER_HOUR: u64 = 60 * 60;
+
+/// How much a counter moved during one second.
+struct Bucket {
+    second: u64,
+    movement: Movement,
+}
+
+/// Rolling totals of how far a counter moved up and down, kept in one
+/// bucket per second for the last hour.
+pub struct Stats {
+    start: Instant,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new(Instant::now())
+    }
+}
+
+impl Stats {
+    fn new(start: Instant) -> Stats {
+        Stats {
+            start,
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Count a change by `delta` made at `now`.
+    pub fn record(&self, delta: i64, now: Instant) {
+        let second = self.second(now);
+        let mut buckets = self.buckets.lock().expect("stats lock poisoned");
+        Self::expire(&mut buckets, second);
+        // changes made under the counter's lock arrive in order, so only the
+        // newest bucket can still be filling up
+        let bucket = match buckets.back_mut() {
+            Some(bucket) if bucket.second >= second => bucket,
+            _ => {
+                buckets.push_back(Bucket {
+                    second,
+                    movement: Movement::default(),
+                });
+                buckets.back_mut().expect("bucket just pushed")
+            }
+        };
+        if delta > 0 {
+            bucket.movement.increments += delta as u64;
+        } else {
+            bucket.movement.decrements += delta.unsigned_abs();
+        }
+    }
+
+    /// The totals over the second, minute and hour ending at `now`.
+    pub fn at(&self, now: Instant) -> CountStats {
+        let second = self.second(now);
+        let buckets = self.buckets.lock().expect("stats lock poisoned");
+        let total = |seconds: u64| {
+            buckets
+                .iter()
+                .rev()
+                .take_while(|bucket| bucket.second + seconds > second)
+                .fold(Movement::default(), |total, bucket| Movement {
+                    increments: total.increments + bucket.movement.increments,
+                    decrements: total.decrements + bucket.movement.decrements,
+                })
+        };
+        CountStats {
+            per_second: total(1),
+            per_minute: total(SECONDS_PER_MINUTE),
+            per_hour: total(SECONDS_PER_HOUR),
+        }
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs()
+    }
+
+    fn expire(buckets: &mut VecDeque<Bucket>, second: u64) {
+        while let Some(oldest) = buckets.front() {
+            if oldest.second + SECONDS_PER_HOUR > second {
+                break;
+            }
+            buckets.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn movement(increments: u64, decrements: u64) -> Movement {
+        Movement {
+            increments,
+            decrements,
+        }
+    }
+
+    #[test]
+    fn changes_are_totalled_over_each_window() {
+        let start = Instant::now();
+        let stats = Stats::new(start);
+        stats.record(5, start);
+        stats.record(-2, start + Duration::from_secs(31));
+        stats.record(1, start + Duration::from_secs(90));
+        stats.record(1, start + Duration::from_millis(90_500));
+
+        let totals = stats.at(start + Duration::from_secs(90));
+        assert_eq!(totals.per_second, movement(2, 0));
+        assert_eq!(totals.per_minute, movement(2, 2));
+        assert_eq!(totals.per_hour, movement(7, 2));
+    }
+
+    #[test]
+    fn buckets_older_than_an_hour_are_dropped() {
+        let start = Instant::now();
+        let stats = Stats::new(start);
+        stats.record(3, start);
+        assert_eq!(
+            stats.at(start + Duration::from_secs(3599)).per_hour,
+            movement(3, 0)
+        );
+
+        stats.record(-1, start + Duration::from_secs(3600));
+        assert_eq!(stats.buckets.lock().unwrap().len(), 1);
+        let totals = stats.at(start + Duration::from_secs(3600));
+        assert_eq!(totals.per_hour, movement(0, 1));
+        assert_eq!(totals.per_second, movement(0, 1));
+    }
+}
@@ -0,0 +1,250 @@
+pub mod file;
+pub mod memory;
+pub mod sqlite;
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use crate::counter::{Counters, CountersSnapshot};
+use serde::Deserialize;
+use std::{fmt, future::Future, io, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+
+const PERSIST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Somewhere to keep counters between restarts.
+///
+/// Stores hold the same replicas that backends gossip to each other, so a
+/// recovered counter merges with what its peers remember instead of
+/// overwriting it.
+pub trait CounterStore: Send + Sync {
+    /// Everything saved so far.
+    fn load(&self) -> Result<CountersSnapshot, StoreError>;
+
+    /// Save the counters and deletions in `changes`, replacing any earlier
+    /// state saved under the same names.
+    fn save(&self, changes: &CountersSnapshot) -> Result<(), StoreError>;
+
+    /// Make sure everything saved so far survives a crash.
+    fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+    Corrupt(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "store I/O failed: {}", err),
+            StoreError::Sqlite(err) => write!(f, "SQLite store failed: {}", err),
+            StoreError::Corrupt(err) => write!(f, "store is corrupt: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        StoreError::Sqlite(err)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(err: serde_json::Error) -> Self {
+        StoreError::Corrupt(err.to_string())
+    }
+}
+
+/// Which store to use, as given on the command line or in the configuration
+/// file: `memory`, `file:<path>` or `sqlite:<path>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum StoreConfig {
+    #[default]
+    Memory,
+    File(PathBuf),
+    Sqlite(PathBuf),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseStoreConfigError(String);
+
+impl fmt::Display for ParseStoreConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown store {:?}, expected memory, file:<path> or sqlite:<path>",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseStoreConfigError {}
+
+impl FromStr for StoreConfig {
+    type Err = ParseStoreConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "memory" => Ok(StoreConfig::Memory),
+            Some(("file", path)) if !path.is_empty() => Ok(StoreConfig::File(path.into())),
+            Some(("sqlite", path)) if !path.is_empty() => Ok(StoreConfig::Sqlite(path.into())),
+            _ => Err(ParseStoreConfigError(s.to_owned())),
+        }
+    }
+}
+
+impl TryFrom<String> for StoreConfig {
+    type Error = ParseStoreConfigError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl StoreConfig {
+    pub fn open(&self) -> Result<Arc<dyn CounterStore>, StoreError> {
+        Ok(match self {
+            StoreConfig::Memory => Arc::new(MemoryStore::default()),
+            StoreConfig::File(path) => Arc::new(FileStore::open(path)?),
+            StoreConfig::Sqlite(path) => Arc::new(SqliteStore::open(path)?),
+        })
+    }
+}
+
+/// Save changes to `counters` in the background, whether they were made
+/// here or merged from peers. Writes are batched, so a crash can lose the
+/// last few milliseconds of changes.
+///
+/// Once `stop` resolves, whatever is left is saved and the store flushed
+/// before the returned task finishes.
+pub fn persist(
+    counters: Arc<Counters>,
+    store: Arc<dyn CounterStore>,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PERSIST_INTERVAL);
+        let mut saved = counters.snapshot();
+        let mut saved_revision = counters.revision();
+        tokio::pin!(stop);
+        let mut stopping = false;
+        while !stopping {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = &mut stop => stopping = true,
+            }
+            let revision = counters.revision();
+            if revision == saved_revision {
+                continue;
+            }
+
+            let snapshot = counters.snapshot();
+            let changes = changes_since(&saved, &snapshot);
+            let store = store.clone();
+            let result = tokio::task::spawn_blocking(move || store.save(&changes)).await;
+            match result {
+                Ok(Ok(())) => {
+                    saved = snapshot;
+                    saved_revision = revision;
+                }
+                // try again on the next tick
+                Ok(Err(err)) => log::error!("failed to save counters: {}", err),
+                Err(err) => log::error!("failed to save counters: {}", err),
+            }
+        }
+
+        let result = tokio::task::spawn_blocking(move || store.flush()).await;
+        match result {
+            Ok(Ok(())) => log::info!("saved counters"),
+            Ok(Err(err)) => log::error!("failed to flush counters: {}", err),
+            Err(err) => log::error!("failed to flush counters: {}", err),
+        }
+    })
+}
+
+/// The counters and deletions in `current` which differ from `saved`.
+pub(crate) fn changes_since(
+    saved: &CountersSnapshot,
+    current: &CountersSnapshot,
+) -> CountersSnapshot {
+    CountersSnapshot {
+        counters: current
+            .counters
+            .iter()
+            .filter(|(name, replica)| saved.counters.get(*name) != Some(replica))
+            .map(|(name, replica)| (name.clone(), replica.clone()))
+            .collect(),
+        deleted: current
+            .deleted
+            .iter()
+            .filter(|(name, epoch)| saved.deleted.get(*name) != Some(epoch))
+            .map(|(name, epoch)| (name.clone(), epoch.clone()))
+            .collect(),
+    }
+}
+
+/// Apply `changes` on top of `snapshot`, the way a store replays its saves.
+fn apply(snapshot: &mut CountersSnapshot, changes: &CountersSnapshot) {
+    for (name, epoch) in &changes.deleted {
+        snapshot.counters.remove(name);
+        snapshot.deleted.insert(name.clone(), epoch.clone());
+    }
+    for (name, replica) in &changes.counters {
+        snapshot.deleted.remove(name);
+        snapshot.counters.insert(name.clone(), replica.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::Bounds;
+
+    #[test]
+    fn store_config_parses_from_command_line() {
+        assert_eq!("memory".parse(), Ok(StoreConfig::Memory));
+        assert_eq!(
+            "file:counters.wal".parse(),
+            Ok(StoreConfig::File("counters.wal".into()))
+        );
+        assert_eq!(
+            "sqlite:/var/lib/limitrs.db".parse(),
+            Ok(StoreConfig::Sqlite("/var/lib/limitrs.db".into()))
+        );
+        assert!("file:".parse::<StoreConfig>().is_err());
+        assert!("redis:localhost".parse::<StoreConfig>().is_err());
+    }
+
+    #[test]
+    fn changes_since_only_includes_what_differs() {
+        let counters = Counters::new("a");
+        counters.put("apples", 1, Bounds::default()).unwrap();
+        counters.put("pears", 1, Bounds::default()).unwrap();
+        let saved = counters.snapshot();
+
+        counters.put("apples", 2, Bounds::default()).unwrap();
+        counters.remove("pears");
+        let changes = changes_since(&saved, &counters.snapshot());
+        assert_eq!(changes.counters.keys().collect::<Vec<_>>(), ["apples"]);
+        assert_eq!(changes.deleted.keys().collect::<Vec<_>>(), ["pears"]);
+
+        let mut replayed = saved;
+        apply(&mut replayed, &changes);
+        assert_eq!(replayed, counters.snapshot());
+    }
+}
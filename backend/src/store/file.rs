@@ -0,0 +1,243 @@
+use super::{apply, CounterStore, StoreError};
+use crate::counter::{CountersSnapshot, Epoch, Replica};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The log is never compacted while smaller than this.
+const MIN_COMPACT_SIZE: u64 = 1024 * 1024;
+/// Beyond that, it is compacted once it has grown to this many times the
+/// size it had after the last compaction.
+const COMPACT_RATIO: u64 = 4;
+
+/// One line of the log.
+#[derive(Serialize, Deserialize)]
+enum Record {
+    Put { name: String, replica: Replica },
+    Delete { name: String, epoch: Epoch },
+}
+
+/// An append-only log of JSON records, one per line. Later records replace
+/// earlier ones for the same counter, and the log is compacted down to one
+/// record per counter each time it is opened, and again whenever it grows
+/// to several times that size.
+///
+/// Every save is synced to disk before it returns, so a crash loses at most
+/// the changes which had not been saved yet.
+pub struct FileStore {
+    path: PathBuf,
+    log: Mutex<Log>,
+}
+
+struct Log {
+    file: File,
+    /// Everything saved so far, to compact the log down to.
+    snapshot: CountersSnapshot,
+    size: u64,
+    compacted_size: u64,
+}
+
+impl FileStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<FileStore, StoreError> {
+        let path = path.into();
+        let snapshot = replay(&path)?;
+        let size = compact(&path, &snapshot)?;
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(FileStore {
+            path,
+            log: Mutex::new(Log {
+                file,
+                snapshot,
+                size,
+                compacted_size: size,
+            }),
+        })
+    }
+}
+
+impl CounterStore for FileStore {
+    fn load(&self) -> Result<CountersSnapshot, StoreError> {
+        let log = self.log.lock().expect("store lock poisoned");
+        Ok(log.snapshot.clone())
+    }
+
+    fn save(&self, changes: &CountersSnapshot) -> Result<(), StoreError> {
+        let mut log = self.log.lock().expect("store lock poisoned");
+        let mut records = Vec::new();
+        write_records(&mut records, changes)?;
+        log.file.write_all(&records)?;
+        log.file.sync_data()?;
+        log.size += records.len() as u64;
+        apply(&mut log.snapshot, changes);
+
+        if log.size >= MIN_COMPACT_SIZE.max(log.compacted_size * COMPACT_RATIO) {
+            let size = compact(&self.path, &log.snapshot)?;
+            log.file = OpenOptions::new().append(true).open(&self.path)?;
+            log::debug!(
+                "compacted {} from {} to {} bytes",
+                self.path.display(),
+                log.size,
+                size
+            );
+            log.size = size;
+            log.compacted_size = size;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StoreError> {
+        let log = self.log.lock().expect("store lock poisoned");
+        log.file.sync_data()?;
+        Ok(())
+    }
+}
+
+fn write_records(out: &mut impl Write, changes: &CountersSnapshot) -> Result<(), StoreError> {
+    // deletions first, so a counter deleted and then recreated ends up existing
+    for (name, epoch) in &changes.deleted {
+        let record = Record::Delete {
+            name: name.clone(),
+            epoch: epoch.clone(),
+        };
+        serde_json::to_writer(&mut *out, &record)?;
+        out.write_all(b"\n")?;
+    }
+    for (name, replica) in &changes.counters {
+        let record = Record::Put {
+            name: name.clone(),
+            replica: replica.clone(),
+        };
+        serde_json::to_writer(&mut *out, &record)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Rebuild the counters from the log at `path`, which may not exist yet.
+fn replay(path: &Path) -> Result<CountersSnapshot, StoreError> {
+    let mut snapshot = CountersSnapshot::default();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(snapshot),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut lines = BufReader::new(file).lines().peekable();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        let record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            // a crash mid-write can leave the last line torn, but nothing else
+            Err(_) if lines.peek().is_none() => {
+                log::warn!("ignoring torn record at the end of {}", path.display());
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match record {
+            Record::Put { name, replica } => {
+                snapshot.deleted.remove(&name);
+                snapshot.counters.insert(name, replica);
+            }
+            Record::Delete { name, epoch } => {
+                snapshot.counters.remove(&name);
+                snapshot.deleted.insert(name, epoch);
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Replace the log at `path` with one holding just `snapshot`, returning
+/// its size. The new log is written alongside and renamed over the old one,
+/// so a crash leaves one or the other in place.
+fn compact(path: &Path, snapshot: &CountersSnapshot) -> Result<u64, StoreError> {
+    let mut compacted = path.as_os_str().to_owned();
+    compacted.push(".compact");
+    let compacted = PathBuf::from(compacted);
+
+    let mut out = BufWriter::new(File::create(&compacted)?);
+    write_records(&mut out, snapshot)?;
+    let file = out.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    let size = file.metadata()?.len();
+    fs::rename(&compacted, path)?;
+    // the rename itself only survives a crash once the directory is synced
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::{Bounds, Counters};
+
+    #[test]
+    fn replays_saves_after_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("counters.wal");
+        let counters = Counters::new("a");
+        counters.put("apples", 3, Bounds::default()).unwrap();
+        counters.put("pears", 1, Bounds::default()).unwrap();
+
+        let store = FileStore::open(&path).unwrap();
+        store.save(&counters.snapshot()).unwrap();
+        counters.remove("pears");
+        store.save(&counters.snapshot()).unwrap();
+        drop(store);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.load().unwrap(), counters.snapshot());
+    }
+
+    #[test]
+    fn compacts_the_log_as_it_grows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("counters.wal");
+        let counters = Counters::new("a");
+        counters.put("apples", 0, Bounds::default()).unwrap();
+        let apples = counters.get("apples").unwrap();
+
+        let store = FileStore::open(&path).unwrap();
+        let mut largest = 0;
+        for _ in 0..10_000 {
+            apples.update::<()>(|value, _| Ok(value + 1)).unwrap();
+            store.save(&counters.snapshot()).unwrap();
+            largest = largest.max(fs::metadata(&path).unwrap().len());
+        }
+        assert!(largest <= MIN_COMPACT_SIZE + 1024);
+        assert_eq!(store.load().unwrap(), counters.snapshot());
+        drop(store);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.load().unwrap(), counters.snapshot());
+    }
+
+    #[test]
+    fn ignores_a_torn_final_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("counters.wal");
+        let counters = Counters::new("a");
+        counters.put("apples", 3, Bounds::default()).unwrap();
+
+        let store = FileStore::open(&path).unwrap();
+        store.save(&counters.snapshot()).unwrap();
+        drop(store);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"Put":{"name":"pe"#).unwrap();
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.load().unwrap(), counters.snapshot());
+    }
+}
@@ -0,0 +1,40 @@
+use super::{apply, CounterStore, StoreError};
+use crate::counter::CountersSnapshot;
+use std::sync::Mutex;
+
+/// Keeps counters for as long as the process lives, which is the behaviour
+/// of a backend without a `--store`.
+#[derive(Default)]
+pub struct MemoryStore {
+    snapshot: Mutex<CountersSnapshot>,
+}
+
+impl CounterStore for MemoryStore {
+    fn load(&self) -> Result<CountersSnapshot, StoreError> {
+        Ok(self.snapshot.lock().expect("store lock poisoned").clone())
+    }
+
+    fn save(&self, changes: &CountersSnapshot) -> Result<(), StoreError> {
+        apply(
+            &mut self.snapshot.lock().expect("store lock poisoned"),
+            changes,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::{Bounds, Counters};
+
+    #[test]
+    fn loads_what_was_saved() {
+        let counters = Counters::new("a");
+        counters.put("apples", 3, Bounds::default()).unwrap();
+
+        let store = MemoryStore::default();
+        store.save(&counters.snapshot()).unwrap();
+        assert_eq!(store.load().unwrap(), counters.snapshot());
+    }
+}
@@ -0,0 +1,100 @@
+use super::{CounterStore, StoreError};
+use crate::counter::CountersSnapshot;
+use rusqlite::{params, Connection};
+use std::{path::Path, sync::Mutex};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS counters (
+        name TEXT PRIMARY KEY,
+        replica TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS deleted (
+        name TEXT PRIMARY KEY,
+        epoch TEXT NOT NULL
+    );
+";
+
+/// Keeps each counter's replica as JSON in an embedded SQLite database.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteStore, StoreError> {
+        SqliteStore::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<SqliteStore, StoreError> {
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl CounterStore for SqliteStore {
+    fn load(&self) -> Result<CountersSnapshot, StoreError> {
+        let connection = self.connection.lock().expect("store lock poisoned");
+        let mut snapshot = CountersSnapshot::default();
+
+        let mut counters = connection.prepare("SELECT name, replica FROM counters")?;
+        let mut rows = counters.query([])?;
+        while let Some(row) = rows.next()? {
+            let replica: String = row.get(1)?;
+            snapshot
+                .counters
+                .insert(row.get(0)?, serde_json::from_str(&replica)?);
+        }
+
+        let mut deleted = connection.prepare("SELECT name, epoch FROM deleted")?;
+        let mut rows = deleted.query([])?;
+        while let Some(row) = rows.next()? {
+            let epoch: String = row.get(1)?;
+            snapshot
+                .deleted
+                .insert(row.get(0)?, serde_json::from_str(&epoch)?);
+        }
+        Ok(snapshot)
+    }
+
+    fn save(&self, changes: &CountersSnapshot) -> Result<(), StoreError> {
+        let mut connection = self.connection.lock().expect("store lock poisoned");
+        let transaction = connection.transaction()?;
+        for (name, epoch) in &changes.deleted {
+            transaction.execute("DELETE FROM counters WHERE name = ?1", params![name])?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO deleted (name, epoch) VALUES (?1, ?2)",
+                params![name, serde_json::to_string(epoch)?],
+            )?;
+        }
+        for (name, replica) in &changes.counters {
+            transaction.execute("DELETE FROM deleted WHERE name = ?1", params![name])?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO counters (name, replica) VALUES (?1, ?2)",
+                params![name, serde_json::to_string(replica)?],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::{Bounds, Counters};
+
+    #[test]
+    fn loads_what_was_saved() {
+        let store = SqliteStore::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let counters = Counters::new("a");
+        counters.put("apples", 3, Bounds::default()).unwrap();
+        counters.put("pears", 1, Bounds::default()).unwrap();
+        store.save(&counters.snapshot()).unwrap();
+
+        counters.remove("pears");
+        store.save(&counters.snapshot()).unwrap();
+        assert_eq!(store.load().unwrap(), counters.snapshot());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{admin::TOKEN, test_server::TestServer};
+use backend::{
+    limiter::{Policy, RateLimitConfig},
+    startup::Settings,
+};
+use client::{AccessList, AccessListsResponse, AccessRule, ErrorKind, ErrorResponse};
+use reqwest::StatusCode;
+
+fn spawn(rate_limits: RateLimitConfig) -> TestServer {
+    TestServer::spawn_server_with(
+        Settings::new("")
+            .rate_limits(rate_limits.route("/api/count", Policy::token_bucket(1, 0.001)))
+            .admin_tokens(vec![TOKEN.into()]),
+    )
+}
+
+async fn get(test_server: &TestServer, path: &str) -> reqwest::Response {
+    test_server
+        .client
+        .get(format!(
+            "http://{}:{}{}",
+            test_server.address, test_server.port, path
+        ))
+        .send()
+        .await
+        .expect("GET failed")
+}
+
+#[tokio::test]
+async fn denied_ranges_are_forbidden_everywhere() {
+    let test_server = spawn(RateLimitConfig::default().deny("127.0.0.0/8".parse().unwrap()));
+
+    for path in ["/api/count", "/health_check", "/no/such/route"] {
+        let response = get(&test_server, path).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+        let body: ErrorResponse = response.json().await.unwrap();
+        assert_eq!(body.error, ErrorKind::Forbidden);
+    }
+}
+
+#[tokio::test]
+async fn allowed_ranges_skip_the_limiter() {
+    let test_server = spawn(
+        RateLimitConfig::default()
+            .deny("127.0.0.0/8".parse().unwrap())
+            .allow("127.0.0.1/32".parse().unwrap()),
+    );
+
+    for _ in 0..3 {
+        let response = get(&test_server, "/api/count").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("ratelimit-limit").is_none());
+    }
+}
+
+#[tokio::test]
+async fn access_lists_change_through_the_admin_api() {
+    let test_server = spawn(RateLimitConfig::default());
+    let url = format!(
+        "http://{}:{}/admin/access",
+        test_server.address, test_server.port
+    );
+    let allow_localhost = AccessRule {
+        list: AccessList::Allow,
+        range: "127.0.0.1".into(),
+    };
+
+    assert_eq!(
+        get(&test_server, "/api/count").await.status(),
+        StatusCode::OK
+    );
+    let response = test_server
+        .client
+        .post(&url)
+        .bearer_auth(TOKEN)
+        .json(&allow_localhost)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        get(&test_server, "/api/count").await.status(),
+        StatusCode::OK
+    );
+
+    let deny = AccessRule {
+        list: AccessList::Deny,
+        range: "2001:db8::/32".into(),
+    };
+    let response = test_server
+        .client
+        .post(&url)
+        .bearer_auth(TOKEN)
+        .json(&deny)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let lists: AccessListsResponse = test_server
+        .client
+        .get(&url)
+        .bearer_auth(TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        lists,
+        AccessListsResponse {
+            allow: vec!["127.0.0.1/32".into()],
+            deny: vec!["2001:db8::/32".into()],
+        }
+    );
+
+    let remove = || {
+        test_server
+            .client
+            .delete(&url)
+            .bearer_auth(TOKEN)
+            .query(&allow_localhost)
+            .send()
+    };
+    assert_eq!(remove().await.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(remove().await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        get(&test_server, "/api/count").await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let response = test_server
+        .client
+        .post(&url)
+        .bearer_auth(TOKEN)
+        .json(&AccessRule {
+            list: AccessList::Deny,
+            range: "10.0.0.0/33".into(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
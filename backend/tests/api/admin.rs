@@ -0,0 +1,337 @@
+use crate::test_server::TestServer;
+use backend::{
+    limiter::{KeyExtractor, Policy, RateLimitConfig},
+    startup::Settings,
+};
+use client::{
+    KeyOverride, KeyState, OverrideKind, PoliciesResponse, RoutePolicy, TopKeysResponse,
+    TopUpRequest,
+};
+use reqwest::{Method, RequestBuilder, StatusCode};
+
+pub const TOKEN: &str = "let-me-in";
+// requests from the test client are keyed on its address
+const KEY: &str = "127.0.0.1";
+
+fn spawn() -> TestServer {
+    TestServer::spawn_server_with(
+        Settings::new("")
+            .rate_limits(
+                RateLimitConfig::counter_defaults()
+                    .route("/api/count", Policy::token_bucket(1, 0.001)),
+            )
+            .admin_tokens(vec![TOKEN.into()]),
+    )
+}
+
+fn admin(test_server: &TestServer, method: Method, path: &str) -> RequestBuilder {
+    test_server
+        .client
+        .request(
+            method,
+            format!(
+                "http://{}:{}/admin{}",
+                test_server.address, test_server.port, path
+            ),
+        )
+        .bearer_auth(TOKEN)
+}
+
+async fn get_count(test_server: &TestServer) -> StatusCode {
+    test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed")
+        .status()
+}
+
+#[tokio::test]
+async fn admin_requests_need_a_token() {
+    let test_server = spawn();
+    let url = format!(
+        "http://{}:{}/admin/keys",
+        test_server.address, test_server.port
+    );
+    let response = test_server.client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+
+    let response = test_server
+        .client
+        .get(&url)
+        .bearer_auth("guess")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = admin(&test_server, Method::GET, "/keys")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn the_admin_api_is_off_without_tokens() {
+    let test_server = TestServer::spawn_server();
+    let response = admin(&test_server, Method::GET, "/keys")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn throttled_keys_can_be_inspected_and_reset() {
+    let test_server = spawn();
+    assert_eq!(get_count(&test_server).await, StatusCode::OK);
+    assert_eq!(get_count(&test_server).await, StatusCode::TOO_MANY_REQUESTS);
+
+    let top: TopKeysResponse = admin(&test_server, Method::GET, "/keys?route=/api/count")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(top.keys.len(), 1);
+    assert_eq!(top.keys[0].key, KEY);
+    assert_eq!((top.keys[0].allowed, top.keys[0].denied), (1, 1));
+
+    let state: KeyState = admin(&test_server, Method::GET, &format!("/keys/{}", KEY))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(state.routes[0].route, "/api/count");
+    assert_eq!(state.routes[0].remaining, 0);
+    assert_eq!(
+        state.routes[0].policy,
+        Policy::TokenBucket {
+            capacity: 1,
+            refill_per_second: 0.001
+        }
+    );
+
+    let response = admin(&test_server, Method::DELETE, &format!("/keys/{}", KEY))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(get_count(&test_server).await, StatusCode::OK);
+
+    let response = admin(&test_server, Method::GET, "/keys/10.9.9.9")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn keys_can_be_topped_up() {
+    let test_server = spawn();
+    assert_eq!(get_count(&test_server).await, StatusCode::OK);
+
+    let top_up = TopUpRequest {
+        route: Some("/api/count".into()),
+        requests: 2,
+    };
+    let response = admin(&test_server, Method::POST, &format!("/keys/{}/top_up", KEY))
+        .json(&top_up)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let state: KeyState = response.json().await.unwrap();
+    assert_eq!(state.routes[0].credit, 2);
+
+    assert_eq!(get_count(&test_server).await, StatusCode::OK);
+    assert_eq!(get_count(&test_server).await, StatusCode::OK);
+    assert_eq!(get_count(&test_server).await, StatusCode::TOO_MANY_REQUESTS);
+
+    let unlimited = TopUpRequest {
+        route: Some("/health_check".into()),
+        requests: 1,
+    };
+    let response = admin(&test_server, Method::POST, &format!("/keys/{}/top_up", KEY))
+        .json(&unlimited)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn keys_can_be_banned_and_exempted() {
+    let test_server = spawn();
+    let path = format!("/keys/{}/override", KEY);
+    let set = |kind| {
+        admin(&test_server, Method::PUT, &path)
+            .json(&KeyOverride { kind, seconds: 60 })
+            .send()
+    };
+
+    let state: KeyState = set(OverrideKind::Ban).await.unwrap().json().await.unwrap();
+    assert_eq!(state.override_.unwrap().kind, OverrideKind::Ban);
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "60");
+
+    set(OverrideKind::Exempt).await.unwrap();
+    for _ in 0..3 {
+        assert_eq!(get_count(&test_server).await, StatusCode::OK);
+    }
+
+    let lift = || admin(&test_server, Method::DELETE, &path).send();
+    assert_eq!(lift().await.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(lift().await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(get_count(&test_server).await, StatusCode::OK);
+    assert_eq!(get_count(&test_server).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn overrides_must_end() {
+    let test_server = spawn();
+    let path = format!("/keys/{}/override", KEY);
+    let response = admin(&test_server, Method::PUT, &path)
+        .json(&KeyOverride {
+            kind: OverrideKind::Ban,
+            seconds: u64::MAX,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(get_count(&test_server).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn api_keys_are_shown_by_digest() {
+    let test_server = TestServer::spawn_server_with(
+        Settings::new("")
+            .rate_limits(
+                RateLimitConfig::counter_defaults()
+                    .key(KeyExtractor::api_key(["secret"]))
+                    .route("/api/count", Policy::token_bucket(1, 0.001)),
+            )
+            .admin_tokens(vec![TOKEN.into()]),
+    );
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .header("x-api-key", "secret")
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let top: TopKeysResponse = admin(&test_server, Method::GET, "/keys")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = top.keys[0].key.clone();
+    assert!(id.starts_with("key:"));
+
+    // a key can be named by its digest or by itself, but is only ever shown by digest
+    for name in [id.as_str(), "secret"] {
+        let state: KeyState = admin(&test_server, Method::GET, &format!("/keys/{}", name))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(state.key, id);
+    }
+
+    let response = admin(&test_server, Method::DELETE, &format!("/keys/{}", id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn policies_can_be_listed_and_created() {
+    let test_server = spawn();
+    let policies: PoliciesResponse = admin(&test_server, Method::GET, "/policies")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(policies
+        .policies
+        .iter()
+        .any(|policy| policy.route == "/api/count"));
+    assert!(!policies
+        .policies
+        .iter()
+        .any(|policy| policy.route == "/health_check"));
+
+    let health_check = RoutePolicy {
+        route: "/health_check".into(),
+        policy: Policy::FixedWindow {
+            limit: 1,
+            window_seconds: 60.0,
+        },
+    };
+    let response = admin(&test_server, Method::POST, "/policies")
+        .json(&health_check)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let check = || {
+        test_server
+            .client
+            .get(format!(
+                "http://{}:{}/health_check",
+                test_server.address, test_server.port
+            ))
+            .send()
+    };
+    assert_eq!(check().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+        check().await.unwrap().status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let invalid = RoutePolicy {
+        route: "/health_check".into(),
+        policy: Policy::Gcra {
+            limit: 0,
+            period_seconds: 1.0,
+        },
+    };
+    let response = admin(&test_server, Method::POST, "/policies")
+        .json(&invalid)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
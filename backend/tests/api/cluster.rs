@@ -0,0 +1,198 @@
+use crate::test_server::TestServer;
+use backend::{
+    cluster::{Cluster, ClusterConfig},
+    limiter::{Policy, RateLimitConfig},
+    startup::Settings,
+};
+use client::{Direction, PutCounterRequest};
+use reqwest::StatusCode;
+use std::time::Duration;
+
+/// Start `size` peers on localhost, each dialing every peer started before it.
+pub async fn spawn_cluster(size: usize) -> Vec<Cluster> {
+    let mut peers: Vec<Cluster> = Vec::new();
+    for _ in 0..size {
+        let config = ClusterConfig {
+            listen: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            bootstrap: peers
+                .iter()
+                .flat_map(|peer| peer.listen_addrs().to_vec())
+                .collect(),
+            mdns: false,
+            identity: None,
+        };
+        peers.push(Cluster::start(&config).await.expect("failed to start peer"));
+    }
+
+    for peer in &peers {
+        tokio::time::timeout(Duration::from_secs(10), peer.wait_for_peers(size - 1))
+            .await
+            .expect("peers failed to connect");
+    }
+    peers
+}
+
+async fn get_count_status(test_server: &TestServer) -> StatusCode {
+    test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed")
+        .status()
+}
+
+#[tokio::test]
+async fn rate_limits_are_shared_across_backends() {
+    let rate_limits = RateLimitConfig::default().route(
+        "/api/count",
+        Policy::fixed_window(6, Duration::from_secs(60)),
+    );
+    let servers: Vec<TestServer> = spawn_cluster(3)
+        .await
+        .into_iter()
+        .map(|peer| {
+            TestServer::spawn_server_with(
+                Settings::new("")
+                    .rate_limits(rate_limits.clone())
+                    .cluster(peer),
+            )
+        })
+        .collect();
+
+    for _ in 0..3 {
+        assert_eq!(get_count_status(&servers[0]).await, StatusCode::OK);
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // the second backend only has half the budget left
+    for _ in 0..3 {
+        assert_eq!(get_count_status(&servers[1]).await, StatusCode::OK);
+    }
+    assert_eq!(
+        get_count_status(&servers[1]).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // and the third has none, despite never having seen a request
+    assert_eq!(
+        get_count_status(&servers[2]).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+async fn wait_for_count(test_server: &TestServer, expected: i32) {
+    let url = format!(
+        "http://{}:{}/api/count",
+        test_server.address, test_server.port
+    );
+    let expected = format!(r#"{{"count":{}}}"#, expected);
+    for _ in 0..50 {
+        let body = test_server
+            .client
+            .get(&url)
+            .send()
+            .await
+            .expect("GET failed")
+            .text()
+            .await
+            .expect("GET failed");
+        if body == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("count never reached {}", expected);
+}
+
+#[tokio::test]
+async fn counter_converges_across_backends() {
+    let servers: Vec<TestServer> = spawn_cluster(2)
+        .await
+        .into_iter()
+        .map(|peer| TestServer::spawn_server_with(Settings::new("").cluster(peer)))
+        .collect();
+
+    let increment = Direction::Increment;
+    let decrement = Direction::Decrement;
+    servers[0].post_update(increment).await;
+    servers[0].post_update(increment).await;
+    servers[1].post_update(increment).await;
+    servers[1].post_update(decrement).await;
+    servers[1].post_update(increment).await;
+
+    for server in &servers {
+        wait_for_count(server, 3).await;
+    }
+}
+
+#[tokio::test]
+async fn named_counters_are_created_and_deleted_across_backends() {
+    let servers: Vec<TestServer> = spawn_cluster(2)
+        .await
+        .into_iter()
+        .map(|peer| TestServer::spawn_server_with(Settings::new("").cluster(peer)))
+        .collect();
+    let url = |server: &TestServer| {
+        format!(
+            "http://{}:{}/api/counters/apples",
+            server.address, server.port
+        )
+    };
+
+    let response = servers[0]
+        .client
+        .put(url(&servers[0]))
+        .json(&PutCounterRequest {
+            count: 4,
+            ..PutCounterRequest::default()
+        })
+        .send()
+        .await
+        .expect("PUT failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    wait_for_status(&servers[1], &url(&servers[1]), StatusCode::OK).await;
+
+    let response = servers[1]
+        .client
+        .delete(url(&servers[1]))
+        .send()
+        .await
+        .expect("DELETE failed");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    wait_for_status(&servers[0], &url(&servers[0]), StatusCode::NOT_FOUND).await;
+}
+
+async fn wait_for_status(test_server: &TestServer, url: &str, expected: StatusCode) {
+    for _ in 0..50 {
+        let status = test_server
+            .client
+            .get(url)
+            .send()
+            .await
+            .expect("GET failed")
+            .status();
+        if status == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{} never returned {}", url, expected);
+}
+
+#[tokio::test]
+async fn peers_keep_their_identity_across_restarts() {
+    let dir = tempfile::tempdir().expect("failed to create directory");
+    let config = ClusterConfig {
+        listen: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        identity: Some(dir.path().join("identity")),
+        ..ClusterConfig::default()
+    };
+    let first = Cluster::start(&config).await.expect("failed to start peer");
+    let second = Cluster::start(&config).await.expect("failed to start peer");
+    assert_eq!(first.local_peer_id(), second.local_peer_id());
+}
@@ -0,0 +1,99 @@
+use crate::test_server::TestServer;
+use backend::{
+    config::{CounterConfig, TlsConfig},
+    startup::Settings,
+    store::StoreConfig,
+};
+use client::{CounterResponse, OverflowPolicy};
+use reqwest::StatusCode;
+
+#[tokio::test]
+async fn configured_counters_exist_at_startup() {
+    let seats = CounterConfig {
+        name: "seats".into(),
+        value: 5,
+        min: 0,
+        max: 10,
+        overflow: OverflowPolicy::Saturate,
+    };
+    let test_server = TestServer::spawn_server_with(Settings::new("").counters(vec![seats]));
+
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/counters/seats",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    let counter: CounterResponse = response.json().await.unwrap();
+    assert_eq!(counter.count, 5);
+    assert_eq!((counter.min, counter.max), (0, 10));
+    assert_eq!(counter.overflow, OverflowPolicy::Saturate);
+}
+
+#[tokio::test]
+async fn configured_counters_are_saved_at_startup() {
+    let seats = CounterConfig {
+        name: "seats".into(),
+        value: 5,
+        min: 0,
+        max: 10,
+        overflow: OverflowPolicy::Saturate,
+    };
+    let store = StoreConfig::Memory.open().unwrap();
+    let test_server =
+        TestServer::spawn_server_with(Settings::new("").counters(vec![seats]).store(store.clone()));
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/health_check",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let saved = store.load().unwrap();
+    assert_eq!(saved.counters["seats"].counts.value(), 5);
+}
+
+#[tokio::test]
+async fn tls_serves_https() {
+    let dir = tempfile::tempdir().unwrap();
+    let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let tls = TlsConfig {
+        cert: dir.path().join("cert.pem"),
+        key: dir.path().join("key.pem"),
+    };
+    std::fs::write(&tls.cert, certificate.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&tls.key, certificate.serialize_private_key_pem()).unwrap();
+    let test_server = TestServer::spawn_server_with(Settings::new("").tls(tls));
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!(
+            "https://localhost:{}/health_check",
+            test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET over TLS failed");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let plain = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/health_check",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await;
+    assert!(plain.map_or(true, |response| !response.status().is_success()));
+}
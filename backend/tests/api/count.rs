@@ -1,5 +1,6 @@
 use crate::test_server::TestServer;
-use client::{CountRequest, Direction};
+use client::{CountRequest, CountResponse, Direction, ErrorKind, ErrorResponse};
+use reqwest::StatusCode;
 use tokio_tungstenite::tungstenite;
 use futures::{SinkExt, StreamExt};
 
@@ -10,15 +11,11 @@ async fn increment_and_decrement_count_works() {
 
     test_server.assert_count_value(0).await;
     test_server
-        .post_update(&CountRequest {
-            direction: Direction::Increment,
-        })
+        .post_update(Direction::Increment)
         .await;
     test_server.assert_count_value(1).await;
     test_server
-        .post_update(&CountRequest {
-            direction: Direction::Decrement,
-        })
+        .post_update(Direction::Decrement)
         .await;
     test_server.assert_count_value(0).await;
 }
@@ -45,7 +42,7 @@ async fn websocket_reads_counts() {
     
     assert_eq!(msg, r#"{"count":0}"#);
 
-    test_server.post_update(&CountRequest { direction: Direction::Increment }).await;
+    test_server.post_update(Direction::Increment).await;
     test_server.assert_count_value(1).await;
 
     assert!(socket.send(tungstenite::Message::text("foo")).await.is_ok());
@@ -57,3 +54,47 @@ async fn websocket_reads_counts() {
     
     assert_eq!(msg, r#"{"count":1}"#)
 }
+
+async fn post_request(test_server: &TestServer, request: &CountRequest) -> reqwest::Response {
+    test_server
+        .client
+        .post(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .json(request)
+        .send()
+        .await
+        .expect("POST failed")
+}
+
+#[tokio::test]
+async fn count_operations_can_be_sent_as_json() {
+    let test_server = TestServer::spawn_server();
+
+    for (request, expected) in [
+        (CountRequest::Add { amount: 10 }, 10),
+        (CountRequest::Subtract { amount: 3 }, 7),
+        (CountRequest::Set { value: -4 }, -4),
+        (CountRequest::CompareAndSwap { expected: -4, new: 2 }, 2),
+        (CountRequest::Reset, 0),
+    ] {
+        let response = post_request(&test_server, &request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: CountResponse = response.json().await.unwrap();
+        assert_eq!(body.count, expected);
+    }
+    test_server.assert_count_value(0).await;
+}
+
+#[tokio::test]
+async fn compare_and_swap_fails_on_mismatch() {
+    let test_server = TestServer::spawn_server();
+
+    let request = CountRequest::CompareAndSwap { expected: 1, new: 2 };
+    let response = post_request(&test_server, &request).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    let body: ErrorResponse = response.json().await.unwrap();
+    assert_eq!(body.error, ErrorKind::PreconditionFailed);
+    test_server.assert_count_value(0).await;
+}
@@ -0,0 +1,237 @@
+use crate::test_server::TestServer;
+use backend::startup::Settings;
+use client::{
+    CountRequest, CounterResponse, CountersResponse, Direction, ErrorKind, ErrorResponse,
+    OverflowPolicy, PutCounterRequest,
+};
+use reqwest::StatusCode;
+
+fn counter_url(test_server: &TestServer, path: &str) -> String {
+    format!(
+        "http://{}:{}/api/counters{}",
+        test_server.address, test_server.port, path
+    )
+}
+
+async fn put_counter(
+    test_server: &TestServer,
+    name: &str,
+    request: &PutCounterRequest,
+) -> reqwest::Response {
+    test_server
+        .client
+        .put(counter_url(test_server, &format!("/{}", name)))
+        .json(request)
+        .send()
+        .await
+        .expect("PUT failed")
+}
+
+async fn post_counter(
+    test_server: &TestServer,
+    name: &str,
+    direction: Direction,
+) -> reqwest::Response {
+    test_server
+        .client
+        .post(counter_url(
+            test_server,
+            &format!("/{}/{}", name, direction),
+        ))
+        .send()
+        .await
+        .expect("POST failed")
+}
+
+#[tokio::test]
+async fn named_counters_can_be_created_altered_and_deleted() {
+    let test_server = TestServer::spawn_server();
+
+    let request = PutCounterRequest {
+        count: 5,
+        ..PutCounterRequest::default()
+    };
+    let response = put_counter(&test_server, "apples", &request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = post_counter(&test_server, "apples", Direction::Increment).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<CounterResponse>().await.unwrap().count, 6);
+
+    let response = test_server
+        .client
+        .get(counter_url(&test_server, ""))
+        .send()
+        .await
+        .expect("GET failed");
+    let names: Vec<String> = response
+        .json::<CountersResponse>()
+        .await
+        .unwrap()
+        .counters
+        .into_iter()
+        .map(|counter| counter.name)
+        .collect();
+    assert_eq!(names, ["apples", "default"]);
+
+    let response = test_server
+        .client
+        .delete(counter_url(&test_server, "/apples"))
+        .send()
+        .await
+        .expect("DELETE failed");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = test_server
+        .client
+        .get(counter_url(&test_server, "/apples"))
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: ErrorResponse = response.json().await.unwrap();
+    assert_eq!(body.error, ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn count_routes_alias_the_default_counter() {
+    let test_server = TestServer::spawn_server();
+
+    test_server.post_update(Direction::Increment).await;
+
+    let counter: CounterResponse = test_server
+        .client
+        .get(counter_url(&test_server, "/default"))
+        .send()
+        .await
+        .expect("GET failed")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(counter.count, 1);
+
+    let response = test_server
+        .client
+        .delete(counter_url(&test_server, "/default"))
+        .send()
+        .await
+        .expect("DELETE failed");
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn named_counters_stay_within_their_bounds() {
+    let test_server = TestServer::spawn_server();
+
+    let request = PutCounterRequest {
+        count: 1,
+        min: Some(0),
+        max: Some(1),
+        ..PutCounterRequest::default()
+    };
+    assert_eq!(
+        put_counter(&test_server, "seats", &request).await.status(),
+        StatusCode::CREATED
+    );
+
+    let increment = Direction::Increment;
+    let response = post_counter(&test_server, "seats", increment).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body: ErrorResponse = response.json().await.unwrap();
+    assert_eq!(body.error, ErrorKind::OutOfBounds);
+
+    let decrement = Direction::Decrement;
+    let response = post_counter(&test_server, "seats", decrement).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = post_counter(&test_server, "seats", decrement).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let out_of_bounds = PutCounterRequest {
+        count: 2,
+        ..request
+    };
+    let response = put_counter(&test_server, "seats", &out_of_bounds).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn named_counters_can_saturate_or_wrap() {
+    let test_server = TestServer::spawn_server();
+    let increment = Direction::Increment;
+
+    for (name, overflow, expected) in [
+        ("saturating", OverflowPolicy::Saturate, 1),
+        ("wrapping", OverflowPolicy::Wrap, -1),
+    ] {
+        let request = PutCounterRequest {
+            count: 1,
+            min: Some(-1),
+            max: Some(1),
+            overflow,
+        };
+        put_counter(&test_server, name, &request).await;
+
+        let response = post_counter(&test_server, name, increment).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let counter: CounterResponse = response.json().await.unwrap();
+        assert_eq!(counter.count, expected);
+        assert_eq!(counter.overflow, overflow);
+
+        // only moves saturate or wrap, values set outright must be in bounds
+        let response = test_server
+            .client
+            .post(counter_url(&test_server, &format!("/{}", name)))
+            .json(&CountRequest::Set { value: 2 })
+            .send()
+            .await
+            .expect("POST failed");
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: ErrorResponse = response.json().await.unwrap();
+        assert_eq!(body.error, ErrorKind::OutOfBounds);
+    }
+}
+
+#[tokio::test]
+async fn named_counters_accept_json_operations() {
+    let test_server = TestServer::spawn_server();
+    let request = PutCounterRequest {
+        max: Some(10),
+        ..PutCounterRequest::default()
+    };
+    put_counter(&test_server, "apples", &request).await;
+
+    let response = test_server
+        .client
+        .post(counter_url(&test_server, "/apples"))
+        .json(&CountRequest::Add { amount: 4 })
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<CounterResponse>().await.unwrap().count, 4);
+
+    let response = test_server
+        .client
+        .post(counter_url(&test_server, "/apples"))
+        .json(&CountRequest::Set { value: 11 })
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn counters_cannot_be_created_beyond_the_maximum() {
+    let test_server = TestServer::spawn_server_with(Settings::new("").max_counters(2));
+    let request = PutCounterRequest::default();
+    let response = put_counter(&test_server, "apples", &request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = put_counter(&test_server, "pears", &request).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let error: ErrorResponse = response.json().await.unwrap();
+    assert_eq!(error.error, ErrorKind::Conflict);
+
+    let response = put_counter(&test_server, "apples", &request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
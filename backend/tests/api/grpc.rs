@@ -0,0 +1,151 @@
+use crate::test_server::TestServer;
+use backend::grpc::proto::{
+    alter_count_request::Op, counter_service_client::CounterServiceClient, AlterCountRequest,
+    Empty, GetCountRequest, WatchCountRequest,
+};
+use backend::{
+    limiter::{KeyExtractor, Policy, RateLimitConfig},
+    startup::Settings,
+};
+use client::Direction;
+use futures::StreamExt;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::time::Duration;
+use tonic::{transport::Channel, Code, Request};
+
+async fn spawn_with_grpc() -> (TestServer, CounterServiceClient<Channel>) {
+    spawn_with_grpc_and(Settings::new("")).await
+}
+
+async fn spawn_with_grpc_and(settings: Settings) -> (TestServer, CounterServiceClient<Channel>) {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .expect("failed to bind gRPC socket");
+    let port = listener.local_addr().unwrap().port();
+    let test_server = TestServer::spawn_server_with(settings.grpc(listener));
+    let client = CounterServiceClient::connect(format!("http://127.0.0.1:{}", port))
+        .await
+        .expect("failed to connect over gRPC");
+    (test_server, client)
+}
+
+fn alter(op: Op) -> AlterCountRequest {
+    AlterCountRequest {
+        name: String::new(),
+        op: Some(op),
+    }
+}
+
+#[tokio::test]
+async fn grpc_reads_and_alters_the_count() {
+    let (test_server, mut client) = spawn_with_grpc().await;
+
+    let reply = client.alter_count(alter(Op::Add(5))).await.unwrap();
+    assert_eq!(reply.into_inner().count, 5);
+    let reply = client
+        .alter_count(alter(Op::Decrement(Empty {})))
+        .await
+        .unwrap();
+    assert_eq!(reply.into_inner().count, 4);
+    test_server.assert_count_value(4).await;
+
+    test_server.post_update(Direction::Increment).await;
+    let reply = client.get_count(GetCountRequest::default()).await.unwrap();
+    assert_eq!(reply.into_inner().count, 5);
+}
+
+#[tokio::test]
+async fn grpc_reports_errors_with_status_codes() {
+    let (_test_server, mut client) = spawn_with_grpc().await;
+
+    let status = client
+        .get_count(GetCountRequest {
+            name: "missing".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = client
+        .alter_count(alter(Op::Set(i32::MIN)))
+        .await
+        .and(client.alter_count(alter(Op::Decrement(Empty {}))).await)
+        .unwrap_err();
+    assert_eq!(status.code(), Code::OutOfRange);
+}
+
+#[tokio::test]
+async fn grpc_watch_streams_changes() {
+    let (test_server, mut client) = spawn_with_grpc().await;
+
+    let mut counts = client
+        .watch_count(WatchCountRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(counts.next().await.unwrap().unwrap().count, 0);
+
+    test_server.post_update(Direction::Increment).await;
+    assert_eq!(counts.next().await.unwrap().unwrap().count, 1);
+}
+
+#[tokio::test]
+async fn grpc_changes_need_a_valid_api_key() {
+    let rate_limits = RateLimitConfig::default()
+        .key(KeyExtractor::api_key(["secret"]))
+        .route("/api/count", Policy::gcra(1, Duration::from_secs(60)));
+    let (_test_server, mut client) =
+        spawn_with_grpc_and(Settings::new("").rate_limits(rate_limits)).await;
+    let with_key = |key: &str, request: AlterCountRequest| {
+        let mut request = Request::new(request);
+        request
+            .metadata_mut()
+            .insert("x-api-key", key.parse().unwrap());
+        request
+    };
+
+    let status = client
+        .alter_count(alter(Op::Increment(Empty {})))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = client
+        .alter_count(with_key("guess", alter(Op::Increment(Empty {}))))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // a call without an op is refused before it spends any of the budget
+    let status = client
+        .alter_count(with_key("secret", AlterCountRequest::default()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    let reply = client
+        .alter_count(with_key("secret", alter(Op::Increment(Empty {}))))
+        .await
+        .unwrap();
+    assert_eq!(reply.into_inner().count, 1);
+}
+
+#[tokio::test]
+async fn grpc_refuses_denied_callers_everything() {
+    let rate_limits = RateLimitConfig::default().deny("127.0.0.0/8".parse().unwrap());
+    let (_test_server, mut client) =
+        spawn_with_grpc_and(Settings::new("").rate_limits(rate_limits)).await;
+
+    let status = client
+        .get_count(GetCountRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    let status = client
+        .watch_count(WatchCountRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    let status = client
+        .alter_count(alter(Op::Increment(Empty {})))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+}
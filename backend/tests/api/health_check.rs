@@ -5,7 +5,7 @@ async fn health_check_works() {
     let test_server = TestServer::spawn_server();
 
     let response = test_server.client
-        .get(&format!(
+        .get(format!(
             "http://{}:{}/health_check",
             test_server.address, test_server.port,
         ))
@@ -0,0 +1,112 @@
+use crate::test_server::TestServer;
+use backend::limiter::{KeyExtractor, RateLimitConfig};
+use client::{CountRequest, Direction, HistoryQuery, HistoryResponse};
+use reqwest::StatusCode;
+
+async fn get_history(test_server: &TestServer, query: &HistoryQuery) -> HistoryResponse {
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count/history",
+            test_server.address, test_server.port
+        ))
+        .query(query)
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn history_records_who_changed_the_count() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default().key(KeyExtractor::Header {
+            name: "x-client-id".into(),
+        }),
+    );
+
+    let response = test_server
+        .client
+        .post(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .header("x-client-id", "alice")
+        .header("x-request-id", "request-1")
+        .json(&CountRequest::Add { amount: 5 })
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.headers()["x-request-id"], "request-1");
+    test_server.post_update(Direction::Decrement).await;
+
+    let history = get_history(&test_server, &HistoryQuery::default()).await;
+    assert_eq!(history.next, None);
+    let events = history.events;
+    assert_eq!(events.len(), 2);
+    assert_eq!((events[0].delta, events[0].count), (5, 5));
+    assert_eq!(events[0].client.as_deref(), Some("alice"));
+    assert_eq!(events[0].request_id.as_deref(), Some("request-1"));
+    assert_eq!((events[1].delta, events[1].count), (-1, 4));
+    assert_eq!(events[1].client, None);
+    // requests without an id are given one
+    assert!(events[1].request_id.is_some());
+}
+
+#[tokio::test]
+async fn history_does_not_reveal_api_keys() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default().key(KeyExtractor::api_key(["secret"])),
+    );
+
+    let response = test_server
+        .client
+        .post(format!(
+            "http://{}:{}/api/count/incr",
+            test_server.address, test_server.port
+        ))
+        .header("x-api-key", "secret")
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let history = get_history(&test_server, &HistoryQuery::default()).await;
+    let client = history.events[0].client.as_deref().unwrap();
+    assert!(client.starts_with("key:"), "{}", client);
+    assert!(!client.contains("secret"));
+}
+
+#[tokio::test]
+async fn history_is_paginated() {
+    let test_server = TestServer::spawn_server();
+    for _ in 0..3 {
+        test_server.post_update(Direction::Increment).await;
+    }
+
+    let mut query = HistoryQuery {
+        limit: Some(2),
+        ..HistoryQuery::default()
+    };
+    let first = get_history(&test_server, &query).await;
+    assert_eq!(first.events.len(), 2);
+    assert!(first.next.is_some());
+
+    query.after = first.next;
+    let second = get_history(&test_server, &query).await;
+    assert_eq!(second.events.len(), 1);
+    assert_eq!(second.events[0].count, 3);
+    assert_eq!(second.next, None);
+
+    let until = first.events[0].timestamp;
+    let none = get_history(
+        &test_server,
+        &HistoryQuery {
+            until: Some(until),
+            ..HistoryQuery::default()
+        },
+    )
+    .await;
+    assert!(none.events.is_empty());
+}
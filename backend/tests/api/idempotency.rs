@@ -0,0 +1,96 @@
+use crate::test_server::TestServer;
+use backend::startup::Settings;
+use client::{CountRequest, CountResponse, IdempotencyKey, IDEMPOTENCY_KEY_HEADER};
+use reqwest::StatusCode;
+use std::time::Duration;
+
+async fn post_with_key(test_server: &TestServer, key: &IdempotencyKey) -> reqwest::Response {
+    test_server
+        .client
+        .post(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .header(IDEMPOTENCY_KEY_HEADER, key.as_str())
+        .json(&CountRequest::Add { amount: 5 })
+        .send()
+        .await
+        .expect("POST failed")
+}
+
+#[tokio::test]
+async fn replayed_keys_return_the_original_response() {
+    let test_server = TestServer::spawn_server();
+    let key = IdempotencyKey::generate();
+
+    let first = post_with_key(&test_server, &key).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert!(first.headers().get("idempotent-replayed").is_none());
+    assert_eq!(first.json::<CountResponse>().await.unwrap().count, 5);
+
+    let replay = post_with_key(&test_server, &key).await;
+    assert_eq!(replay.status(), StatusCode::OK);
+    assert_eq!(replay.headers()["idempotent-replayed"], "true");
+    assert_eq!(replay.json::<CountResponse>().await.unwrap().count, 5);
+    test_server.assert_count_value(5).await;
+
+    // a new key is a new mutation
+    post_with_key(&test_server, &IdempotencyKey::generate()).await;
+    test_server.assert_count_value(10).await;
+}
+
+#[tokio::test]
+async fn keys_reused_with_another_body_are_unprocessable() {
+    let test_server = TestServer::spawn_server();
+    let key = IdempotencyKey::generate();
+    post_with_key(&test_server, &key).await;
+
+    let response = test_server
+        .client
+        .post(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .header(IDEMPOTENCY_KEY_HEADER, key.as_str())
+        .json(&CountRequest::Add { amount: 7 })
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    test_server.assert_count_value(5).await;
+}
+
+#[tokio::test]
+async fn keys_are_forgotten_after_their_ttl() {
+    let test_server = TestServer::spawn_server_with(
+        Settings::new("").idempotency_ttl(Duration::from_millis(100)),
+    );
+    let key = IdempotencyKey::generate();
+
+    post_with_key(&test_server, &key).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    post_with_key(&test_server, &key).await;
+    test_server.assert_count_value(10).await;
+}
+
+#[tokio::test]
+async fn path_form_accepts_keys_too() {
+    let test_server = TestServer::spawn_server();
+    let key = IdempotencyKey::generate();
+    let url = format!(
+        "http://{}:{}/api/count/incr",
+        test_server.address, test_server.port
+    );
+
+    for _ in 0..3 {
+        let response = test_server
+            .client
+            .post(&url)
+            .header(IDEMPOTENCY_KEY_HEADER, key.as_str())
+            .send()
+            .await
+            .expect("POST failed");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    test_server.assert_count_value(1).await;
+}
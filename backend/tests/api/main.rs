@@ -1,4 +1,21 @@
+mod access;
+mod admin;
+mod cluster;
+mod config;
 mod count;
+mod counters;
+mod grpc;
 mod health_check;
+mod history;
+mod idempotency;
+mod metrics;
+mod rate_limit;
+mod reload;
+mod resp;
+mod socket;
+mod shutdown;
+mod sse;
+mod stats;
+mod store;
 mod test_server;
 
@@ -0,0 +1,69 @@
+use crate::test_server::TestServer;
+use backend::limiter::{Policy, RateLimitConfig};
+use client::Direction;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+async fn scrape(test_server: &TestServer) -> String {
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/metrics",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("application/openmetrics-text"));
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn metrics_cover_requests_and_counters() {
+    let test_server = TestServer::spawn_server();
+    test_server.post_update(Direction::Increment).await;
+    test_server.post_update(Direction::Increment).await;
+    test_server.assert_count_value(2).await;
+
+    let body = scrape(&test_server).await;
+    assert!(body.contains(
+        r#"http_requests_total{method="POST",route="/api/count/:direction",status="200"} 2"#
+    ));
+    assert!(body.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/api/count",status="200"} 1"#
+    ));
+    assert!(body.contains(r#"limitrs_counter_value{name="default"} 2"#));
+    assert!(body.contains("limitrs_websocket_connections 0"));
+    assert!(body.contains("process_start_time_seconds"));
+}
+
+#[tokio::test]
+async fn metrics_count_rate_limit_decisions() {
+    let test_server = TestServer::spawn_server_with_limits(RateLimitConfig::default().route(
+        "/api/count",
+        Policy::fixed_window(1, Duration::from_secs(60)),
+    ));
+    test_server.assert_count_value(0).await;
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let body = scrape(&test_server).await;
+    assert!(body.contains(
+        r#"limitrs_rate_limit_decisions_total{route="/api/count",decision="allowed"} 1"#
+    ));
+    assert!(body
+        .contains(r#"limitrs_rate_limit_decisions_total{route="/api/count",decision="denied"} 1"#));
+    assert!(body.contains(r#"route="/api/count",status="429"} 1"#));
+}
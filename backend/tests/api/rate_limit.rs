@@ -0,0 +1,322 @@
+use crate::test_server::TestServer;
+use backend::limiter::{KeyExtractor, Policy, RateLimitConfig};
+use client::{CountRequest, ErrorKind, ErrorResponse};
+use reqwest::StatusCode;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+#[tokio::test]
+async fn requests_beyond_capacity_are_rejected() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default().route("/api/count", Policy::token_bucket(2, 0.01)),
+    );
+    let url = format!(
+        "http://{}:{}/api/count",
+        test_server.address, test_server.port
+    );
+
+    for _ in 0..2 {
+        let response = test_server
+            .client
+            .get(&url)
+            .send()
+            .await
+            .expect("GET failed");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = test_server
+        .client
+        .get(&url)
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn routes_are_limited_independently() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default()
+            .route("/api/count", Policy::token_bucket(1, 0.01))
+            .route(
+                "/api/count/:direction",
+                Policy::fixed_window(5, Duration::from_secs(60)),
+            ),
+    );
+    let get_url = format!(
+        "http://{}:{}/api/count",
+        test_server.address, test_server.port
+    );
+    let post_url = format!(
+        "http://{}:{}/api/count/incr",
+        test_server.address, test_server.port
+    );
+
+    let response = test_server
+        .client
+        .get(&get_url)
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = test_server
+        .client
+        .get(&get_url)
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let response = test_server
+        .client
+        .post(&post_url)
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn methods_on_one_route_can_be_limited_apart() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default()
+            .route("/api/count", Policy::token_bucket(5, 0.01))
+            .route("POST /api/count", Policy::token_bucket(1, 0.01)),
+    );
+    let url = format!(
+        "http://{}:{}/api/count",
+        test_server.address, test_server.port
+    );
+
+    let post = || {
+        test_server
+            .client
+            .post(&url)
+            .json(&CountRequest::Increment)
+            .send()
+    };
+    assert_eq!(post().await.expect("POST failed").status(), StatusCode::OK);
+    assert_eq!(
+        post().await.expect("POST failed").status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let response = test_server
+        .client
+        .get(&url)
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["ratelimit-limit"], "5");
+}
+
+#[tokio::test]
+async fn unlimited_routes_are_not_affected() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default().route("/api/count", Policy::token_bucket(1, 0.01)),
+    );
+
+    for _ in 0..5 {
+        let response = test_server
+            .client
+            .get(format!(
+                "http://{}:{}/health_check",
+                test_server.address, test_server.port,
+            ))
+            .send()
+            .await
+            .expect("Failed to send GET request");
+        assert!(response.status().is_success());
+    }
+}
+
+#[tokio::test]
+async fn header_keys_get_separate_budgets() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default()
+            .key(KeyExtractor::Header {
+                name: "x-client-id".into(),
+            })
+            .route("/api/count", Policy::token_bucket(1, 0.01)),
+    );
+    let url = format!(
+        "http://{}:{}/api/count",
+        test_server.address, test_server.port
+    );
+
+    for (client, expected) in [
+        ("alice", StatusCode::OK),
+        ("bob", StatusCode::OK),
+        ("alice", StatusCode::TOO_MANY_REQUESTS),
+    ] {
+        let response = test_server
+            .client
+            .get(&url)
+            .header("x-client-id", client)
+            .send()
+            .await
+            .expect("GET failed");
+        assert_eq!(response.status(), expected, "client {}", client);
+    }
+}
+
+#[tokio::test]
+async fn forwarded_clients_behind_trusted_proxy_get_separate_budgets() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default()
+            .key(KeyExtractor::ForwardedFor {
+                trusted_proxies: vec![Ipv4Addr::LOCALHOST.into()],
+            })
+            .route("/api/count", Policy::token_bucket(1, 0.01)),
+    );
+    let url = format!(
+        "http://{}:{}/api/count",
+        test_server.address, test_server.port
+    );
+
+    for (client, expected) in [
+        ("192.0.2.1", StatusCode::OK),
+        ("192.0.2.2", StatusCode::OK),
+        ("192.0.2.1", StatusCode::TOO_MANY_REQUESTS),
+    ] {
+        let response = test_server
+            .client
+            .get(&url)
+            .header("x-forwarded-for", client)
+            .send()
+            .await
+            .expect("GET failed");
+        assert_eq!(response.status(), expected, "client {}", client);
+    }
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_api_key_are_unauthorized() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default()
+            .key(KeyExtractor::api_key(["secret"]))
+            .route("/api/count", Policy::token_bucket(5, 0.01)),
+    );
+    let url = format!(
+        "http://{}:{}/api/count",
+        test_server.address, test_server.port
+    );
+
+    let response = test_server
+        .client
+        .get(&url)
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = test_server
+        .client
+        .get(&url)
+        .header("x-api-key", "guess")
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = test_server
+        .client
+        .get(&url)
+        .header("x-api-key", "secret")
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unlimited_routes_need_no_api_key() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default()
+            .key(KeyExtractor::api_key(["secret"]))
+            .route("/api/count", Policy::token_bucket(5, 0.01)),
+    );
+
+    for path in ["/health_check", "/metrics", "/api/count/stats"] {
+        let response = test_server
+            .client
+            .get(format!(
+                "http://{}:{}{}",
+                test_server.address, test_server.port, path
+            ))
+            .send()
+            .await
+            .expect("GET failed");
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn limited_routes_report_their_budget() {
+    let test_server = TestServer::spawn_server_with_limits(RateLimitConfig::default().route(
+        "/api/count/:direction",
+        Policy::fixed_window(2, Duration::from_secs(60)),
+    ));
+    let url = format!(
+        "http://{}:{}/api/count/incr",
+        test_server.address, test_server.port
+    );
+
+    let response = test_server
+        .client
+        .post(&url)
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers["ratelimit-limit"], "2");
+    assert_eq!(headers["ratelimit-remaining"], "1");
+    assert_eq!(headers["ratelimit-policy"], "2;w=60");
+    assert!(headers.contains_key("ratelimit-reset"));
+    assert!(!headers.contains_key("retry-after"));
+
+    test_server
+        .client
+        .post(&url)
+        .send()
+        .await
+        .expect("POST failed");
+
+    let response = test_server
+        .client
+        .post(&url)
+        .send()
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["ratelimit-remaining"], "0");
+    let retry_after: u64 = response.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after));
+
+    let body: ErrorResponse = response.json().await.expect("invalid error body");
+    assert_eq!(body.error, ErrorKind::RateLimited);
+    assert_eq!(body.retry_after, Some(retry_after));
+}
+
+#[tokio::test]
+async fn unlimited_routes_have_no_rate_limit_headers() {
+    let test_server = TestServer::spawn_server();
+
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed");
+    assert!(!response.headers().contains_key("ratelimit-limit"));
+}
@@ -0,0 +1,162 @@
+use crate::{admin::TOKEN, test_server::TestServer};
+use backend::{config::Config, reload::ConfigSource, startup::Settings};
+use client::{ConfigStatus, CountResponse, ReloadTrigger, SocketMessage};
+use futures::StreamExt;
+use reqwest::StatusCode;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite;
+
+const LIMITED: &str = r#"
+[rate_limits.routes."/api/count"]
+algorithm = "token_bucket"
+capacity = 1
+refill_per_second = 0.001
+"#;
+
+const DIRECTION_LIMITED: &str = r#"
+[rate_limits.routes."/api/count/:direction"]
+algorithm = "token_bucket"
+capacity = 1
+refill_per_second = 0.001
+"#;
+
+async fn config_status(test_server: &TestServer) -> ConfigStatus {
+    test_server
+        .client
+        .get(format!(
+            "http://{}:{}/admin/config",
+            test_server.address, test_server.port
+        ))
+        .bearer_auth(TOKEN)
+        .send()
+        .await
+        .expect("GET failed")
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn editing_the_config_file_swaps_limits_in_place() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), LIMITED).unwrap();
+    let config = Config::load(Some(file.path()), std::iter::empty()).unwrap();
+    let source = ConfigSource::file(file.path()).unwrap();
+    let test_server = TestServer::spawn_server_with(
+        Settings::new("")
+            .rate_limits(config.rate_limits)
+            .reload(source)
+            .admin_tokens(vec![TOKEN.into()]),
+    );
+    let url = |path: &str| {
+        format!(
+            "http://{}:{}{}",
+            test_server.address, test_server.port, path
+        )
+    };
+
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!(
+        "ws://{}:{}/ws/count",
+        test_server.address, test_server.port
+    ))
+    .await
+    .unwrap();
+    let get = || test_server.client.get(url("/api/count")).send();
+    assert_eq!(get().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(get().await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(config_status(&test_server).await.generation, 0);
+
+    std::fs::write(file.path(), format!("{}{}", LIMITED, DIRECTION_LIMITED)).unwrap();
+    let status = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let status = config_status(&test_server).await;
+            if status.generation > 0 {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the edit was never reloaded");
+    let reload = &status.reloads[0];
+    assert_eq!(reload.trigger, ReloadTrigger::File);
+    assert_eq!(reload.applied, ["rate_limits.routes./api/count/:direction"]);
+    assert_eq!(reload.error, None);
+
+    // the unchanged route keeps its exhausted budget
+    assert_eq!(get().await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    let post = || test_server.client.post(url("/api/count/incr")).send();
+    assert_eq!(post().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+        post().await.unwrap().status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // and the socket opened before the reload is still told about changes
+    loop {
+        match socket.next().await.unwrap().unwrap() {
+            tungstenite::Message::Text(text) => {
+                if let SocketMessage::Count(CountResponse { count: 1, .. }) =
+                    serde_json::from_str(&text).unwrap()
+                {
+                    break;
+                }
+            }
+            tungstenite::Message::Ping(_) => continue,
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn invalid_edits_are_reported_and_refused() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), LIMITED).unwrap();
+    let source = ConfigSource::file(file.path()).unwrap();
+    let test_server = TestServer::spawn_server_with(
+        Settings::new("")
+            .reload(source)
+            .admin_tokens(vec![TOKEN.into()]),
+    );
+
+    std::fs::write(
+        file.path(),
+        "[rate_limits.routes.\"/api/count\"]\nalgorithm = \"nope\"\n",
+    )
+    .unwrap();
+    let status = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let status = config_status(&test_server).await;
+            if !status.reloads.is_empty() {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the edit was never reloaded");
+    assert_eq!(status.generation, 0);
+    assert_eq!(status.path, Some(file.path().display().to_string()));
+    assert!(status.reloads[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("rate_limits.routes"));
+}
+
+#[tokio::test]
+async fn config_status_needs_a_reloadable_config() {
+    let test_server =
+        TestServer::spawn_server_with(Settings::new("").admin_tokens(vec![TOKEN.into()]));
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/admin/config",
+            test_server.address, test_server.port
+        ))
+        .bearer_auth(TOKEN)
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,64 @@
+use crate::test_server::TestServer;
+use backend::startup::Settings;
+use client::PutCounterRequest;
+use reqwest::StatusCode;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn spawn_with_resp() -> (TestServer, TcpStream) {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .expect("failed to bind RESP socket");
+    let port = listener.local_addr().unwrap().port();
+    let test_server = TestServer::spawn_server_with(Settings::new("").resp(listener));
+    let stream = TcpStream::connect(("127.0.0.1", port))
+        .await
+        .expect("failed to connect over RESP");
+    (test_server, stream)
+}
+
+/// Send `command` as an array of bulk strings, as Redis clients do, and read the reply.
+async fn send(stream: &mut TcpStream, command: &[&str]) -> String {
+    let mut request = format!("*{}\r\n", command.len());
+    for arg in command {
+        request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut reply = vec![0; 64];
+    let read = stream.read(&mut reply).await.unwrap();
+    String::from_utf8(reply[..read].to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn resp_commands_drive_the_counters() {
+    let (test_server, mut stream) = spawn_with_resp().await;
+
+    assert_eq!(send(&mut stream, &["PING"]).await, "+PONG\r\n");
+    assert_eq!(
+        send(&mut stream, &["INCRBY", "default", "5"]).await,
+        ":5\r\n"
+    );
+    assert_eq!(send(&mut stream, &["DECR", "default"]).await, ":4\r\n");
+    test_server.assert_count_value(4).await;
+
+    assert_eq!(send(&mut stream, &["GET", "apples"]).await, "$-1\r\n");
+    assert_eq!(
+        send(&mut stream, &["SET", "apples", "12"]).await,
+        "-ERR no counter named apples\r\n"
+    );
+    let response = test_server
+        .client
+        .put(format!(
+            "http://{}:{}/api/counters/apples",
+            test_server.address, test_server.port
+        ))
+        .json(&PutCounterRequest::default())
+        .send()
+        .await
+        .expect("PUT failed");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(send(&mut stream, &["SET", "apples", "12"]).await, "+OK\r\n");
+    assert_eq!(send(&mut stream, &["GET", "apples"]).await, "$2\r\n12\r\n");
+    assert!(send(&mut stream, &["FLUSHALL"]).await.starts_with("-ERR"));
+}
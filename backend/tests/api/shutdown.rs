@@ -0,0 +1,90 @@
+use crate::test_server::TestServer;
+use backend::{startup::Settings, store::StoreConfig};
+use client::Direction;
+use futures::StreamExt;
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::{self, protocol::frame::coding::CloseCode};
+
+const SHUTDOWN_LIMIT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn shutdown_sends_websockets_a_going_away_close() {
+    let test_server = TestServer::spawn_server();
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!(
+        "ws://{}:{}/ws/count",
+        test_server.address, test_server.port
+    ))
+    .await
+    .unwrap();
+    // wait for the initial count, so the socket is being served
+    loop {
+        if let tungstenite::Message::Text(_) = socket.next().await.unwrap().unwrap() {
+            break;
+        }
+    }
+
+    let shutdown = tokio::spawn(test_server.shutdown());
+    let frame = loop {
+        match socket.next().await.unwrap().unwrap() {
+            tungstenite::Message::Close(frame) => break frame.expect("close without a frame"),
+            _ => continue,
+        }
+    };
+    assert_eq!(frame.code, CloseCode::Away);
+    timeout(SHUTDOWN_LIMIT, shutdown)
+        .await
+        .expect("server still running")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_ends_event_streams() {
+    let test_server = TestServer::spawn_server();
+    let mut response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/sse/count",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed");
+    assert!(response.chunk().await.unwrap().is_some());
+
+    timeout(SHUTDOWN_LIMIT, test_server.shutdown())
+        .await
+        .expect("server still running");
+    while let Some(_chunk) = response.chunk().await.unwrap() {}
+}
+
+#[tokio::test]
+async fn shutdown_stops_accepting_connections() {
+    let test_server = TestServer::spawn_server();
+    let url = format!(
+        "http://{}:{}/health_check",
+        test_server.address, test_server.port
+    );
+    timeout(SHUTDOWN_LIMIT, test_server.shutdown())
+        .await
+        .expect("server still running");
+
+    assert!(reqwest::Client::new().get(&url).send().await.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_saves_the_latest_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = StoreConfig::File(dir.path().join("counters.wal"));
+    let test_server =
+        TestServer::spawn_server_with(Settings::new("").store(config.open().unwrap()));
+    test_server.post_update(Direction::Increment).await;
+    test_server.post_update(Direction::Increment).await;
+    // no waiting for the background save: shutting down must do it
+    timeout(SHUTDOWN_LIMIT, test_server.shutdown())
+        .await
+        .expect("server still running");
+
+    let restarted = TestServer::spawn_server_with(Settings::new("").store(config.open().unwrap()));
+    restarted.assert_count_value(2).await;
+}
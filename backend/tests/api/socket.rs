@@ -0,0 +1,151 @@
+use crate::test_server::TestServer;
+use backend::limiter::{KeyExtractor, RateLimitConfig};
+use client::{Command, ErrorKind, Reply, SocketCommand, SocketMessage, SocketReply};
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{self, client::IntoClientRequest},
+    MaybeTlsStream, WebSocketStream,
+};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(test_server: &TestServer) -> Socket {
+    connect_with_key(test_server, None).await
+}
+
+async fn connect_with_key(test_server: &TestServer, api_key: Option<&str>) -> Socket {
+    let mut request = format!("ws://{}:{}/ws/count", test_server.address, test_server.port)
+        .into_client_request()
+        .unwrap();
+    if let Some(api_key) = api_key {
+        request
+            .headers_mut()
+            .insert("x-api-key", api_key.parse().unwrap());
+    }
+    let (socket, _response) = tokio_tungstenite::connect_async(request).await.unwrap();
+    socket
+}
+
+async fn next_message(socket: &mut Socket) -> SocketMessage {
+    loop {
+        match socket.next().await.unwrap().unwrap() {
+            tungstenite::Message::Text(msg) => return serde_json::from_str(&msg).unwrap(),
+            tungstenite::Message::Ping(_) => continue,
+            _other => panic!("unexpected message"),
+        }
+    }
+}
+
+async fn next_reply(socket: &mut Socket) -> SocketReply {
+    loop {
+        if let SocketMessage::Reply(reply) = next_message(socket).await {
+            return reply;
+        }
+    }
+}
+
+async fn send(socket: &mut Socket, id: u64, command: Command) {
+    let command = serde_json::to_string(&SocketCommand { id, command }).unwrap();
+    socket
+        .send(tungstenite::Message::text(command))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn commands_alter_the_count_over_the_socket() {
+    let test_server = TestServer::spawn_server();
+    let mut socket = connect(&test_server).await;
+
+    send(&mut socket, 1, Command::Increment).await;
+    let reply = next_reply(&mut socket).await;
+    assert_eq!(
+        reply,
+        SocketReply {
+            id: Some(1),
+            reply: Reply::Count { count: 1 }
+        }
+    );
+    test_server.assert_count_value(1).await;
+
+    send(&mut socket, 2, Command::Ping).await;
+    assert_eq!(next_reply(&mut socket).await.reply, Reply::Pong);
+
+    socket
+        .send(tungstenite::Message::text(
+            r#"{"id":3,"command":"Explode"}"#,
+        ))
+        .await
+        .unwrap();
+    let reply = next_reply(&mut socket).await;
+    assert_eq!(reply.id, Some(3));
+    assert!(matches!(
+        reply.reply,
+        Reply::Error {
+            error: ErrorKind::InvalidRequest,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn unsubscribed_sockets_only_receive_replies() {
+    let test_server = TestServer::spawn_server();
+    let mut socket = connect(&test_server).await;
+    assert!(matches!(
+        next_message(&mut socket).await,
+        SocketMessage::Count(_)
+    ));
+
+    send(&mut socket, 1, Command::Unsubscribe).await;
+    assert_eq!(next_reply(&mut socket).await.reply, Reply::Unsubscribed);
+
+    test_server.post_update(client::Direction::Increment).await;
+    send(&mut socket, 2, Command::Get).await;
+    // the change was not pushed, so the next message is the reply
+    match next_message(&mut socket).await {
+        SocketMessage::Reply(reply) => assert_eq!(reply.reply, Reply::Count { count: 1 }),
+        other => panic!("expected a reply, got {:?}", other),
+    }
+
+    send(&mut socket, 3, Command::Subscribe).await;
+    assert_eq!(next_reply(&mut socket).await.reply, Reply::Subscribed);
+    match next_message(&mut socket).await {
+        SocketMessage::Count(count) => assert_eq!(count.count, 1),
+        other => panic!("expected the count, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn sockets_without_a_valid_api_key_cannot_alter_the_count() {
+    let test_server = TestServer::spawn_server_with_limits(
+        RateLimitConfig::default().key(KeyExtractor::api_key(["secret"])),
+    );
+
+    for api_key in [None, Some("guess")] {
+        let mut socket = connect_with_key(&test_server, api_key).await;
+        send(&mut socket, 1, Command::Increment).await;
+        assert!(matches!(
+            next_reply(&mut socket).await.reply,
+            Reply::Error {
+                error: ErrorKind::Unauthorized,
+                ..
+            }
+        ));
+        // but it can still watch
+        send(&mut socket, 2, Command::Get).await;
+        assert_eq!(
+            next_reply(&mut socket).await.reply,
+            Reply::Count { count: 0 }
+        );
+    }
+
+    let mut socket = connect_with_key(&test_server, Some("secret")).await;
+    send(&mut socket, 1, Command::Increment).await;
+    assert_eq!(
+        next_reply(&mut socket).await.reply,
+        Reply::Count { count: 1 }
+    );
+    test_server.assert_count_value(1).await;
+}
@@ -0,0 +1,83 @@
+use crate::test_server::TestServer;
+use client::{CountResponse, Direction};
+
+/// Reads Server-Sent Events from a streaming response as `(id, count)` pairs.
+struct Events {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl Events {
+    async fn connect(test_server: &TestServer, last_event_id: Option<&str>) -> Events {
+        let mut request = test_server.client.get(format!(
+            "http://{}:{}/sse/count",
+            test_server.address, test_server.port
+        ));
+        if let Some(id) = last_event_id {
+            request = request.header("last-event-id", id);
+        }
+        let response = request.send().await.expect("GET failed");
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        Events {
+            response,
+            buffer: String::new(),
+        }
+    }
+
+    async fn next(&mut self) -> (String, i32) {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let event: String = self.buffer.drain(..end + 2).collect();
+                let (mut id, mut data) = (None, None);
+                for line in event.lines() {
+                    if let Some(value) = line.strip_prefix("id:") {
+                        id = Some(value.trim().to_owned());
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        data = Some(value.trim().to_owned());
+                    }
+                }
+                // keep-alive comments carry neither
+                if let (Some(id), Some(data)) = (id, data) {
+                    let response: CountResponse = serde_json::from_str(&data).unwrap();
+                    return (id, response.count);
+                }
+                continue;
+            }
+            let chunk = self
+                .response
+                .chunk()
+                .await
+                .expect("stream failed")
+                .expect("stream ended");
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn sse_streams_count_changes() {
+    let test_server = TestServer::spawn_server();
+    let mut events = Events::connect(&test_server, None).await;
+    assert_eq!(events.next().await, ("0".to_owned(), 0));
+
+    test_server.post_update(Direction::Increment).await;
+    assert_eq!(events.next().await, ("1".to_owned(), 1));
+    test_server.post_update(Direction::Increment).await;
+    assert_eq!(events.next().await, ("2".to_owned(), 2));
+}
+
+#[tokio::test]
+async fn sse_resumes_from_last_event_id() {
+    let test_server = TestServer::spawn_server();
+    for _ in 0..3 {
+        test_server.post_update(Direction::Increment).await;
+    }
+
+    let mut events = Events::connect(&test_server, Some("1")).await;
+    assert_eq!(events.next().await, ("2".to_owned(), 2));
+    assert_eq!(events.next().await, ("3".to_owned(), 3));
+
+    test_server.post_update(Direction::Decrement).await;
+    assert_eq!(events.next().await, ("4".to_owned(), 2));
+}
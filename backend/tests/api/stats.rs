@@ -0,0 +1,71 @@
+use crate::test_server::TestServer;
+use client::{CountRequest, CountResponse, CountStats, Direction, Movement};
+use futures::{Stream, StreamExt};
+use reqwest::StatusCode;
+use tokio_tungstenite::tungstenite;
+
+#[tokio::test]
+async fn stats_total_recent_movement() {
+    let test_server = TestServer::spawn_server();
+    test_server
+        .client
+        .post(format!(
+            "http://{}:{}/api/count",
+            test_server.address, test_server.port
+        ))
+        .json(&CountRequest::Add { amount: 5 })
+        .send()
+        .await
+        .expect("POST failed");
+    test_server.post_update(Direction::Decrement).await;
+
+    let response = test_server
+        .client
+        .get(format!(
+            "http://{}:{}/api/count/stats",
+            test_server.address, test_server.port
+        ))
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats: CountStats = response.json().await.unwrap();
+    let expected = Movement {
+        increments: 5,
+        decrements: 1,
+    };
+    assert_eq!(stats.per_minute, expected);
+    assert_eq!(stats.per_hour, expected);
+}
+
+async fn next_count<S>(socket: &mut S) -> CountResponse
+where
+    S: Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+{
+    loop {
+        match socket.next().await.unwrap().unwrap() {
+            tungstenite::Message::Text(msg) => return serde_json::from_str(&msg).unwrap(),
+            tungstenite::Message::Ping(_) => continue,
+            _other => panic!("unexpected message"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn websocket_sends_stats_when_asked() {
+    let test_server = TestServer::spawn_server();
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!(
+        "ws://{}:{}/ws/count?stats=true",
+        test_server.address, test_server.port
+    ))
+    .await
+    .unwrap();
+
+    let initial = next_count(&mut socket).await;
+    assert_eq!(initial.stats, Some(CountStats::default()));
+
+    test_server.post_update(Direction::Increment).await;
+    let update = next_count(&mut socket).await;
+    assert_eq!(update.count, 1);
+    assert_eq!(update.stats.unwrap().per_minute.increments, 1);
+}
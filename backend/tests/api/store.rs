@@ -0,0 +1,29 @@
+use crate::test_server::TestServer;
+use backend::{startup::Settings, store::StoreConfig};
+use client::Direction;
+use std::time::Duration;
+
+async fn counters_survive_a_restart(config: StoreConfig) {
+    let test_server =
+        TestServer::spawn_server_with(Settings::new("").store(config.open().unwrap()));
+    let increment = Direction::Increment;
+    test_server.post_update(increment).await;
+    test_server.post_update(increment).await;
+    // give the background save time to run
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let restarted = TestServer::spawn_server_with(Settings::new("").store(config.open().unwrap()));
+    restarted.assert_count_value(2).await;
+}
+
+#[tokio::test]
+async fn counters_survive_a_restart_with_a_file_store() {
+    let dir = tempfile::tempdir().unwrap();
+    counters_survive_a_restart(StoreConfig::File(dir.path().join("counters.wal"))).await;
+}
+
+#[tokio::test]
+async fn counters_survive_a_restart_with_a_sqlite_store() {
+    let dir = tempfile::tempdir().unwrap();
+    counters_survive_a_restart(StoreConfig::Sqlite(dir.path().join("counters.db"))).await;
+}
@@ -1,37 +1,66 @@
-use backend::startup::run;
+use backend::{
+    limiter::RateLimitConfig,
+    startup::{run, Settings},
+};
 
 use std::net::{Ipv4Addr, SocketAddr, TcpListener};
-use client::CountRequest;
+use client::Direction;
+use tokio::{sync::oneshot, task::JoinHandle};
 
 pub struct TestServer {
     pub address: String,
     pub port: u16,
     pub client: reqwest::Client,
+    stop: oneshot::Sender<()>,
+    server: JoinHandle<()>,
 }
 
 impl TestServer {
     pub fn spawn_server() -> TestServer {
+        TestServer::spawn_server_with_limits(RateLimitConfig::default())
+    }
+
+    pub fn spawn_server_with_limits(rate_limits: RateLimitConfig) -> TestServer {
+        TestServer::spawn_server_with(Settings::new("").rate_limits(rate_limits))
+    }
+
+    pub fn spawn_server_with(settings: Settings) -> TestServer {
         // bind to an OS-assigned port on localhost
         let sock_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
         let listener = TcpListener::bind(sock_addr).expect("failed to bind to socket");
         // retrieve the port for this socket
         let port = listener.local_addr().unwrap().port();
 
-        // start up the server in a new green thread
-        tokio::spawn(async move {
-            run(listener, "".into()).await;
+        // start up the server in a new green thread, until told to stop
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            run(listener, settings, async {
+                // keep serving when the TestServer is dropped without a shutdown
+                if stopped.await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            })
+            .await;
         });
 
         TestServer {
             address: Ipv4Addr::LOCALHOST.to_string(),
             port,
             client: reqwest::Client::new(),
+            stop,
+            server,
         }
     }
+
+    /// Shut the server down, returning once it has finished.
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(());
+        self.server.await.expect("server panicked");
+    }
     
     pub async fn assert_count_value(&self, expected: i32) {
         let response = self.client
-        .get(&format!(
+        .get(format!(
             "http://{}:{}/api/count",
             self.address, self.port,
         ))
@@ -43,11 +72,11 @@ impl TestServer {
     assert_eq!(format!(r#"{{"count":{}}}"#, expected), response.text().await.expect("GET failed"));
     }
     
-    pub async fn post_update(&self, message: &CountRequest) {
+    pub async fn post_update(&self, direction: Direction) {
         let response = self.client
-        .post(&format!(
+        .post(format!(
             "http://{}:{}/api/count/{}",
-            self.address, self.port, message.to_string()
+            self.address, self.port, direction
         ))
         .send()
         .await
@@ -0,0 +1,40 @@
+use crate::pool::{Backend, Pool};
+use hyper::{client::HttpConnector, Client, Uri};
+use std::sync::Arc;
+use std::time::Duration;
+
+const HEALTH_CHECK_PATH: &str = "/health_check";
+
+/// Poll every backend's health check on `interval`, taking backends that
+/// fail or time out out of rotation until they recover.
+pub async fn monitor(pool: Arc<Pool>, interval: Duration) {
+    let client = Client::new();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let checks = pool
+            .backends()
+            .iter()
+            .map(|backend| check(&client, backend, interval));
+        futures::future::join_all(checks).await;
+    }
+}
+
+async fn check(client: &Client<HttpConnector>, backend: &Backend, timeout: Duration) {
+    let uri: Uri = match format!("http://{}{}", backend.addr, HEALTH_CHECK_PATH).parse() {
+        Ok(uri) => uri,
+        Err(_) => return,
+    };
+    let healthy = matches!(
+        tokio::time::timeout(timeout, client.get(uri)).await,
+        Ok(Ok(response)) if response.status().is_success()
+    );
+
+    if backend.set_healthy(healthy) {
+        if healthy {
+            log::info!("backend {} is healthy again", backend.addr);
+        } else {
+            log::warn!("backend {} failed its health check", backend.addr);
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod health;
+pub mod pool;
+pub mod proxy;
+pub mod startup;
@@ -0,0 +1,79 @@
+use balancer::{
+    pool::Strategy,
+    startup::{run, Settings},
+};
+use clap::Parser;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener};
+use std::str::FromStr;
+use std::time::Duration;
+
+const LOGGING_VARIABLE: &str = "RUST_LOG";
+
+// Setup the command line interface with clap.
+#[derive(Parser, Debug)]
+#[clap(
+    name = "balancer",
+    about = "A load balancer for our backend instances!"
+)]
+struct Opt {
+    /// set the log level
+    #[clap(short = 'l', long = "log-level", default_value = "debug")]
+    log_level: String,
+
+    /// set the listen addr
+    #[clap(short = 'a', long = "addr", default_value = "::1")]
+    addr: String,
+
+    /// set the listen port
+    #[clap(short = 'p', long = "port", default_value = "8080")]
+    port: u16,
+
+    /// add a backend to balance across, e.g. [::1]:8081
+    #[clap(short = 'b', long = "backend", required = true)]
+    backends: Vec<SocketAddr>,
+
+    /// set the balancing strategy: round-robin, least-connections or consistent-hash
+    #[clap(short = 's', long = "strategy", default_value = "round-robin", value_parser = parse_strategy)]
+    strategy: Strategy,
+
+    /// set the seconds between backend health checks
+    #[clap(long = "health-interval", default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    health_interval: u64,
+}
+
+fn parse_strategy(s: &str) -> Result<Strategy, String> {
+    Strategy::from_str(s).map_err(|_| format!("unknown strategy {:?}", s))
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::parse();
+
+    // set up logging
+    match std::env::var(LOGGING_VARIABLE) {
+        Ok(_) => (),
+        Err(_) => std::env::set_var(
+            LOGGING_VARIABLE,
+            format!("{},hyper=info,mio=info", opt.log_level),
+        ),
+    }
+    // log to the console
+    tracing_subscriber::fmt::init();
+
+    let sock_addr = SocketAddr::from((
+        IpAddr::from_str(opt.addr.as_str()).unwrap_or(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        opt.port,
+    ));
+    let listener = TcpListener::bind(sock_addr).expect("failed to bind to socket");
+
+    log::info!("balancing {:?} on http://{}", opt.backends, sock_addr);
+    run(
+        listener,
+        Settings {
+            backends: opt.backends,
+            strategy: opt.strategy,
+            health_check_interval: Duration::from_secs(opt.health_interval),
+        },
+    )
+    .await
+}
@@ -0,0 +1,232 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Points each backend occupies on the consistent-hash ring; more points
+/// spread keys more evenly.
+const VIRTUAL_NODES: usize = 64;
+
+/// How the pool chooses a backend for each request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    RoundRobin,
+    LeastConnections,
+    ConsistentHash,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseStrategyError;
+
+impl FromStr for Strategy {
+    type Err = ParseStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "least-connections" => Ok(Strategy::LeastConnections),
+            "consistent-hash" => Ok(Strategy::ConsistentHash),
+            _ => Err(ParseStrategyError),
+        }
+    }
+}
+
+/// One upstream backend instance.
+#[derive(Debug)]
+pub struct Backend {
+    pub addr: SocketAddr,
+    healthy: AtomicBool,
+    connections: AtomicUsize,
+}
+
+impl Backend {
+    fn new(addr: SocketAddr) -> Backend {
+        Backend {
+            addr,
+            healthy: AtomicBool::new(true),
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    /// Record the result of a health check, returning whether it changed.
+    pub fn set_healthy(&self, healthy: bool) -> bool {
+        self.healthy.swap(healthy, Ordering::AcqRel) != healthy
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Acquire)
+    }
+
+    /// Count a connection against this backend until the guard is dropped.
+    pub fn connect(self: &Arc<Self>) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::AcqRel);
+        ConnectionGuard {
+            backend: self.clone(),
+        }
+    }
+}
+
+pub struct ConnectionGuard {
+    backend: Arc<Backend>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.backend.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The set of backends traffic is spread across.
+pub struct Pool {
+    backends: Vec<Arc<Backend>>,
+    strategy: Strategy,
+    next: AtomicUsize,
+    // (point, backend index), sorted by point
+    ring: Vec<(u64, usize)>,
+}
+
+impl Pool {
+    pub fn new(addrs: &[SocketAddr], strategy: Strategy) -> Pool {
+        let backends: Vec<_> = addrs
+            .iter()
+            .map(|addr| Arc::new(Backend::new(*addr)))
+            .collect();
+
+        let mut ring: Vec<_> = backends
+            .iter()
+            .enumerate()
+            .flat_map(|(index, backend)| {
+                (0..VIRTUAL_NODES).map(move |point| (hash(&(backend.addr, point)), index))
+            })
+            .collect();
+        ring.sort_unstable();
+
+        Pool {
+            backends,
+            strategy,
+            next: AtomicUsize::new(0),
+            ring,
+        }
+    }
+
+    pub fn backends(&self) -> &[Arc<Backend>] {
+        &self.backends
+    }
+
+    /// Choose a healthy backend for a request from `client` using the pool's strategy.
+    pub fn pick(&self, client: &str) -> Option<Arc<Backend>> {
+        match self.strategy {
+            Strategy::RoundRobin => self.round_robin(),
+            Strategy::LeastConnections => self.least_connections(),
+            Strategy::ConsistentHash => self.sticky(client),
+        }
+    }
+
+    /// Choose a healthy backend for `client` that stays the same for as long
+    /// as that backend is healthy, whatever the pool's strategy.
+    pub fn sticky(&self, client: &str) -> Option<Arc<Backend>> {
+        if self.ring.is_empty() {
+            return None;
+        }
+        let point = hash(&client);
+        let start = self.ring.partition_point(|(p, _)| *p < point);
+        (0..self.ring.len())
+            .map(|offset| self.ring[(start + offset) % self.ring.len()].1)
+            .map(|index| &self.backends[index])
+            .find(|backend| backend.is_healthy())
+            .cloned()
+    }
+
+    fn round_robin(&self) -> Option<Arc<Backend>> {
+        let count = self.backends.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..count)
+            .map(|offset| &self.backends[(start + offset) % count])
+            .find(|backend| backend.is_healthy())
+            .cloned()
+    }
+
+    fn least_connections(&self) -> Option<Arc<Backend>> {
+        self.backends
+            .iter()
+            .filter(|backend| backend.is_healthy())
+            .min_by_key(|backend| backend.connections())
+            .cloned()
+    }
+}
+
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(count: u16) -> Vec<SocketAddr> {
+        (0..count)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], 9000 + port)))
+            .collect()
+    }
+
+    #[test]
+    fn parse_strategy() {
+        assert_eq!(Strategy::from_str("round-robin"), Ok(Strategy::RoundRobin));
+        assert_eq!(Strategy::from_str("random"), Err(ParseStrategyError));
+    }
+
+    #[test]
+    fn round_robin_cycles_through_healthy_backends() {
+        let pool = Pool::new(&addrs(3), Strategy::RoundRobin);
+        pool.backends()[1].set_healthy(false);
+        let picked: Vec<_> = (0..4)
+            .map(|_| pool.pick("client").unwrap().addr.port())
+            .collect();
+        assert_eq!(picked, vec![9000, 9002, 9002, 9000]);
+    }
+
+    #[test]
+    fn least_connections_prefers_idle_backends() {
+        let pool = Pool::new(&addrs(2), Strategy::LeastConnections);
+        let first = pool.pick("client").unwrap();
+        let _guard = first.connect();
+        let second = pool.pick("client").unwrap();
+        assert_ne!(first.addr, second.addr);
+    }
+
+    #[test]
+    fn consistent_hash_is_sticky_until_backend_fails() {
+        let pool = Pool::new(&addrs(4), Strategy::ConsistentHash);
+        let first = pool.pick("192.0.2.1").unwrap();
+        for _ in 0..10 {
+            assert_eq!(pool.pick("192.0.2.1").unwrap().addr, first.addr);
+        }
+
+        first.set_healthy(false);
+        let failover = pool.pick("192.0.2.1").unwrap();
+        assert_ne!(failover.addr, first.addr);
+
+        first.set_healthy(true);
+        assert_eq!(pool.pick("192.0.2.1").unwrap().addr, first.addr);
+    }
+
+    #[test]
+    fn no_healthy_backends_picks_nothing() {
+        let pool = Pool::new(&addrs(2), Strategy::RoundRobin);
+        for backend in pool.backends() {
+            backend.set_healthy(false);
+        }
+        assert!(pool.pick("client").is_none());
+        assert!(pool.sticky("client").is_none());
+    }
+}
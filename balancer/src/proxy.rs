@@ -0,0 +1,180 @@
+use crate::pool::Pool;
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use hyper::{client::HttpConnector, Body, Client};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Headers that only describe the connection they arrived on, and so must
+/// not be forwarded (RFC 9110, section 7.6.1).
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Forward a request to a healthy backend chosen by the pool.
+///
+/// Protocol upgrades (i.e. `/ws/count`) always go to the backend the
+/// client is pinned to by consistent hashing, and once the backend agrees
+/// to switch protocols the two connections are spliced together.
+pub async fn proxy(
+    Extension(pool): Extension<Arc<Pool>>,
+    Extension(client): Extension<Client<HttpConnector>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request<Body>,
+) -> Response {
+    let client_key = peer.ip().to_string();
+    let upgrade = is_upgrade(request.headers());
+    let backend = if upgrade {
+        pool.sticky(&client_key)
+    } else {
+        pool.pick(&client_key)
+    };
+    let backend = match backend {
+        Some(backend) => backend,
+        None => return (StatusCode::SERVICE_UNAVAILABLE, "No Healthy Backends").into_response(),
+    };
+    let connection = backend.connect();
+
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    *request.uri_mut() = match format!("http://{}{}", backend.addr, path).parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Bad Request").into_response(),
+    };
+    append_forwarded_for(request.headers_mut(), peer.ip());
+
+    if !upgrade {
+        strip_hop_by_hop(request.headers_mut());
+        return match client.request(request).await {
+            Ok(mut response) => {
+                strip_hop_by_hop(response.headers_mut());
+                response.into_response()
+            }
+            Err(err) => {
+                log::warn!("backend {} failed: {}", backend.addr, err);
+                (StatusCode::BAD_GATEWAY, "Bad Gateway").into_response()
+            }
+        };
+    }
+
+    let downstream = hyper::upgrade::on(&mut request);
+    let mut response = match client.request(request).await {
+        Ok(response) => response,
+        Err(err) => {
+            log::warn!("backend {} failed to upgrade: {}", backend.addr, err);
+            return (StatusCode::BAD_GATEWAY, "Bad Gateway").into_response();
+        }
+    };
+
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let upstream = hyper::upgrade::on(&mut response);
+        tokio::spawn(async move {
+            // the connection counts against the backend for as long as it is open
+            let _connection = connection;
+            match tokio::try_join!(downstream, upstream) {
+                Ok((mut downstream, mut upstream)) => {
+                    if let Err(err) =
+                        tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await
+                    {
+                        log::debug!("upgraded connection closed: {}", err);
+                    }
+                }
+                Err(err) => log::error!("failed to upgrade connection: {}", err),
+            }
+        });
+    }
+    response.into_response()
+}
+
+fn is_upgrade(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    connection_upgrade && headers.contains_key(header::UPGRADE)
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    // headers named by Connection are hop-by-hop too
+    let named: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    for name in HOP_BY_HOP
+        .iter()
+        .copied()
+        .chain(named.iter().map(String::as_str))
+    {
+        headers.remove(name);
+    }
+}
+
+/// Record the client's address so backends can key rate limits on it.
+fn append_forwarded_for(headers: &mut HeaderMap, client: IpAddr) {
+    let forwarded_for = match headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+        Some(existing) => format!("{}, {}", existing, client),
+        None => client.to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+        headers.insert("x-forwarded-for", value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn websocket_handshake_is_an_upgrade() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONNECTION,
+            HeaderValue::from_static("keep-alive, Upgrade"),
+        );
+        assert!(!is_upgrade(&headers));
+        headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        assert!(is_upgrade(&headers));
+    }
+
+    #[test]
+    fn hop_by_hop_headers_are_stripped() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("x-private"));
+        headers.insert("x-private", HeaderValue::from_static("secret"));
+        headers.insert(
+            header::TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        );
+        headers.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::ACCEPT));
+    }
+
+    #[test]
+    fn forwarded_for_is_appended() {
+        let mut headers = HeaderMap::new();
+        append_forwarded_for(&mut headers, [192, 0, 2, 1].into());
+        append_forwarded_for(&mut headers, [10, 0, 0, 1].into());
+        assert_eq!(headers["x-forwarded-for"], "192.0.2.1, 10.0.0.1");
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{
+    health,
+    pool::{Pool, Strategy},
+    proxy::proxy,
+};
+use axum::{Extension, Router};
+use hyper::Client;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::trace::TraceLayer;
+
+/// Everything `run` needs beyond the listener.
+pub struct Settings {
+    pub backends: Vec<SocketAddr>,
+    pub strategy: Strategy,
+    pub health_check_interval: Duration,
+}
+
+pub async fn run(listener: TcpListener, settings: Settings) {
+    let pool = Arc::new(Pool::new(&settings.backends, settings.strategy));
+    tokio::spawn(health::monitor(
+        pool.clone(),
+        settings.health_check_interval,
+    ));
+
+    let app = Router::new()
+        .fallback(proxy)
+        .layer(TraceLayer::new_for_http())
+        .layer(Extension(Client::new()))
+        .layer(Extension(pool));
+
+    axum::Server::from_tcp(listener)
+        .expect("failed to bind to socket address")
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .expect("Unable to start balancer");
+}
@@ -0,0 +1,2 @@
+mod proxy;
+mod test_balancer;
@@ -0,0 +1,81 @@
+use crate::test_balancer::TestBalancer;
+use balancer::pool::Strategy;
+use futures::StreamExt;
+use reqwest::StatusCode;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite;
+
+#[tokio::test]
+async fn round_robin_spreads_requests_across_backends() {
+    let balancer = TestBalancer::spawn(2, 0, Strategy::RoundRobin);
+
+    for _ in 0..4 {
+        let response = balancer
+            .client
+            .post(balancer.url("/api/count/incr"))
+            .send()
+            .await
+            .expect("POST failed");
+        assert!(response.status().is_success());
+    }
+
+    assert_eq!(balancer.backend_count(0).await, r#"{"count":2}"#);
+    assert_eq!(balancer.backend_count(1).await, r#"{"count":2}"#);
+}
+
+#[tokio::test]
+async fn failed_backends_are_taken_out_of_rotation() {
+    let balancer = TestBalancer::spawn(1, 1, Strategy::RoundRobin);
+    // give the health checks a chance to run
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    for _ in 0..4 {
+        let response = balancer
+            .client
+            .post(balancer.url("/api/count/incr"))
+            .send()
+            .await
+            .expect("POST failed");
+        assert!(response.status().is_success());
+    }
+
+    assert_eq!(balancer.backend_count(0).await, r#"{"count":4}"#);
+}
+
+#[tokio::test]
+async fn no_healthy_backends_is_unavailable() {
+    let balancer = TestBalancer::spawn(0, 2, Strategy::LeastConnections);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let response = balancer
+        .client
+        .get(balancer.url("/api/count"))
+        .send()
+        .await
+        .expect("GET failed");
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn websockets_are_proxied_to_a_sticky_backend() {
+    let balancer = TestBalancer::spawn(3, 0, Strategy::RoundRobin);
+
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!(
+        "ws://{}:{}/ws/count",
+        balancer.address, balancer.port
+    ))
+    .await
+    .unwrap();
+
+    let msg = match socket.next().await.unwrap().unwrap() {
+        tungstenite::Message::Ping(v) => v,
+        _other => panic!("unexpected message"),
+    };
+    assert_eq!(msg, vec![1, 2, 3]);
+
+    let msg = match socket.next().await.unwrap().unwrap() {
+        tungstenite::Message::Text(msg) => msg,
+        _other => panic!("unexpected message"),
+    };
+    assert_eq!(msg, r#"{"count":0}"#);
+}
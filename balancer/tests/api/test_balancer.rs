@@ -0,0 +1,77 @@
+use backend::startup::Settings as BackendSettings;
+use balancer::{
+    pool::Strategy,
+    startup::{run, Settings},
+};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::time::Duration;
+
+pub struct TestBalancer {
+    pub address: String,
+    pub port: u16,
+    pub backends: Vec<SocketAddr>,
+    pub client: reqwest::Client,
+}
+
+impl TestBalancer {
+    /// Start `live` backends, plus one address nothing listens on for each
+    /// of `dead`, and balance across all of them.
+    pub fn spawn(live: usize, dead: usize, strategy: Strategy) -> TestBalancer {
+        let mut backends: Vec<SocketAddr> = (0..live).map(|_| spawn_backend()).collect();
+        backends.extend((0..dead).map(|_| unused_addr()));
+
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .expect("failed to bind to socket");
+        let port = listener.local_addr().unwrap().port();
+
+        let settings = Settings {
+            backends: backends.clone(),
+            strategy,
+            health_check_interval: Duration::from_millis(100),
+        };
+        tokio::spawn(async move {
+            run(listener, settings).await;
+        });
+
+        TestBalancer {
+            address: Ipv4Addr::LOCALHOST.to_string(),
+            port,
+            backends,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}:{}{}", self.address, self.port, path)
+    }
+
+    /// Read a backend's count directly, bypassing the balancer.
+    pub async fn backend_count(&self, index: usize) -> String {
+        self.client
+            .get(format!("http://{}/api/count", self.backends[index]))
+            .send()
+            .await
+            .expect("GET failed")
+            .text()
+            .await
+            .expect("GET failed")
+    }
+}
+
+fn spawn_backend() -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .expect("failed to bind to socket");
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        backend::startup::run(listener, BackendSettings::new(""), std::future::pending()).await;
+    });
+    addr
+}
+
+fn unused_addr() -> SocketAddr {
+    // the port is released as soon as the listener is dropped
+    TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .expect("failed to bind to socket")
+        .local_addr()
+        .unwrap()
+}
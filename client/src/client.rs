@@ -1,45 +1,641 @@
+use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub struct CountRequest {
-    pub direction: Direction,
+/// A change to a counter, sent as the JSON body of `POST /api/count` or
+/// `POST /api/counters/:name`, e.g. `{"op":"Add","amount":5}`.
+///
+/// The older `{"direction":"Increment"}` body is still accepted, but never sent.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(tag = "op", remote = "Self")]
+pub enum CountRequest {
+    Increment,
+    Decrement,
+    Add {
+        amount: u32,
+    },
+    Subtract {
+        amount: u32,
+    },
+    Set {
+        value: i32,
+    },
+    Reset,
+    /// Set the counter to `new`, but only if it currently holds `expected`.
+    CompareAndSwap {
+        expected: i32,
+        new: i32,
+    },
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct ParseCountRequestError;
+impl From<Direction> for CountRequest {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::Increment => CountRequest::Increment,
+            Direction::Decrement => CountRequest::Decrement,
+        }
+    }
+}
+
+impl Serialize for CountRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CountRequest::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CountRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Body {
+            Op(#[serde(deserialize_with = "CountRequest::deserialize")] CountRequest),
+            Direction { direction: Direction },
+        }
+
+        Ok(match Body::deserialize(deserializer)? {
+            Body::Op(request) => request,
+            Body::Direction { direction } => direction.into(),
+        })
+    }
+}
 
 impl FromStr for CountRequest {
-    type Err = ParseCountRequestError;
+    type Err = ParseDirectionError;
+
+    /// Parse `incr` or `decr`, as in `POST /api/count/:direction`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Direction>().map(CountRequest::from)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CountResponse {
+    pub count: i32,
+    /// Sent over the WebSocket to clients which connect with `?stats=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<CountStats>,
+}
+
+/// How far the counter has moved up and down over a trailing window.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Movement {
+    pub increments: u64,
+    pub decrements: u64,
+}
+
+/// How fast the counter is moving, as returned by `GET /api/count/stats`.
+/// Each window ends with the current second.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CountStats {
+    pub per_second: Movement,
+    pub per_minute: Movement,
+    pub per_hour: Movement,
+}
+
+/// A single step, which can also be sent as the path segment of
+/// `POST /api/count/:direction`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    Increment,
+    Decrement,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseDirectionError;
+
+impl FromStr for Direction {
+    type Err = ParseDirectionError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "incr" => Ok(CountRequest { direction: Direction::Increment }),
-            "decr" => Ok(CountRequest { direction: Direction::Decrement }),
-            _ => Err(ParseCountRequestError),
+            "incr" => Ok(Direction::Increment),
+            "decr" => Ok(Direction::Decrement),
+            _ => Err(ParseDirectionError),
         }
     }
 }
 
-impl ToString for CountRequest {
-    fn to_string(&self) -> String {
-        match self.direction {
-            Direction::Increment => "incr".to_owned(),
-            Direction::Decrement => "decr".to_owned(),
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Increment => write!(f, "incr"),
+            Direction::Decrement => write!(f, "decr"),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct CountResponse {
+/// What happens to a move (an increment, decrement, add or subtract) that
+/// would take a counter past one of its bounds. Values set outright must be
+/// within them.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OverflowPolicy {
+    /// Refuse the change.
+    #[default]
+    Reject,
+    /// Stop at the bound.
+    Saturate,
+    /// Carry on from the opposite bound.
+    Wrap,
+}
+
+/// One named counter, as returned by the `/api/counters` routes.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CounterResponse {
+    pub name: String,
     pub count: i32,
+    pub min: i32,
+    pub max: i32,
+    pub overflow: OverflowPolicy,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub enum Direction {
+pub struct CountersResponse {
+    pub counters: Vec<CounterResponse>,
+}
+
+/// One change made to a counter by a backend, as listed by `GET /api/count/history`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CountEvent {
+    /// Increases by one with every change, so it can be used as a cursor.
+    pub id: u64,
+    /// When the change was made, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub delta: i64,
+    /// The count the change left behind.
+    pub count: i32,
+    /// The key the client was rate-limited under, if any, or for API keys a
+    /// digest of it such as `key:3a7bd3e2360a`.
+    pub client: Option<String>,
+    /// The `X-Request-Id` of the request which made the change.
+    pub request_id: Option<String>,
+}
+
+/// The query string of `GET /api/count/history`. Every field is optional;
+/// times are in milliseconds since the Unix epoch.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct HistoryQuery {
+    /// Only events at or after this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    /// Only events before this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    /// Only events with a greater id, i.e. the page after `next`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<u64>,
+    /// The most events to return.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// A page of events, oldest first.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct HistoryResponse {
+    pub events: Vec<CountEvent>,
+    /// Pass as `after` to fetch the next page; missing on the last page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<u64>,
+}
+
+/// The body of `PUT /api/counters/:name`, which creates or replaces a counter.
+/// Missing bounds default to the full range of an `i32`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct PutCounterRequest {
+    #[serde(default)]
+    pub count: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<i32>,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+/// A command sent over `/ws/count`, e.g. `{"id":1,"command":"Increment"}`.
+/// The reply carries the same `id`, so clients can match them up.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketCommand {
+    pub id: u64,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "command")]
+pub enum Command {
     Increment,
     Decrement,
+    Get,
+    /// Resume pushing the count whenever it changes, starting with the current one.
+    Subscribe,
+    /// Stop pushing the count; sockets are subscribed when they connect.
+    Unsubscribe,
+    Ping,
+}
+
+/// The reply to a `SocketCommand`, e.g. `{"id":1,"reply":"Count","count":5}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SocketReply {
+    /// The id of the command, missing if it could not be read at all.
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub reply: Reply,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "reply")]
+pub enum Reply {
+    Count {
+        count: i32,
+    },
+    Subscribed,
+    Unsubscribed,
+    Pong,
+    Error {
+        error: ErrorKind,
+        message: String,
+    },
+    /// The command would change the count faster than the client's rate limit
+    /// allows; it may be sent again after `retry_after` seconds.
+    RateLimited {
+        retry_after: u64,
+    },
+}
+
+/// Anything the server sends over `/ws/count`: replies to commands, and the
+/// count itself whenever it changes.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SocketMessage {
+    Reply(SocketReply),
+    Count(CountResponse),
+}
+
+/// The JSON body sent alongside an error status code.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub error: ErrorKind,
+    pub message: String,
+    /// Seconds to wait before retrying, for errors that clear up on their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorKind {
+    RateLimited,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    InvalidRequest,
+    Conflict,
+    OutOfBounds,
+    PreconditionFailed,
+    Internal,
+}
+
+/// What made the server reload its configuration.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadTrigger {
+    /// The configuration file changed.
+    File,
+    /// The server was sent SIGHUP.
+    Signal,
+}
+
+/// The outcome of one configuration reload.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigReload {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub trigger: ReloadTrigger,
+    /// Keys whose new values took effect, e.g. `rate_limits.routes./api/count`.
+    #[serde(default)]
+    pub applied: Vec<String>,
+    /// Keys which changed but only take effect after a restart.
+    #[serde(default)]
+    pub ignored: Vec<String>,
+    /// Why the new configuration was refused, leaving the old one in place.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Returned by `GET /admin/config`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigStatus {
+    /// The file the configuration is reloaded from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// How many reloads have been applied since startup.
+    pub generation: u64,
+    /// The most recent reloads, oldest first, whether they succeeded or not.
+    pub reloads: Vec<ConfigReload>,
+}
+
+/// The nominal budget of a policy: `limit` requests per `window`, as
+/// advertised in the `RateLimit-Policy` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    pub limit: u32,
+    pub window: Duration,
+}
+
+/// The most requests any policy may admit at once. The sliding window log
+/// remembers every one of them, for every key.
+const MAX_LIMIT: u32 = 1_000_000;
+/// The longest window, period or refill time of any policy, short enough
+/// that limiters can always add it to an `Instant`.
+const MAX_WINDOW: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Which algorithm limits a route, and its parameters, tagged by `algorithm`
+/// both in the backend's configuration and in `/admin/policies`, e.g.
+/// `{ "algorithm": "fixed_window", "limit": 10, "window_seconds": 1.0 }`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum Policy {
+    TokenBucket {
+        capacity: u32,
+        refill_per_second: f64,
+    },
+    FixedWindow {
+        limit: u32,
+        window_seconds: f64,
+    },
+    SlidingWindowLog {
+        limit: u32,
+        window_seconds: f64,
+    },
+    SlidingWindowCounter {
+        limit: u32,
+        window_seconds: f64,
+    },
+    LeakyBucket {
+        capacity: u32,
+        leak_per_second: f64,
+    },
+    Gcra {
+        limit: u32,
+        period_seconds: f64,
+    },
+}
+
+impl Policy {
+    pub fn token_bucket(capacity: u32, refill_per_second: f64) -> Policy {
+        Policy::TokenBucket {
+            capacity,
+            refill_per_second,
+        }
+    }
+
+    pub fn fixed_window(limit: u32, window: Duration) -> Policy {
+        Policy::FixedWindow {
+            limit,
+            window_seconds: window.as_secs_f64(),
+        }
+    }
+
+    pub fn sliding_window_log(limit: u32, window: Duration) -> Policy {
+        Policy::SlidingWindowLog {
+            limit,
+            window_seconds: window.as_secs_f64(),
+        }
+    }
+
+    pub fn sliding_window_counter(limit: u32, window: Duration) -> Policy {
+        Policy::SlidingWindowCounter {
+            limit,
+            window_seconds: window.as_secs_f64(),
+        }
+    }
+
+    pub fn leaky_bucket(capacity: u32, leak_per_second: f64) -> Policy {
+        Policy::LeakyBucket {
+            capacity,
+            leak_per_second,
+        }
+    }
+
+    pub fn gcra(limit: u32, period: Duration) -> Policy {
+        Policy::Gcra {
+            limit,
+            period_seconds: period.as_secs_f64(),
+        }
+    }
+
+    /// The budget this policy grants. Bucket algorithms advertise their
+    /// capacity over the time it takes to refill (or drain) completely.
+    pub fn quota(&self) -> Quota {
+        let (limit, window_seconds) = self.budget();
+        Quota {
+            limit,
+            // only invalid policies have windows too long for a `Duration`
+            window: Duration::try_from_secs_f64(window_seconds).unwrap_or(Duration::MAX),
+        }
+    }
+
+    /// The limit and window, in seconds, of `quota`.
+    fn budget(&self) -> (u32, f64) {
+        match *self {
+            Policy::TokenBucket {
+                capacity,
+                refill_per_second,
+            } => (capacity, capacity as f64 / refill_per_second),
+            Policy::FixedWindow {
+                limit,
+                window_seconds,
+            }
+            | Policy::SlidingWindowLog {
+                limit,
+                window_seconds,
+            }
+            | Policy::SlidingWindowCounter {
+                limit,
+                window_seconds,
+            } => (limit, window_seconds),
+            Policy::LeakyBucket {
+                capacity,
+                leak_per_second,
+            } => (capacity, capacity as f64 / leak_per_second),
+            Policy::Gcra {
+                limit,
+                period_seconds,
+            } => (limit, period_seconds),
+        }
+    }
+
+    /// Check the parameters describe a limiter that can admit anything, e.g.
+    /// that no window is empty.
+    pub fn validate(&self) -> Result<(), String> {
+        let (count, rate) = match *self {
+            Policy::TokenBucket {
+                capacity,
+                refill_per_second,
+            } => (
+                ("capacity", capacity),
+                ("refill_per_second", refill_per_second),
+            ),
+            Policy::FixedWindow {
+                limit,
+                window_seconds,
+            }
+            | Policy::SlidingWindowLog {
+                limit,
+                window_seconds,
+            }
+            | Policy::SlidingWindowCounter {
+                limit,
+                window_seconds,
+            } => (("limit", limit), ("window_seconds", window_seconds)),
+            Policy::LeakyBucket {
+                capacity,
+                leak_per_second,
+            } => (("capacity", capacity), ("leak_per_second", leak_per_second)),
+            Policy::Gcra {
+                limit,
+                period_seconds,
+            } => (("limit", limit), ("period_seconds", period_seconds)),
+        };
+        if count.1 == 0 {
+            return Err(format!("{} must be at least 1", count.0));
+        }
+        if count.1 > MAX_LIMIT {
+            return Err(format!("{} must be at most {}", count.0, MAX_LIMIT));
+        }
+        if !(rate.1.is_finite() && rate.1 > 0.0) {
+            return Err(format!("{} must be a positive number", rate.0));
+        }
+        if self.budget().1 > MAX_WINDOW.as_secs_f64() {
+            return Err(format!("{} gives a window longer than a year", rate.0));
+        }
+        Ok(())
+    }
+}
+
+/// The policy limiting one route, as sent to and returned by `/admin/policies`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoutePolicy {
+    /// The route pattern, e.g. `/api/count/:direction`.
+    pub route: String,
+    pub policy: Policy,
+}
+
+/// Returned by `GET /admin/policies`, sorted by route.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PoliciesResponse {
+    pub policies: Vec<RoutePolicy>,
+}
+
+/// How much a client key has asked of the limiters on this backend.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub key: String,
+    pub allowed: u64,
+    pub denied: u64,
+}
+
+/// Query parameters for `GET /admin/keys`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct TopKeysQuery {
+    /// Only count requests to this route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    /// At most this many keys, 10 by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// Returned by `GET /admin/keys`, the keys with the most requests first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TopKeysResponse {
+    pub keys: Vec<KeyUsage>,
+}
+
+/// Query parameters naming one route, for admin requests about a key which
+/// otherwise apply to every route.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct RouteQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+}
+
+/// One key's limiter on one route.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RouteState {
+    pub route: String,
+    pub policy: Policy,
+    pub allowed: u64,
+    pub denied: u64,
+    /// Requests the limiter would admit now, as of its last decision.
+    pub remaining: u32,
+    /// Seconds until the whole quota is available again.
+    pub reset_seconds: u64,
+    /// Requests admitted beyond the policy, granted by top-ups.
+    pub credit: u32,
+}
+
+/// Returned by `GET /admin/keys/:key`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyState {
+    pub key: String,
+    pub routes: Vec<RouteState>,
+    /// A ban or exemption in force, and how long it has left.
+    #[serde(rename = "override", default, skip_serializing_if = "Option::is_none")]
+    pub override_: Option<KeyOverride>,
+}
+
+/// Sent to `POST /admin/keys/:key/top_up`, admitting `requests` more than
+/// the policy allows. Unused requests never expire.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct TopUpRequest {
+    /// The route to top up, or every limited route if left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    pub requests: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum OverrideKind {
+    /// Deny every request on limited routes.
+    Ban,
+    /// Admit every request, without consuming any budget.
+    Exempt,
+}
+
+/// A temporary ban or exemption, sent to `PUT /admin/keys/:key/override`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyOverride {
+    pub kind: OverrideKind,
+    /// Seconds until the override lapses.
+    pub seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessList {
+    /// Ranges which are never limited.
+    Allow,
+    /// Ranges which are refused with `403 Forbidden`.
+    Deny,
+}
+
+/// One CIDR range on an access list, sent to `POST /admin/access` and, as
+/// query parameters, to `DELETE /admin/access`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccessRule {
+    pub list: AccessList,
+    /// e.g. `10.0.0.0/8` or `2001:db8::/32`.
+    pub range: String,
+}
+
+/// Returned by `GET /admin/access`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccessListsResponse {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
 }
 
 #[cfg(test)]
@@ -49,28 +645,202 @@ mod tests {
     #[test]
     fn parse_request_errors() {
         let s = "other";
-        let result = CountRequest::from_str(s);
-        let expected = Err(ParseCountRequestError);
+        let result = Direction::from_str(s);
+        let expected = Err(ParseDirectionError);
         assert_eq!(result, expected);
     }
 
     #[test]
     fn parse_request_succeeds() {
         let s = "incr";
-        let result = CountRequest::from_str(s);
-        let expected = Ok(CountRequest { direction: Direction::Increment });
+        let result = Direction::from_str(s);
+        let expected = Ok(Direction::Increment);
         assert_eq!(result, expected);
 
         let s = "decr";
-        let result = CountRequest::from_str(s);
-        let expected = Ok(CountRequest { direction: Direction::Decrement });
+        let result = Direction::from_str(s);
+        let expected = Ok(Direction::Decrement);
         assert_eq!(result, expected);
     }
 
     #[test]
     fn to_string_outputs_expected() {
-        assert_eq!("incr", CountRequest { direction: Direction::Increment }.to_string());
-        assert_eq!("decr", CountRequest { direction: Direction::Decrement }.to_string());
+        assert_eq!("incr", Direction::Increment.to_string());
+        assert_eq!("decr", Direction::Decrement.to_string());
+    }
+
+    #[test]
+    fn count_request_is_tagged_by_operation() {
+        let request = CountRequest::CompareAndSwap {
+            expected: 1,
+            new: 5,
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"op":"CompareAndSwap","expected":1,"new":5}"#);
+        assert_eq!(
+            serde_json::from_str::<CountRequest>(&json).unwrap(),
+            request
+        );
+
+        let json = serde_json::to_string(&CountRequest::Reset).unwrap();
+        assert_eq!(json, r#"{"op":"Reset"}"#);
+    }
+
+    #[test]
+    fn count_request_accepts_the_direction_body() {
+        let request: CountRequest = serde_json::from_str(r#"{"direction":"Decrement"}"#).unwrap();
+        assert_eq!(request, CountRequest::Decrement);
+        assert_eq!("incr".parse(), Ok(CountRequest::Increment));
+        assert!(serde_json::from_str::<CountRequest>(r#"{"direction":"Sideways"}"#).is_err());
+    }
+
+    #[test]
+    fn error_response_omits_missing_retry_after() {
+        let error = ErrorResponse {
+            error: ErrorKind::Unauthorized,
+            message: "Missing API Key".into(),
+            retry_after: None,
+        };
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(
+            json,
+            r#"{"error":"Unauthorized","message":"Missing API Key"}"#
+        );
+        assert_eq!(serde_json::from_str::<ErrorResponse>(&json).unwrap(), error);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn put_counter_request_defaults_missing_fields() {
+        let request: PutCounterRequest = serde_json::from_str(r#"{"max":10}"#).unwrap();
+        let expected = PutCounterRequest {
+            count: 0,
+            min: None,
+            max: Some(10),
+            overflow: OverflowPolicy::Reject,
+        };
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn socket_commands_and_replies_share_an_id() {
+        let command: SocketCommand =
+            serde_json::from_str(r#"{"id":7,"command":"Increment"}"#).unwrap();
+        assert_eq!(
+            command,
+            SocketCommand {
+                id: 7,
+                command: Command::Increment
+            }
+        );
+
+        let reply = SocketReply {
+            id: Some(7),
+            reply: Reply::Count { count: 3 },
+        };
+        let json = serde_json::to_string(&reply).unwrap();
+        assert_eq!(json, r#"{"id":7,"reply":"Count","count":3}"#);
+    }
+
+    #[test]
+    fn socket_messages_tell_replies_from_pushed_counts() {
+        match serde_json::from_str(r#"{"id":1,"reply":"Pong"}"#).unwrap() {
+            SocketMessage::Reply(reply) => assert_eq!(reply.reply, Reply::Pong),
+            other => panic!("expected a reply, got {:?}", other),
+        }
+        match serde_json::from_str(r#"{"count":4}"#).unwrap() {
+            SocketMessage::Count(count) => assert_eq!(count.count, 4),
+            other => panic!("expected a count, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn policies_round_trip_through_json() {
+        let policies = [
+            Policy::token_bucket(5, 0.5),
+            Policy::fixed_window(10, Duration::from_secs(1)),
+            Policy::sliding_window_log(10, Duration::from_secs(2)),
+            Policy::sliding_window_counter(10, Duration::from_secs(3)),
+            Policy::leaky_bucket(4, 2.0),
+            Policy::gcra(10, Duration::from_secs(1)),
+        ];
+        for policy in policies {
+            let json = serde_json::to_string(&policy).unwrap();
+            assert_eq!(serde_json::from_str::<Policy>(&json).unwrap(), policy);
+        }
+    }
+
+    #[test]
+    fn unknown_algorithm_is_rejected() {
+        let json = r#"{ "algorithm": "random_drop", "limit": 5 }"#;
+        assert!(serde_json::from_str::<Policy>(json).is_err());
+    }
+
+    #[test]
+    fn bucket_quotas_span_a_full_refill() {
+        assert_eq!(
+            Policy::token_bucket(10, 2.0).quota(),
+            Quota {
+                limit: 10,
+                window: Duration::from_secs(5)
+            }
+        );
+        assert_eq!(
+            Policy::fixed_window(3, Duration::from_secs(60)).quota(),
+            Quota {
+                limit: 3,
+                window: Duration::from_secs(60)
+            }
+        );
+    }
+
+    #[test]
+    fn policies_which_admit_nothing_are_invalid() {
+        assert_eq!(Policy::gcra(10, Duration::from_secs(1)).validate(), Ok(()));
+        assert_eq!(
+            Policy::token_bucket(0, 1.0).validate(),
+            Err("capacity must be at least 1".into())
+        );
+        assert_eq!(
+            Policy::fixed_window(5, Duration::ZERO).validate(),
+            Err("window_seconds must be a positive number".into())
+        );
+        assert!(Policy::leaky_bucket(5, f64::NAN).validate().is_err());
+    }
+
+    #[test]
+    fn limits_are_capped() {
+        assert_eq!(Policy::token_bucket(MAX_LIMIT, 1.0).validate(), Ok(()));
+        assert_eq!(
+            Policy::sliding_window_log(u32::MAX, Duration::from_secs(1)).validate(),
+            Err("limit must be at most 1000000".into())
+        );
+    }
+
+    #[test]
+    fn policies_whose_window_exceeds_a_year_are_invalid() {
+        let endless = Policy::FixedWindow {
+            limit: 5,
+            window_seconds: 1e30,
+        };
+        assert_eq!(
+            endless.validate(),
+            Err("window_seconds gives a window longer than a year".into())
+        );
+        assert_eq!(endless.quota().window, Duration::MAX);
+
+        // fits in a `Duration`, but not when added to an `Instant`
+        let distant = Policy::FixedWindow {
+            limit: 5,
+            window_seconds: 1e19,
+        };
+        assert!(distant.validate().is_err());
+
+        let stalled = Policy::token_bucket(5, 1e-300);
+        assert_eq!(
+            stalled.validate(),
+            Err("refill_per_second gives a window longer than a year".into())
+        );
+        assert!(Policy::gcra(5, Duration::MAX).validate().is_err());
+        assert_eq!(Policy::gcra(5, MAX_WINDOW).validate(), Ok(()));
+    }
+}
@@ -1,3 +1,12 @@
 mod client;
+mod retry;
 
-pub use crate::client::{CountRequest, CountResponse, Direction};
+pub use crate::client::{
+    AccessList, AccessListsResponse, AccessRule, Command, ConfigReload, ConfigStatus, CountEvent,
+    CountRequest, CountResponse, CountStats, CounterResponse, CountersResponse, Direction,
+    ErrorKind, ErrorResponse, HistoryQuery, HistoryResponse, KeyOverride, KeyState, KeyUsage,
+    Movement, OverflowPolicy, OverrideKind, PoliciesResponse, Policy, PutCounterRequest, Quota,
+    ReloadTrigger, Reply, RoutePolicy, RouteQuery, RouteState, SocketCommand, SocketMessage,
+    SocketReply, TopKeysQuery, TopKeysResponse, TopUpRequest,
+};
+pub use crate::retry::{IdempotencyKey, Retry, IDEMPOTENCY_KEY_HEADER};
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// The header carrying an [`IdempotencyKey`] on a mutation.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Identifies one logical mutation, so that the backend applies it once no
+/// matter how many times it is sent.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    /// A fresh, random key.
+    pub fn generate() -> IdempotencyKey {
+        IdempotencyKey(Uuid::new_v4().to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Retries a mutation with exponential backoff, sending the same
+/// [`IdempotencyKey`] on every attempt so that retries are never counted twice.
+///
+/// It is not tied to any HTTP client or async runtime: `send` performs one
+/// attempt, and `sleep` waits between attempts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retry {
+    attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Retry {
+    /// Give up after `attempts` tries, counting the first.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Wait `base_delay` before the first retry, doubling it for each one after.
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Never wait longer than `max_delay` between attempts.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// How long to wait after failed attempt number `attempt`, counting from zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+
+    /// Call `send` with a newly generated key until it succeeds or the
+    /// attempts run out, returning the last error.
+    pub async fn run<T, E, F, Fut, S, Sleep>(&self, sleep: S, send: F) -> Result<T, E>
+    where
+        F: FnMut(IdempotencyKey) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        S: FnMut(Duration) -> Sleep,
+        Sleep: Future<Output = ()>,
+    {
+        self.run_with_key(IdempotencyKey::generate(), sleep, send)
+            .await
+    }
+
+    /// As [`Retry::run`], with a key chosen by the caller.
+    pub async fn run_with_key<T, E, F, Fut, S, Sleep>(
+        &self,
+        key: IdempotencyKey,
+        mut sleep: S,
+        mut send: F,
+    ) -> Result<T, E>
+    where
+        F: FnMut(IdempotencyKey) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        S: FnMut(Duration) -> Sleep,
+        Sleep: Future<Output = ()>,
+    {
+        let mut attempt = 0;
+        loop {
+            match send(key.clone()).await {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt + 1 >= self.attempts => return Err(err),
+                Err(_) => {
+                    sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::cell::RefCell;
+
+    #[test]
+    fn generated_keys_are_unique() {
+        assert_ne!(IdempotencyKey::generate(), IdempotencyKey::generate());
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_maximum() {
+        let retry = Retry::default()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(350));
+        assert_eq!(retry.delay(0), Duration::from_millis(100));
+        assert_eq!(retry.delay(1), Duration::from_millis(200));
+        assert_eq!(retry.delay(2), Duration::from_millis(350));
+        assert_eq!(retry.delay(40), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn run_sends_the_same_key_on_every_attempt() {
+        let keys = RefCell::new(Vec::new());
+        let slept = RefCell::new(Vec::new());
+        let retry = Retry::default().attempts(3);
+
+        let result: Result<(), &str> = block_on(retry.run(
+            |delay| {
+                slept.borrow_mut().push(delay);
+                async {}
+            },
+            |key| {
+                keys.borrow_mut().push(key);
+                async { Err("unavailable") }
+            },
+        ));
+
+        assert_eq!(result, Err("unavailable"));
+        let keys = keys.into_inner();
+        assert_eq!(keys.len(), 3);
+        assert!(keys.iter().all(|key| *key == keys[0]));
+        assert_eq!(slept.into_inner().len(), 2);
+    }
+
+    #[test]
+    fn run_stops_at_the_first_success() {
+        let mut attempts = 0;
+        let result: Result<u32, ()> = block_on(Retry::default().run(
+            |_| async {},
+            |_| {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 2 {
+                        Err(())
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+        ));
+        assert_eq!(result, Ok(2));
+    }
+}
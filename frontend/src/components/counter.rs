@@ -1,24 +1,28 @@
 use anyhow::Error;
-use client::{CountRequest, CountResponse, Direction};
+use client::{
+    Command, CountRequest, CountResponse, IdempotencyKey, Reply, Retry, SocketCommand,
+    SocketMessage, IDEMPOTENCY_KEY_HEADER,
+};
 use gloo_console::log;
 use gloo_net::http::Request;
+use gloo_timers::future::TimeoutFuture;
 use js_sys::Date;
 use wasm_bindgen_futures::spawn_local;
-use yew::{html, Callback, Component, Context, Html};
+use yew::{html, Component, Context, Html};
 use yew_websocket::{
     macros::Json,
     websocket::{WebSocketService, WebSocketStatus, WebSocketTask},
 };
 
 pub enum WsAction {
-    SendData,
+    SendData(Command),
     Disconnect,
     Lost,
 }
 
 pub enum Msg {
     WsAction(WsAction),
-    WsReady(Result<CountResponse, Error>),
+    WsReady(Result<SocketMessage, Error>),
 }
 
 impl From<WsAction> for Msg {
@@ -30,6 +34,8 @@ impl From<WsAction> for Msg {
 pub struct Counter {
     pub data: Option<CountResponse>,
     pub ws: Option<WebSocketTask>,
+    /// The id of the last command sent over `ws`.
+    pub last_command: u64,
 }
 
 impl Counter {
@@ -65,14 +71,40 @@ impl Component for Counter {
         Self {
             data: None,
             ws: Some(task),
+            last_command: 0,
         }
     }
 
-    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::WsAction(action) => match action {
-                WsAction::SendData => {
+                WsAction::SendData(command) => {
                     log!("sending data");
+                    match &mut self.ws {
+                        Some(ws) => {
+                            self.last_command += 1;
+                            let command = SocketCommand {
+                                id: self.last_command,
+                                command,
+                            };
+                            match serde_json::to_string(&command) {
+                                Ok(json) => ws.send(json),
+                                Err(e) => log!(format!("failed to build command: {}", e)),
+                            }
+                        }
+                        // without a socket, fall back to posting the change
+                        None => spawn_local(async move {
+                            match command {
+                                Command::Increment => {
+                                    post_count_update(&CountRequest::Increment).await
+                                }
+                                Command::Decrement => {
+                                    post_count_update(&CountRequest::Decrement).await
+                                }
+                                _ => (),
+                            }
+                        }),
+                    }
                     false
                 }
                 WsAction::Disconnect => {
@@ -89,11 +121,25 @@ impl Component for Counter {
             Msg::WsReady(response) => {
                 log!("response received");
                 match response {
-                    Ok(wsr) => {
+                    Ok(SocketMessage::Count(wsr)) => {
                         log!(format!("response was {:?}", wsr.count));
                         self.data = Some(wsr);
                         true
                     }
+                    Ok(SocketMessage::Reply(reply)) => match reply.reply {
+                        Reply::Count { count } => {
+                            self.data = Some(CountResponse { count, stats: None });
+                            true
+                        }
+                        Reply::Error { error, message } => {
+                            log!(format!(
+                                "command {:?} failed: {:?} {}",
+                                reply.id, error, message
+                            ));
+                            false
+                        }
+                        _ => false,
+                    },
                     Err(e) => {
                         log!(format!("error was {:?}", e));
                         false
@@ -104,23 +150,12 @@ impl Component for Counter {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let incr = Callback::from(move |_| {
-            spawn_local(async move {
-                post_count_update(&CountRequest {
-                    direction: Direction::Increment,
-                })
-                .await;
-            });
-        });
-
-        let decr = Callback::from(move |_| {
-            spawn_local(async move {
-                post_count_update(&CountRequest {
-                    direction: Direction::Decrement,
-                })
-                .await;
-            });
-        });
+        let incr = ctx
+            .link()
+            .callback(|_| WsAction::SendData(Command::Increment));
+        let decr = ctx
+            .link()
+            .callback(|_| WsAction::SendData(Command::Decrement));
 
         html! {
             <div>
@@ -142,16 +177,35 @@ impl Component for Counter {
 
 async fn post_count_update(count_request: &CountRequest) {
     log!("post called");
-    let j = serde_json::to_string(count_request);
-    match j {
-        Ok(data) => {
-            Request::post(format!("/api/count/{}", count_request.to_string()).as_str())
-                .send()
-                .await
-                .map_err(|err| err.to_string());
-            ()
-        }
+    let result = Retry::default()
+        .run(
+            |delay| TimeoutFuture::new(delay.as_millis() as u32),
+            |key| send_count_update(count_request, key),
+        )
+        .await;
+    if let Err(err) = result {
+        log!(format!("failed to post count update: {}", err));
+    }
+}
 
-        Err(_) => (),
+/// One attempt at posting `count_request`, failing only if it is worth retrying.
+async fn send_count_update(
+    count_request: &CountRequest,
+    key: IdempotencyKey,
+) -> Result<(), String> {
+    let response = Request::post("/api/count")
+        .header(IDEMPOTENCY_KEY_HEADER, key.as_str())
+        .json(count_request)
+        .map_err(|err| err.to_string())?
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    match response.status() {
+        429 | 500..=599 => Err(format!("server responded {}", response.status())),
+        status if !response.ok() => {
+            log!(format!("count update refused with {}", status));
+            Ok(())
+        }
+        _ => Ok(()),
     }
 }